@@ -0,0 +1,263 @@
+//! Schedules messages on block intervals. Tracks each network's chainhead with a
+//! `BlockClock` and reports the message block whenever a network crosses its
+//! examination interval, so radios do not each poll graph node and compute message
+//! blocks themselves.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use serde_derive::{Deserialize, Serialize};
+use tracing::trace;
+
+use crate::networks::{NetworkName, NETWORKS};
+use crate::{BlockClock, BlockPointer, ClockTick};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockSchedulerConfig {
+    /// How often to poll graph node for chainheads
+    pub poll_interval: Duration,
+    /// Networks to schedule, every supported network indexed by graph node when empty
+    pub networks: Vec<NetworkName>,
+}
+
+impl Default for BlockSchedulerConfig {
+    fn default() -> Self {
+        BlockSchedulerConfig {
+            poll_interval: Duration::from_secs(10),
+            networks: vec![],
+        }
+    }
+}
+
+/// Scheduling event of a network
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockSchedulerEvent {
+    /// The network crossed into a new examination interval, messages are due for `block`.
+    /// `missed` counts the intervals skipped since the previous event, such as when
+    /// polls failed or graph node stalled
+    MessageBlock {
+        network: NetworkName,
+        block: BlockPointer,
+        missed: u64,
+    },
+    /// The chainhead moved backwards, such as after a reorg or when failing over to a
+    /// graph node that is further behind
+    ChainheadRegressed {
+        network: NetworkName,
+        previous: u64,
+        chainhead: u64,
+    },
+}
+
+impl BlockSchedulerEvent {
+    pub fn network(&self) -> &NetworkName {
+        match self {
+            BlockSchedulerEvent::MessageBlock { network, .. }
+            | BlockSchedulerEvent::ChainheadRegressed { network, .. } => network,
+        }
+    }
+}
+
+impl fmt::Display for BlockSchedulerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockSchedulerEvent::MessageBlock {
+                network,
+                block,
+                missed: 0,
+            } => write!(f, "Message block {} on {network}", block.number),
+            BlockSchedulerEvent::MessageBlock {
+                network,
+                block,
+                missed,
+            } => write!(
+                f,
+                "Message block {} on {network}, {missed} intervals missed",
+                block.number
+            ),
+            BlockSchedulerEvent::ChainheadRegressed {
+                network,
+                previous,
+                chainhead,
+            } => write!(
+                f,
+                "Chainhead of {network} moved back from {previous} to {chainhead}"
+            ),
+        }
+    }
+}
+
+/// Block clocks of each scheduled network
+#[derive(Clone, Debug, Default)]
+pub struct BlockScheduler {
+    networks: Vec<NetworkName>,
+    clocks: HashMap<NetworkName, BlockClock>,
+}
+
+impl BlockScheduler {
+    /// Schedule `networks`, or every supported network when empty
+    pub fn new(networks: Vec<NetworkName>) -> Self {
+        BlockScheduler {
+            networks,
+            clocks: HashMap::new(),
+        }
+    }
+
+    /// Move the clocks of the scheduled networks to the latest chainheads and return
+    /// the ticks. Networks missing from the network registry are skipped
+    pub fn observe(
+        &mut self,
+        chainheads: &HashMap<NetworkName, BlockPointer>,
+    ) -> Vec<(NetworkName, ClockTick)> {
+        let registry = NETWORKS.read().unwrap();
+        let mut ticks = vec![];
+        for (network, chainhead) in chainheads {
+            if !self.networks.is_empty() && !self.networks.contains(network) {
+                continue;
+            }
+            let Some(interval) = registry.get(network).map(|n| n.interval) else {
+                trace!(network = %network, "Skip scheduling unsupported network");
+                continue;
+            };
+            let tick = self
+                .clocks
+                .entry(network.clone())
+                .or_default()
+                .tick(chainhead.number, interval);
+            if tick != ClockTick::Unchanged {
+                ticks.push((network.clone(), tick));
+            }
+        }
+        ticks
+    }
+
+    /// Set a network's last crossed message block back, so the interval fires again
+    /// on the next observation, such as when its block hash could not be resolved
+    pub fn rewind(&mut self, network: &NetworkName, compare_block: u64) {
+        if let Some(clock) = self.clocks.get_mut(network) {
+            clock.compare_block = compare_block;
+        }
+    }
+
+    pub fn clock(&self, network: &NetworkName) -> Option<&BlockClock> {
+        self.clocks.get(network)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::callbook::CallBook;
+    use crate::data_source::FixtureDataSource;
+
+    fn chainheads(number: u64) -> HashMap<NetworkName, BlockPointer> {
+        HashMap::from([
+            (
+                NetworkName::from("mainnet"),
+                BlockPointer::new(number, format!("0x{number}")),
+            ),
+            (
+                NetworkName::from("not-a-chain"),
+                BlockPointer::new(number, format!("0x{number}")),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_block_clock() {
+        let mut clock = BlockClock::default();
+        assert_eq!(
+            clock.tick(95, 30),
+            ClockTick::Crossed {
+                message_block: 90,
+                missed: 0
+            }
+        );
+        assert_eq!(clock.tick(119, 30), ClockTick::Unchanged);
+        assert_eq!(
+            clock.tick(185, 30),
+            ClockTick::Crossed {
+                message_block: 180,
+                missed: 2
+            }
+        );
+        assert_eq!(
+            clock.tick(170, 30),
+            ClockTick::Regressed {
+                previous: 185,
+                message_block: 150
+            }
+        );
+        // The rolled back interval fires again
+        assert_eq!(
+            clock.tick(181, 30),
+            ClockTick::Crossed {
+                message_block: 180,
+                missed: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_scheduler_observe() {
+        // Mainnet is examined every 30 blocks
+        let mut scheduler = BlockScheduler::new(vec![]);
+        let ticks = scheduler.observe(&chainheads(100));
+        assert_eq!(
+            ticks,
+            vec![(
+                NetworkName::from("mainnet"),
+                ClockTick::Crossed {
+                    message_block: 90,
+                    missed: 0
+                }
+            )]
+        );
+        assert!(scheduler.observe(&chainheads(110)).is_empty());
+
+        scheduler.rewind(&NetworkName::from("mainnet"), 0);
+        assert_eq!(scheduler.observe(&chainheads(110)).len(), 1);
+
+        let mut scheduler = BlockScheduler::new(vec![NetworkName::from("goerli")]);
+        assert!(scheduler.observe(&chainheads(100)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_schedule_message_blocks() {
+        let statuses = serde_json::from_value(serde_json::json!([{
+            "subgraph": "Qm1",
+            "synced": true,
+            "health": "healthy",
+            "node": null,
+            "fatalError": null,
+            "chains": [{
+                "network": "mainnet",
+                "latestBlock": {"number": "95", "hash": "0xlatest"},
+                "chainHeadBlock": {"number": "95", "hash": "0xhead"}
+            }]
+        }]))
+        .unwrap();
+        let callbook =
+            CallBook::new(String::new(), String::new(), None).with_data_source(Arc::new(
+                FixtureDataSource::new()
+                    .with_indexing_statuses(statuses)
+                    .with_block_hash("mainnet", 90, "0xninety"),
+            ));
+
+        let mut events = callbook.schedule_message_blocks(BlockSchedulerConfig {
+            poll_interval: Duration::from_millis(10),
+            networks: vec![],
+        });
+        assert_eq!(
+            events.recv().await,
+            Some(BlockSchedulerEvent::MessageBlock {
+                network: NetworkName::from("mainnet"),
+                block: BlockPointer::new(90, "0xninety".to_string()),
+                missed: 0,
+            })
+        );
+    }
+}
@@ -0,0 +1,414 @@
+//! Reorg-aware validation of the blocks radios attest to. Keeps the hashes of recent
+//! message blocks per network, re-verifies them once they are past a confirmation
+//! depth, and classifies a peer's disagreeing block hash as a reorg, a fork of the
+//! peer, or a fork of the local graph node.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use serde_derive::{Deserialize, Serialize};
+use tracing::{trace, warn};
+
+use crate::callbook::CallBook;
+use crate::graphql::client_rpc::same_block_hash;
+use crate::graphql::QueryError;
+use crate::networks::NetworkName;
+use crate::{BlockPointer, NetworkPointer};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockValidatorConfig {
+    /// Blocks on top of a recorded block after which it is re-verified and considered final
+    pub confirmation_depth: u64,
+    /// Recorded blocks kept per network, the oldest are dropped first
+    pub max_blocks_per_network: usize,
+}
+
+impl Default for BlockValidatorConfig {
+    fn default() -> Self {
+        BlockValidatorConfig {
+            confirmation_depth: 64,
+            max_blocks_per_network: 256,
+        }
+    }
+}
+
+/// Outcome of comparing a peer's block hash with the local one
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockValidation {
+    /// The peer attests to the same block
+    Match,
+    /// No local hash was recorded for the block
+    Unknown,
+    /// The locally recorded block was replaced by `canonical`, messages sent for the
+    /// recorded hash are stale
+    Reorg { recorded: String, canonical: String },
+    /// The local hash is still current and most peers agree with it; the peer is on a
+    /// fork or has not caught up with a reorg
+    RemoteFork { local: String, remote: String },
+    /// The local hash is still current but more peers attest to the remote hash; the
+    /// local graph node is likely on a fork
+    LocalFork { local: String, remote: String },
+}
+
+impl fmt::Display for BlockValidation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockValidation::Match => write!(f, "block hashes match"),
+            BlockValidation::Unknown => write!(f, "no local block hash"),
+            BlockValidation::Reorg {
+                recorded,
+                canonical,
+            } => write!(f, "block {recorded} was reorged out for {canonical}"),
+            BlockValidation::RemoteFork { local, remote } => {
+                write!(f, "peer is on a fork with {remote}, local block is {local}")
+            }
+            BlockValidation::LocalFork { local, remote } => write!(
+                f,
+                "local block {local} is likely on a fork, peers attest to {remote}"
+            ),
+        }
+    }
+}
+
+/// Recorded block that changed hash when re-verified
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReorgedBlock {
+    /// Network and block as recorded
+    pub recorded: NetworkPointer,
+    pub canonical_hash: String,
+}
+
+/// Classify a peer's hash for a block against the recorded and the currently resolved
+/// local hash. `local_support` and `remote_support` count the peers attesting to each
+pub fn classify_block_hash(
+    recorded: &str,
+    current: &str,
+    remote: &str,
+    local_support: usize,
+    remote_support: usize,
+) -> BlockValidation {
+    if same_block_hash(recorded, remote) && same_block_hash(current, remote) {
+        BlockValidation::Match
+    } else if !same_block_hash(recorded, current) {
+        BlockValidation::Reorg {
+            recorded: recorded.to_string(),
+            canonical: current.to_string(),
+        }
+    } else if remote_support > local_support {
+        BlockValidation::LocalFork {
+            local: current.to_string(),
+            remote: remote.to_string(),
+        }
+    } else {
+        BlockValidation::RemoteFork {
+            local: current.to_string(),
+            remote: remote.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct RecordedBlock {
+    hash: String,
+    confirmed: bool,
+    /// Peers attesting to each hash of the block
+    peers: HashMap<String, HashSet<String>>,
+}
+
+impl RecordedBlock {
+    fn support(&self, hash: &str) -> usize {
+        self.peers
+            .iter()
+            .filter(|(h, _)| same_block_hash(h, hash))
+            .map(|(_, peers)| peers.len())
+            .sum()
+    }
+}
+
+/// Recent message blocks per network, with the hashes peers attested to
+#[derive(Clone, Debug, Default)]
+pub struct BlockValidator {
+    config: BlockValidatorConfig,
+    blocks: HashMap<String, BTreeMap<u64, RecordedBlock>>,
+}
+
+impl BlockValidator {
+    pub fn new(config: BlockValidatorConfig) -> Self {
+        BlockValidator {
+            config,
+            blocks: HashMap::new(),
+        }
+    }
+
+    /// Record the block hash a local message attests to, replacing a previous record
+    pub fn record_local(&mut self, pointer: &NetworkPointer) {
+        let blocks = self.blocks.entry(pointer.network.clone()).or_default();
+        let peers = blocks
+            .remove(&pointer.block.number)
+            .map(|b| b.peers)
+            .unwrap_or_default();
+        blocks.insert(
+            pointer.block.number,
+            RecordedBlock {
+                hash: pointer.block.hash.clone(),
+                confirmed: false,
+                peers,
+            },
+        );
+        while blocks.len() > self.config.max_blocks_per_network {
+            blocks.pop_first();
+        }
+    }
+
+    /// Record the block hash a peer's message attests to. Only blocks with a local
+    /// record are tracked
+    pub fn record_remote(&mut self, sender: &str, pointer: &NetworkPointer) {
+        if let Some(block) = self
+            .blocks
+            .get_mut(&pointer.network)
+            .and_then(|blocks| blocks.get_mut(&pointer.block.number))
+        {
+            for peers in block.peers.values_mut() {
+                peers.remove(sender);
+            }
+            block
+                .peers
+                .entry(pointer.block.hash.clone())
+                .or_default()
+                .insert(sender.to_string());
+        }
+    }
+
+    /// Locally recorded hash of a block
+    pub fn local_hash(&self, network: &str, block_number: u64) -> Option<&str> {
+        self.blocks
+            .get(network)
+            .and_then(|blocks| blocks.get(&block_number))
+            .map(|b| b.hash.as_str())
+    }
+
+    /// Record a peer's block and compare it with the local one. Disagreements are
+    /// resolved again through `CallBook::block_hash` to tell reorgs from forks. A reorg
+    /// updates the recorded hash to the canonical one, so it is only reported once
+    pub async fn validate_remote(
+        &mut self,
+        callbook: &CallBook,
+        sender: &str,
+        pointer: &NetworkPointer,
+    ) -> Result<BlockValidation, QueryError> {
+        self.record_remote(sender, pointer);
+        let Some(block) = self
+            .blocks
+            .get(&pointer.network)
+            .and_then(|blocks| blocks.get(&pointer.block.number))
+        else {
+            return Ok(BlockValidation::Unknown);
+        };
+        if same_block_hash(&block.hash, &pointer.block.hash) {
+            return Ok(BlockValidation::Match);
+        }
+        let recorded = block.hash.clone();
+        let current = callbook
+            .block_hash(&pointer.network, pointer.block.number)
+            .await?;
+        let Some(block) = self
+            .blocks
+            .get_mut(&pointer.network)
+            .and_then(|blocks| blocks.get_mut(&pointer.block.number))
+        else {
+            return Ok(BlockValidation::Unknown);
+        };
+        // The local graph node counts as one attestation of the local hash
+        let validation = classify_block_hash(
+            &recorded,
+            &current,
+            &pointer.block.hash,
+            block.support(&current) + 1,
+            block.support(&pointer.block.hash),
+        );
+        if let BlockValidation::Reorg { canonical, .. } = &validation {
+            block.hash = canonical.clone();
+        }
+        trace!(
+            network = %pointer.network,
+            block_number = pointer.block.number,
+            sender,
+            validation = tracing::field::debug(&validation),
+            "Validated peer block hash"
+        );
+        Ok(validation)
+    }
+
+    /// Re-resolve recorded blocks that are `confirmation_depth` behind the chainheads and
+    /// return those whose hash changed. Re-verified blocks are marked confirmed and
+    /// updated to the canonical hash; blocks that fail to resolve are retried next time
+    pub async fn verify_confirmations(
+        &mut self,
+        callbook: &CallBook,
+        chainheads: &HashMap<NetworkName, BlockPointer>,
+    ) -> Vec<ReorgedBlock> {
+        let mut reorged = vec![];
+        for (network, blocks) in self.blocks.iter_mut() {
+            let Some(chainhead) = chainheads.get(&NetworkName::from_string(network)) else {
+                continue;
+            };
+            let depth = self.config.confirmation_depth;
+            for (number, block) in blocks
+                .iter_mut()
+                .filter(|(number, block)| !block.confirmed && **number + depth <= chainhead.number)
+            {
+                let canonical = match callbook.block_hash(network, *number).await {
+                    Ok(hash) => hash,
+                    Err(e) => {
+                        warn!(
+                            err = tracing::field::debug(&e),
+                            network,
+                            block_number = number,
+                            "Could not re-verify block hash"
+                        );
+                        continue;
+                    }
+                };
+                if !same_block_hash(&block.hash, &canonical) {
+                    warn!(
+                        network,
+                        block_number = number,
+                        recorded = block.hash,
+                        canonical,
+                        "Recorded block was reorged out"
+                    );
+                    reorged.push(ReorgedBlock {
+                        recorded: NetworkPointer {
+                            network: network.clone(),
+                            block: BlockPointer::new(*number, block.hash.clone()),
+                        },
+                        canonical_hash: canonical.clone(),
+                    });
+                    block.hash = canonical;
+                }
+                block.confirmed = true;
+            }
+        }
+        reorged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::data_source::FixtureDataSource;
+
+    fn pointer(number: u64, hash: &str) -> NetworkPointer {
+        NetworkPointer {
+            network: "mainnet".to_string(),
+            block: BlockPointer::new(number, hash.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_classify_block_hash() {
+        assert_eq!(
+            classify_block_hash("0xaa", "aa", "0xAA", 1, 1),
+            BlockValidation::Match
+        );
+        assert_eq!(
+            classify_block_hash("aa", "bb", "bb", 1, 1),
+            BlockValidation::Reorg {
+                recorded: "aa".to_string(),
+                canonical: "bb".to_string()
+            }
+        );
+        assert!(matches!(
+            classify_block_hash("aa", "aa", "cc", 2, 1),
+            BlockValidation::RemoteFork { .. }
+        ));
+        assert!(matches!(
+            classify_block_hash("aa", "aa", "cc", 1, 3),
+            BlockValidation::LocalFork { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_validator() {
+        // Blocks 100 and 115 were replaced since they were recorded, block 130 was not
+        let callbook =
+            CallBook::new(String::new(), String::new(), None).with_data_source(Arc::new(
+                FixtureDataSource::new()
+                    .with_block_hash("mainnet", 100, "bb")
+                    .with_block_hash("mainnet", 115, "ff")
+                    .with_block_hash("mainnet", 130, "cc"),
+            ));
+        let mut validator = BlockValidator::new(BlockValidatorConfig {
+            confirmation_depth: 20,
+            max_blocks_per_network: 3,
+        });
+        validator.record_local(&pointer(70, "00"));
+        validator.record_local(&pointer(100, "aa"));
+        validator.record_local(&pointer(115, "ee"));
+        validator.record_local(&pointer(130, "cc"));
+        assert_eq!(validator.local_hash("mainnet", 70), None);
+
+        assert_eq!(
+            validator
+                .validate_remote(&callbook, "peer-0", &pointer(100, "bb"))
+                .await
+                .unwrap(),
+            BlockValidation::Reorg {
+                recorded: "aa".to_string(),
+                canonical: "bb".to_string()
+            }
+        );
+        // The recorded hash follows the reorg, later peers on the canonical block match
+        assert_eq!(validator.local_hash("mainnet", 100), Some("bb"));
+        assert_eq!(
+            validator
+                .validate_remote(&callbook, "peer-1", &pointer(100, "bb"))
+                .await
+                .unwrap(),
+            BlockValidation::Match
+        );
+        assert_eq!(
+            validator
+                .validate_remote(&callbook, "peer-0", &pointer(99, "bb"))
+                .await
+                .unwrap(),
+            BlockValidation::Unknown
+        );
+        assert!(matches!(
+            validator
+                .validate_remote(&callbook, "peer-0", &pointer(130, "dd"))
+                .await
+                .unwrap(),
+            BlockValidation::RemoteFork { .. }
+        ));
+        validator.record_remote("peer-1", &pointer(130, "dd"));
+        assert!(matches!(
+            validator
+                .validate_remote(&callbook, "peer-2", &pointer(130, "dd"))
+                .await
+                .unwrap(),
+            BlockValidation::LocalFork { .. }
+        ));
+
+        let chainheads = HashMap::from([(
+            NetworkName::from("mainnet"),
+            BlockPointer::new(140, "0xhead".to_string()),
+        )]);
+        let reorged = validator.verify_confirmations(&callbook, &chainheads).await;
+        assert_eq!(
+            reorged,
+            vec![ReorgedBlock {
+                recorded: pointer(115, "ee"),
+                canonical_hash: "ff".to_string(),
+            }]
+        );
+        assert_eq!(validator.local_hash("mainnet", 115), Some("ff"));
+        // Confirmed blocks are not verified again
+        assert!(validator
+            .verify_confirmations(&callbook, &chainheads)
+            .await
+            .is_empty());
+    }
+}
@@ -0,0 +1,566 @@
+//! Alerting layer in front of the notification router. Repeats of an alert within the
+//! dedupe window are suppressed and summarized in a digest once the window passes,
+//! each channel is rate limited, with the alerts it missed summarized in its next
+//! digest, and a resolve notification is sent when an alerted
+//! condition clears. Active alerts are kept in memory and optionally persisted to a
+//! JSON file, so a restarted radio can still resolve them. Alerts can be muted for a
+//! while, and alerts raised while muted are summarized in digests once it ends.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex as SyncMutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_derive::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use super::notifier::{Alert, Notifier};
+use super::router::{deliver, NotificationReport, NotificationRouter};
+
+/// Longest mute accepted
+pub const MAX_MUTE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Alerts delivered to a channel per `period`
+    pub max_alerts: usize,
+    pub period: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            max_alerts: 10,
+            period: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlertingConfig {
+    /// Repeats of an alert within this window after it was sent are suppressed
+    pub dedupe_window: Duration,
+    /// Limit applied to each channel separately
+    pub rate_limit: RateLimit,
+    /// File the active alerts are persisted to
+    pub state_path: Option<PathBuf>,
+}
+
+impl Default for AlertingConfig {
+    fn default() -> Self {
+        AlertingConfig {
+            dedupe_window: Duration::from_secs(600),
+            rate_limit: RateLimit::default(),
+            state_path: None,
+        }
+    }
+}
+
+/// Alert that was raised and not resolved yet
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActiveAlert {
+    /// Latest occurrence of the alert
+    pub alert: Alert,
+    /// Unix timestamp in milliseconds of the first occurrence
+    pub first_seen: u64,
+    /// Unix timestamp in milliseconds the alert or its digest was last sent
+    pub last_sent: u64,
+    /// Occurrences suppressed since it was last sent
+    pub suppressed: u64,
+    /// Occurrences a channel missed to its rate limit, by channel
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub throttled: HashMap<String, u64>,
+}
+
+/// What happened to a raised alert
+#[derive(Debug)]
+pub enum AlertOutcome {
+    Sent(NotificationReport),
+    /// Duplicate of an alert sent within the dedupe window, counted towards its digest
+    Suppressed {
+        repeats: u64,
+    },
+}
+
+#[derive(Debug, Default)]
+struct AlertingState {
+    active: HashMap<String, ActiveAlert>,
+    /// Delivery times in milliseconds per channel within the rate limit period
+    deliveries: HashMap<String, VecDeque<u64>>,
+    /// Unix timestamp in milliseconds alerts are muted until
+    muted_until: Option<u64>,
+}
+
+/// Deduplicating, throttling alert sender, shared by every clone
+#[derive(Clone)]
+pub struct AlertManager {
+    router: NotificationRouter,
+    config: AlertingConfig,
+    state: Arc<SyncMutex<AlertingState>>,
+    /// Held while writing the state file, so writes never interleave
+    persisting: Arc<SyncMutex<()>>,
+}
+
+impl AlertManager {
+    /// Create the alert manager, restoring the active alerts from the state file if
+    /// one is configured and exists
+    pub fn new(router: NotificationRouter, config: AlertingConfig) -> Self {
+        let active = config
+            .state_path
+            .as_ref()
+            .filter(|path| path.exists())
+            .and_then(|path| {
+                std::fs::read_to_string(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+                    .map_err(|e| warn!(err = e, "Could not restore active alerts"))
+                    .ok()
+            })
+            .unwrap_or_default();
+        AlertManager {
+            router,
+            config,
+            state: Arc::new(SyncMutex::new(AlertingState {
+                active,
+                deliveries: HashMap::new(),
+                muted_until: None,
+            })),
+            persisting: Arc::default(),
+        }
+    }
+
+    /// Alerts raised and not resolved yet, by key
+    pub fn active_alerts(&self) -> HashMap<String, ActiveAlert> {
+        self.state.lock().unwrap().active.clone()
+    }
+
+    /// Mute alerts for the duration, returning the Unix timestamp in milliseconds the
+    /// mute ends, or `None` without muting when the duration exceeds `MAX_MUTE`.
+    /// Resolve notifications are still sent
+    pub fn mute(&self, duration: Duration) -> Option<u64> {
+        let until = Some(duration)
+            .filter(|duration| *duration <= MAX_MUTE)
+            .and_then(|duration| u64::try_from(duration.as_millis()).ok())
+            .and_then(|millis| unix_now_ms().checked_add(millis))?;
+        self.state.lock().unwrap().muted_until = Some(until);
+        Some(until)
+    }
+
+    pub fn unmute(&self) {
+        self.state.lock().unwrap().muted_until = None;
+    }
+
+    /// Unix timestamp in milliseconds the current mute ends
+    pub fn muted_until(&self) -> Option<u64> {
+        let now = unix_now_ms();
+        self.state
+            .lock()
+            .unwrap()
+            .muted_until
+            .filter(|until| now < *until)
+    }
+
+    /// Send an alert unless it repeats one sent within the dedupe window
+    pub async fn raise(&self, alert: Alert) -> AlertOutcome {
+        self.raise_at(alert, unix_now_ms()).await
+    }
+
+    async fn raise_at(&self, alert: Alert, now: u64) -> AlertOutcome {
+        let key = alert.key();
+        let window = self.config.dedupe_window.as_millis() as u64;
+        let (alert, occurrences) = {
+            let mut state = self.state.lock().unwrap();
+            if state.muted_until.is_some_and(|until| now < until) {
+                let active = state.active.entry(key).or_insert_with(|| ActiveAlert {
+                    alert: alert.clone(),
+                    first_seen: now,
+                    last_sent: now,
+                    suppressed: 0,
+                    throttled: HashMap::new(),
+                });
+                active.suppressed += 1;
+                active.alert = alert;
+                return AlertOutcome::Suppressed {
+                    repeats: active.suppressed,
+                };
+            }
+            match state.active.get_mut(&key) {
+                Some(active) if now < active.last_sent + window => {
+                    active.suppressed += 1;
+                    active.alert = alert;
+                    return AlertOutcome::Suppressed {
+                        repeats: active.suppressed,
+                    };
+                }
+                Some(active) => {
+                    let sent = digest(active, alert.clone(), now, window);
+                    let occurrences = active.suppressed + 1;
+                    active.alert = alert;
+                    active.last_sent = now;
+                    active.suppressed = 0;
+                    (sent, occurrences)
+                }
+                None => {
+                    state.active.insert(
+                        key.clone(),
+                        ActiveAlert {
+                            alert: alert.clone(),
+                            first_seen: now,
+                            last_sent: now,
+                            suppressed: 0,
+                            throttled: HashMap::new(),
+                        },
+                    );
+                    (alert, 1)
+                }
+            }
+        };
+        self.persist().await;
+        let report = self
+            .send(&alert, self.router.notifiers_for(&alert), now, true)
+            .await;
+        self.hold_back(&key, &report.throttled, occurrences).await;
+        AlertOutcome::Sent(report)
+    }
+
+    /// Notify that the condition of an active alert cleared. Resolve notifications are
+    /// not rate limited, so no channel misses that an incident is over
+    pub async fn resolve(&self, key: &str) -> Option<NotificationReport> {
+        let active = self.state.lock().unwrap().active.remove(key)?;
+        self.persist().await;
+        let alert = active.alert.resolve();
+        let notifiers = self.router.notifiers_for(&alert);
+        Some(self.send(&alert, notifiers, unix_now_ms(), false).await)
+    }
+
+    /// Send digests of the alerts with suppressed repeats whose dedupe window passed,
+    /// and to each channel a digest of the alerts it missed to its rate limit
+    pub async fn flush_digests(&self) -> Vec<NotificationReport> {
+        self.flush_digests_at(unix_now_ms()).await
+    }
+
+    async fn flush_digests_at(&self, now: u64) -> Vec<NotificationReport> {
+        let window = self.config.dedupe_window.as_millis() as u64;
+        let mut digests = vec![];
+        let mut missed = vec![];
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.muted_until.is_some_and(|until| now < until) {
+                return vec![];
+            }
+            for (key, active) in state.active.iter_mut() {
+                if active.suppressed > 0 && now >= active.last_sent + window {
+                    let digest = digest(active, active.alert.clone(), now, window);
+                    digests.push((key.clone(), digest, active.suppressed));
+                    active.last_sent = now;
+                    active.suppressed = 0;
+                }
+                for (channel, occurrences) in active.throttled.drain() {
+                    let digest = active.alert.clone().with_field(
+                        "Throttled",
+                        format!("{occurrences} like this held back by the rate limit"),
+                    );
+                    missed.push((key.clone(), channel, digest, occurrences));
+                }
+            }
+        }
+        if digests.is_empty() && missed.is_empty() {
+            return vec![];
+        }
+        self.persist().await;
+        let mut reports = vec![];
+        for (key, alert, occurrences) in digests {
+            let report = self
+                .send(&alert, self.router.notifiers_for(&alert), now, true)
+                .await;
+            self.hold_back(&key, &report.throttled, occurrences).await;
+            reports.push(report);
+        }
+        for (key, channel, alert, occurrences) in missed {
+            let notifiers = self
+                .router
+                .notifiers_for(&alert)
+                .into_iter()
+                .filter(|notifier| notifier.name() == channel)
+                .collect();
+            let report = self.send(&alert, notifiers, now, true).await;
+            self.hold_back(&key, &report.throttled, occurrences).await;
+            reports.push(report);
+        }
+        reports
+    }
+
+    /// Flush digests every `interval` in a background task
+    pub fn spawn_digests(&self, interval: Duration) -> JoinHandle<()> {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                manager.flush_digests().await;
+            }
+        })
+    }
+
+    /// Deliver to the channels, skipping those over their rate limit
+    async fn send(
+        &self,
+        alert: &Alert,
+        notifiers: Vec<Arc<dyn Notifier>>,
+        now: u64,
+        rate_limited: bool,
+    ) -> NotificationReport {
+        let period = self.config.rate_limit.period.as_millis() as u64;
+        let mut throttled = vec![];
+        let notifiers = {
+            let mut state = self.state.lock().unwrap();
+            notifiers
+                .into_iter()
+                .filter(|notifier| {
+                    let deliveries = state
+                        .deliveries
+                        .entry(notifier.name().to_string())
+                        .or_default();
+                    while deliveries.front().is_some_and(|t| t + period <= now) {
+                        deliveries.pop_front();
+                    }
+                    if rate_limited && deliveries.len() >= self.config.rate_limit.max_alerts {
+                        throttled.push(notifier.name().to_string());
+                        return false;
+                    }
+                    deliveries.push_back(now);
+                    true
+                })
+                .collect()
+        };
+        if !throttled.is_empty() {
+            debug!(
+                channels = tracing::field::debug(&throttled),
+                title = alert.title,
+                "Alert throttled by channel rate limits"
+            );
+        }
+        let mut report = deliver(notifiers, alert).await;
+        report.throttled = throttled;
+        report
+    }
+
+    /// Count the occurrences of an alert the throttled channels missed towards their
+    /// next digest
+    async fn hold_back(&self, key: &str, channels: &[String], occurrences: u64) {
+        if channels.is_empty() {
+            return;
+        }
+        {
+            let mut state = self.state.lock().unwrap();
+            let Some(active) = state.active.get_mut(key) else {
+                return;
+            };
+            for channel in channels {
+                *active.throttled.entry(channel.clone()).or_default() += occurrences;
+            }
+        }
+        self.persist().await;
+    }
+
+    /// Write the active alerts through a temporary file so a crash never leaves a
+    /// partial file. Writes are serialized and each one saves the state current when
+    /// it starts, so an older state never replaces a newer one
+    async fn persist(&self) {
+        let Some(path) = self.config.state_path.clone() else {
+            return;
+        };
+        let state = self.state.clone();
+        let persisting = self.persisting.clone();
+        let written = tokio::task::spawn_blocking(move || {
+            let _persisting = persisting.lock().unwrap();
+            let json =
+                serde_json::to_string(&state.lock().unwrap().active).map_err(|e| e.to_string())?;
+            let tmp_path = path.with_extension("tmp");
+            std::fs::write(&tmp_path, json)
+                .and_then(|_| std::fs::rename(&tmp_path, &path))
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|written| written);
+        if let Err(e) = written {
+            warn!(err = e, "Could not persist active alerts");
+        }
+    }
+}
+
+/// The latest occurrence, with a summary of the repeats suppressed since the alert was
+/// last sent
+fn digest(active: &ActiveAlert, alert: Alert, now: u64, window: u64) -> Alert {
+    if active.suppressed == 0 {
+        return alert;
+    }
+    let minutes = now.saturating_sub(active.last_sent).max(window) / 60_000;
+    alert.with_field(
+        "Repeats",
+        format!(
+            "{} more like this in the last {minutes}m",
+            active.suppressed
+        ),
+    )
+}
+
+fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bots::notifier::Severity;
+    use crate::bots::router::tests::RecordingNotifier;
+    use crate::bots::router::RouteRule;
+
+    fn alert_manager(config: AlertingConfig) -> (AlertManager, Arc<RecordingNotifier>) {
+        let notifier = RecordingNotifier::new("recording", false);
+        let router =
+            NotificationRouter::new().with_notifier(notifier.clone(), RouteRule::default());
+        (AlertManager::new(router, config), notifier)
+    }
+
+    fn alert(deployment: &str) -> Alert {
+        Alert::new(Severity::Warning, "poi-radio", "Divergent POI")
+            .with_key(format!("poi/{deployment}"))
+            .with_field("deployment", deployment)
+    }
+
+    const MINUTE: u64 = 60_000;
+
+    #[tokio::test]
+    async fn test_dedupe_and_digest() {
+        let (manager, notifier) = alert_manager(AlertingConfig::default());
+        assert!(matches!(
+            manager.raise_at(alert("Qm1"), 0).await,
+            AlertOutcome::Sent(_)
+        ));
+        for i in 1..=12 {
+            assert!(matches!(
+                manager.raise_at(alert("Qm1"), i * MINUTE / 2).await,
+                AlertOutcome::Suppressed { repeats } if repeats == i
+            ));
+        }
+        // A different condition is not a duplicate
+        manager.raise_at(alert("Qm2"), MINUTE).await;
+        assert_eq!(notifier.alerts.lock().unwrap().len(), 2);
+
+        assert!(manager.flush_digests_at(5 * MINUTE).await.is_empty());
+        let reports = manager.flush_digests_at(10 * MINUTE).await;
+        assert_eq!(reports.len(), 1);
+        let digest = notifier.alerts.lock().unwrap()[2].clone();
+        assert_eq!(
+            digest.fields.last().unwrap().1,
+            "12 more like this in the last 10m"
+        );
+        assert!(manager.flush_digests_at(30 * MINUTE).await.is_empty());
+
+        // Resolving sends once and forgets the alert
+        manager.resolve("poi/Qm1").await.unwrap();
+        assert!(notifier.alerts.lock().unwrap()[3].resolved);
+        assert!(manager.resolve("poi/Qm1").await.is_none());
+        assert_eq!(manager.active_alerts().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let (manager, notifier) = alert_manager(AlertingConfig {
+            rate_limit: RateLimit {
+                max_alerts: 2,
+                period: Duration::from_secs(60),
+            },
+            ..Default::default()
+        });
+        for deployment in ["Qm1", "Qm2", "Qm3"] {
+            manager.raise_at(alert(deployment), 0).await;
+        }
+        let AlertOutcome::Sent(report) = manager.raise_at(alert("Qm4"), MINUTE / 2).await else {
+            panic!("Alert was suppressed")
+        };
+        assert_eq!(report.throttled, vec!["recording"]);
+        assert_eq!(notifier.alerts.lock().unwrap().len(), 2);
+
+        manager.raise_at(alert("Qm5"), MINUTE).await;
+        assert_eq!(notifier.alerts.lock().unwrap().len(), 3);
+        assert_eq!(
+            manager.active_alerts()["poi/Qm4"].throttled,
+            HashMap::from([("recording".to_string(), 1)])
+        );
+
+        // The throttled alerts are summarized once the channel is under its limit
+        assert_eq!(manager.flush_digests_at(2 * MINUTE).await.len(), 2);
+        let alerts = notifier.alerts.lock().unwrap();
+        assert_eq!(alerts.len(), 5);
+        assert!(alerts[3..].iter().all(
+            |alert| alert.fields.last().unwrap().1 == "1 like this held back by the rate limit"
+        ));
+        assert!(manager
+            .active_alerts()
+            .values()
+            .all(|active| active.throttled.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_mute() {
+        let (manager, notifier) = alert_manager(AlertingConfig::default());
+        manager.state.lock().unwrap().muted_until = Some(10 * MINUTE);
+        for (i, at) in [0, MINUTE].into_iter().enumerate() {
+            assert!(matches!(
+                manager.raise_at(alert("Qm1"), at).await,
+                AlertOutcome::Suppressed { repeats } if repeats == i as u64 + 1
+            ));
+        }
+        assert!(manager.flush_digests_at(5 * MINUTE).await.is_empty());
+        assert!(notifier.alerts.lock().unwrap().is_empty());
+
+        // Alerts raised while muted are summarized once the mute ends
+        assert_eq!(manager.flush_digests_at(11 * MINUTE).await.len(), 1);
+        assert_eq!(
+            notifier.alerts.lock().unwrap()[0].fields.last().unwrap().1,
+            "2 more like this in the last 11m"
+        );
+
+        assert!(manager.mute(Duration::from_secs(3600)).unwrap() > unix_now_ms());
+        assert!(manager.mute(Duration::MAX).is_none());
+        assert!(manager.muted_until().is_some());
+        manager.unmute();
+        assert!(manager.muted_until().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_persisted_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AlertingConfig {
+            state_path: Some(dir.path().join("alerts.json")),
+            ..Default::default()
+        };
+        let (manager, _) = alert_manager(config.clone());
+        manager.raise(alert("Qm1")).await;
+
+        let (restarted, notifier) = alert_manager(config.clone());
+        assert!(restarted.active_alerts().contains_key("poi/Qm1"));
+        restarted.resolve("poi/Qm1").await.unwrap();
+        assert!(notifier.alerts.lock().unwrap()[0].resolved);
+
+        // Concurrent writes from clones leave the latest state on disk
+        let raises: Vec<_> = (0..20)
+            .map(|i| {
+                let manager = restarted.clone();
+                tokio::spawn(async move { manager.raise(alert(&format!("Qm{i}"))).await })
+            })
+            .collect();
+        for raise in raises {
+            raise.await.unwrap();
+        }
+        let (restarted, _) = alert_manager(config);
+        assert_eq!(restarted.active_alerts().len(), 20);
+    }
+}
@@ -0,0 +1,279 @@
+//! Chat commands operators send to a running radio, such as `/status` or `/mute 1h`.
+//! Radios implement `RadioCommands` to answer them, and the Telegram and Slack
+//! listeners pass the commands of allowlisted chats and users to a `CommandHandler`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::warn;
+
+use super::alerting::MAX_MUTE;
+
+pub const COMMANDS_HELP: &str = "Commands:\n\
+/status - radio status\n\
+/peers - connected Graphcast peers\n\
+/topics - content topics the radio is subscribed to\n\
+/mute <duration> - mute alerts, such as /mute 30m or /mute 1h\n\
+/unmute - resume alerts\n\
+/help - this message";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BotCommand {
+    Status,
+    Peers,
+    Topics,
+    Mute(Duration),
+    Unmute,
+    Help,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    #[error("Unknown command: {0}")]
+    Unknown(String),
+    #[error("Missing argument: {0}")]
+    MissingArgument(&'static str),
+    #[error("Invalid duration '{0}', expected a number followed by s, m, h or d, up to 30d")]
+    InvalidDuration(String),
+}
+
+impl BotCommand {
+    /// Parse a chat message, `None` when it is not a command. Commands may be addressed
+    /// to a bot, as in `/status@radio_bot`
+    pub fn parse(text: &str) -> Option<Result<Self, CommandError>> {
+        let mut words = text.split_whitespace();
+        let command = words.next()?.strip_prefix('/')?;
+        let command = command.split('@').next().unwrap_or_default();
+        Some(match command.to_lowercase().as_str() {
+            "status" => Ok(BotCommand::Status),
+            "peers" => Ok(BotCommand::Peers),
+            "topics" => Ok(BotCommand::Topics),
+            "mute" => words
+                .next()
+                .ok_or(CommandError::MissingArgument("duration"))
+                .and_then(parse_duration)
+                .map(BotCommand::Mute),
+            "unmute" => Ok(BotCommand::Unmute),
+            "help" | "start" => Ok(BotCommand::Help),
+            _ => Err(CommandError::Unknown(command.to_string())),
+        })
+    }
+}
+
+/// Parse durations such as `90s`, `30m`, `1h` or `2d`, up to `MAX_MUTE`
+pub fn parse_duration(text: &str) -> Result<Duration, CommandError> {
+    let invalid = || CommandError::InvalidDuration(text.to_string());
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (amount, unit) = text.split_at(split);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    amount
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .filter(|duration| *duration <= MAX_MUTE)
+        .ok_or_else(invalid)
+}
+
+/// Radio state reported by the chat commands
+#[async_trait]
+pub trait RadioCommands: Send + Sync {
+    /// Summary of the radio, such as its name, version and sync state
+    async fn status(&self) -> String;
+
+    /// Connected peers
+    async fn peers(&self) -> Vec<String>;
+
+    /// Subscribed content topics
+    async fn topics(&self) -> Vec<String>;
+
+    /// Mute the radio's alerts for the duration, such as with `AlertManager::mute`,
+    /// and describe the outcome
+    async fn mute(&self, duration: Duration) -> String;
+
+    async fn unmute(&self) -> String;
+}
+
+/// Chats and users allowed to command the radio. Either an allowed chat or an allowed
+/// user is sufficient, and an empty allowlist rejects every command
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandAllowlist {
+    /// Telegram chat ids or Slack channel ids
+    #[serde(default)]
+    pub chat_ids: Vec<String>,
+    /// Telegram user ids or Slack user ids
+    #[serde(default)]
+    pub user_ids: Vec<String>,
+}
+
+impl CommandAllowlist {
+    pub fn allows(&self, chat_id: &str, user_id: &str) -> bool {
+        self.chat_ids.iter().any(|id| id == chat_id) || self.user_ids.iter().any(|id| id == user_id)
+    }
+}
+
+/// Answers the commands of allowlisted chats and users
+#[derive(Clone)]
+pub struct CommandHandler {
+    radio: Arc<dyn RadioCommands>,
+    allowlist: CommandAllowlist,
+}
+
+impl CommandHandler {
+    pub fn new(radio: Arc<dyn RadioCommands>, allowlist: CommandAllowlist) -> Self {
+        CommandHandler { radio, allowlist }
+    }
+
+    /// Reply to a chat message, `None` when it is not a command
+    pub async fn handle(&self, chat_id: &str, user_id: &str, text: &str) -> Option<String> {
+        let command = BotCommand::parse(text)?;
+        if !self.allowlist.allows(chat_id, user_id) {
+            warn!(
+                chat_id,
+                user_id, "Rejected radio command from a chat and user not allowlisted"
+            );
+            return Some(String::from("Not authorized to command this radio"));
+        }
+        let reply = match command {
+            Ok(BotCommand::Status) => self.radio.status().await,
+            Ok(BotCommand::Peers) => list("peers", self.radio.peers().await),
+            Ok(BotCommand::Topics) => list("topics", self.radio.topics().await),
+            Ok(BotCommand::Mute(duration)) => self.radio.mute(duration).await,
+            Ok(BotCommand::Unmute) => self.radio.unmute().await,
+            Ok(BotCommand::Help) => COMMANDS_HELP.to_string(),
+            Err(e) => format!("{e}\n\n{COMMANDS_HELP}"),
+        };
+        Some(reply)
+    }
+}
+
+fn list(name: &str, items: Vec<String>) -> String {
+    if items.is_empty() {
+        return format!("No {name}");
+    }
+    let mut text = format!("{} {name}:", items.len());
+    for item in items {
+        text.push_str(&format!("\n- {item}"));
+    }
+    text
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Radio with fixed state, recording mutes
+    #[derive(Default)]
+    pub(crate) struct TestRadio {
+        pub muted: Mutex<Option<Duration>>,
+    }
+
+    #[async_trait]
+    impl RadioCommands for TestRadio {
+        async fn status(&self) -> String {
+            String::from("poi-radio 0.1.0, 3 deployments")
+        }
+
+        async fn peers(&self) -> Vec<String> {
+            vec![String::from("0xe9a1"), String::from("0x7f3c")]
+        }
+
+        async fn topics(&self) -> Vec<String> {
+            vec![]
+        }
+
+        async fn mute(&self, duration: Duration) -> String {
+            *self.muted.lock().unwrap() = Some(duration);
+            format!("Muted for {}s", duration.as_secs())
+        }
+
+        async fn unmute(&self) -> String {
+            *self.muted.lock().unwrap() = None;
+            String::from("Unmuted")
+        }
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(BotCommand::parse("hello"), None);
+        assert_eq!(BotCommand::parse(" /status "), Some(Ok(BotCommand::Status)));
+        assert_eq!(
+            BotCommand::parse("/peers@radio_bot"),
+            Some(Ok(BotCommand::Peers))
+        );
+        assert_eq!(
+            BotCommand::parse("/mute 1h"),
+            Some(Ok(BotCommand::Mute(Duration::from_secs(3600))))
+        );
+        assert_eq!(
+            BotCommand::parse("/mute"),
+            Some(Err(CommandError::MissingArgument("duration")))
+        );
+        assert_eq!(
+            BotCommand::parse("/mute forever"),
+            Some(Err(CommandError::InvalidDuration(String::from("forever"))))
+        );
+        assert_eq!(
+            BotCommand::parse("/reboot"),
+            Some(Err(CommandError::Unknown(String::from("reboot"))))
+        );
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(172_800)));
+        assert!(parse_duration("1w").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("31d").is_err());
+        assert!(parse_duration("18446744073709551615d").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_commands() {
+        let radio = Arc::new(TestRadio::default());
+        let handler = CommandHandler::new(
+            radio.clone(),
+            CommandAllowlist {
+                chat_ids: vec![String::from("-1001234")],
+                user_ids: vec![String::from("U42")],
+            },
+        );
+
+        assert_eq!(handler.handle("-1001234", "7", "good morning").await, None);
+        assert_eq!(
+            handler.handle("-1001234", "7", "/peers").await.unwrap(),
+            "2 peers:\n- 0xe9a1\n- 0x7f3c"
+        );
+        assert_eq!(
+            handler.handle("C1", "U42", "/topics").await.unwrap(),
+            "No topics"
+        );
+        assert_eq!(
+            handler.handle("C1", "U42", "/mute 30m").await.unwrap(),
+            "Muted for 1800s"
+        );
+        assert_eq!(
+            *radio.muted.lock().unwrap(),
+            Some(Duration::from_secs(1800))
+        );
+        assert_eq!(
+            handler.handle("C1", "U7", "/unmute").await.unwrap(),
+            "Not authorized to command this radio"
+        );
+        assert!(radio.muted.lock().unwrap().is_some());
+        assert!(handler
+            .handle("C1", "U42", "/reboot")
+            .await
+            .unwrap()
+            .starts_with("Unknown command: reboot"));
+    }
+}
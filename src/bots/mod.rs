@@ -0,0 +1,319 @@
+pub mod alerting;
+pub mod commands;
+pub mod notifier;
+pub mod pagerduty;
+pub mod router;
+pub mod slack;
+pub mod webhook;
+
+use std::collections::HashMap;
+use teloxide::types::ParseMode;
+use thiserror::Error;
+
+use rsb_derive::Builder;
+use slack_morphism::prelude::*;
+
+use notifier::Alert;
+
+use teloxide::prelude::*;
+use teloxide::types::{ChatId, UpdateKind};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use commands::CommandHandler;
+
+// DiscordBot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscordBot {}
+
+#[derive(Error, Debug)]
+pub enum DiscordBotError {
+    #[error("Request error: {0}")]
+    RequestError(#[from] reqwest::Error),
+}
+
+impl DiscordBot {
+    pub async fn send_webhook(
+        webhook_url: &str,
+        radio_name: &str,
+        content: &str,
+    ) -> Result<(), DiscordBotError> {
+        let mut map = HashMap::new();
+        map.insert(
+            "content",
+            format!("🚨 Notification from Radio '{radio_name}' \n{content}"),
+        );
+
+        let client = reqwest::Client::new();
+
+        client.post(webhook_url).json(&map).send().await?;
+
+        Ok(())
+    }
+}
+
+// SlackBot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlackBot {}
+
+#[derive(Error, Debug)]
+pub enum SlackBotError {
+    #[error("Request error: {0}")]
+    RequestError(#[from] reqwest::Error),
+}
+
+impl SlackBot {
+    pub async fn send_webhook(
+        webhook_url: &str,
+        radio_name: &str,
+        content: &str,
+    ) -> Result<(), SlackBotError> {
+        let mut map = HashMap::new();
+        map.insert(
+            "text",
+            format!("🚨 Notification from Radio '{radio_name}' \n{content}"),
+        );
+
+        let client = reqwest::Client::new();
+
+        client.post(webhook_url).json(&map).send().await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct TelegramBot {
+    bot: Bot,
+}
+
+// `Bot`'s own Debug prints the token
+impl std::fmt::Debug for TelegramBot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TelegramBot")
+            .field("bot_token", &"[REDACTED]")
+            .finish()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TelegramBotError {
+    #[error("Request error: {0}")]
+    RequestError(#[from] teloxide::RequestError),
+}
+
+impl TelegramBot {
+    pub fn new(bot_token: String) -> Self {
+        Self {
+            bot: Bot::new(bot_token),
+        }
+    }
+
+    /// Call another Bot API endpoint, such as a local stub
+    pub fn with_api_url(self, api_url: reqwest::Url) -> Self {
+        Self {
+            bot: self.bot.set_api_url(api_url),
+        }
+    }
+
+    /// Long poll for chat messages in a background task and reply to the commands
+    pub fn spawn_command_listener(&self, handler: CommandHandler) -> JoinHandle<()> {
+        let bot = self.bot.clone();
+        tokio::spawn(async move {
+            // Skip the commands sent while the radio was down. An offset of -1 returns
+            // only the latest pending update and confirms the earlier ones
+            let mut offset = match bot.get_updates().offset(-1).send().await {
+                Ok(updates) => updates.last().map_or(0, |update| update.id + 1),
+                Err(e) => {
+                    warn!(
+                        err = tracing::field::debug(&e),
+                        "Could not skip pending Telegram updates"
+                    );
+                    0
+                }
+            };
+            loop {
+                // Stay below the request timeout of the bot's HTTP client
+                let updates = match bot.get_updates().offset(offset).timeout(10).send().await {
+                    Ok(updates) => updates,
+                    Err(e) => {
+                        warn!(
+                            err = tracing::field::debug(&e),
+                            "Could not get Telegram updates"
+                        );
+                        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                        continue;
+                    }
+                };
+                for update in updates {
+                    offset = update.id + 1;
+                    let UpdateKind::Message(message) = update.kind else {
+                        continue;
+                    };
+                    let (Some(text), Some(user)) = (message.text(), message.from()) else {
+                        continue;
+                    };
+                    let Some(reply) = handler
+                        .handle(&message.chat.id.to_string(), &user.id.to_string(), text)
+                        .await
+                    else {
+                        continue;
+                    };
+                    debug!(chat_id = message.chat.id.0, "Replying to radio command");
+                    if let Err(e) = bot.send_message(message.chat.id, reply).send().await {
+                        warn!(
+                            err = tracing::field::debug(&e),
+                            "Could not reply to Telegram command"
+                        );
+                    }
+                }
+            }
+        })
+    }
+
+    pub async fn send_message(
+        &self,
+        chat_id: i64,
+        radio_name: &str,
+        content: &str,
+    ) -> Result<(), TelegramBotError> {
+        let message = format!(
+            "🚨 Notification from Radio '{radio_name}' \n{content}",
+            radio_name = radio_name,
+            content = content
+        );
+        self.bot
+            .send_message(ChatId(chat_id), message)
+            .parse_mode(ParseMode::Html) // or ParseMode::MarkdownV2
+            .disable_web_page_preview(true)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Builder)]
+pub struct AlertMessageTemplateParams {
+    pub user_id: Option<SlackUserId>,
+    pub radio_name: String,
+    pub content: String,
+    /// Whether the alerted condition cleared
+    #[default = "false"]
+    pub resolved: bool,
+}
+
+impl AlertMessageTemplateParams {
+    pub fn create(radio_name: String, content: String) -> Self {
+        Self {
+            user_id: None,
+            radio_name,
+            content,
+            resolved: false,
+        }
+    }
+
+    /// Template of a structured alert, with its title, fields and links as the content
+    pub fn from_alert(alert: &Alert) -> Self {
+        let mut content = format!("[{}] {}", alert.severity, alert.title);
+        for (name, value) in &alert.fields {
+            content.push_str(&format!("\n{name}: {value}"));
+        }
+        for link in &alert.links {
+            content.push_str(&format!("\n{}: {}", link.label, link.url));
+        }
+        Self {
+            user_id: None,
+            radio_name: alert.radio.clone(),
+            content,
+            resolved: alert.resolved,
+        }
+    }
+}
+
+impl SlackMessageTemplate for AlertMessageTemplateParams {
+    fn render_template(&self) -> SlackMessageContent {
+        let emoji = if self.resolved { "✅" } else { "🚨" };
+        let user = match &self.user_id {
+            Some(id) => format!(
+                "{emoji} Hello {}!, Notification from Radio '{}'",
+                id.to_slack_format(),
+                &self.radio_name
+            ),
+            None => format!("{emoji} Notification from Radio '{}'", &self.radio_name),
+        };
+        let user = if self.resolved {
+            format!("{user}, resolved")
+        } else {
+            user
+        };
+        SlackMessageContent::new().with_blocks(slack_blocks![
+            some_into(SlackSectionBlock::new().with_text(pt!(user))),
+            some_into(SlackDividerBlock::new()),
+            some_into(SlackSectionBlock::new().with_text(pt!(&self.content)))
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{extract::Path, routing::post, Json, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::bots::commands::{tests::TestRadio, CommandAllowlist};
+    use crate::graphql::http_client::tests::spawn_stub;
+
+    #[tokio::test]
+    async fn test_command_listener_skips_backlog() {
+        let calls: Arc<Mutex<Vec<(String, Value)>>> = Arc::default();
+        let recorded = calls.clone();
+        let app = Router::new().route(
+            "/bottoken/:method",
+            post(
+                move |Path(method): Path<String>, Json(body): Json<Value>| async move {
+                    let pending = body["offset"] == -1;
+                    recorded.lock().unwrap().push((method, body));
+                    let result = if pending {
+                        json!([{
+                            "update_id": 41,
+                            "message": {
+                                "message_id": 1,
+                                "date": 1700000000,
+                                "chat": {"id": 7, "type": "private", "first_name": "Ops"},
+                                "from": {"id": 7, "is_bot": false, "first_name": "Ops"},
+                                "text": "/mute 1h"
+                            }
+                        }])
+                    } else {
+                        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                        json!([])
+                    };
+                    Json(json!({"ok": true, "result": result}))
+                },
+            ),
+        );
+        let url = spawn_stub(app).await;
+
+        let radio = Arc::new(TestRadio::default());
+        let allowlist = CommandAllowlist {
+            chat_ids: vec![String::from("7")],
+            user_ids: vec![],
+        };
+        let listener = TelegramBot::new(String::from("token"))
+            .with_api_url(url.parse().unwrap())
+            .spawn_command_listener(CommandHandler::new(radio.clone(), allowlist));
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        listener.abort();
+
+        // The pending mute is skipped and polling resumes after it
+        assert!(radio.muted.lock().unwrap().is_none());
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls[0].1["offset"], -1);
+        assert_eq!(calls[1].1["offset"], 42);
+        assert!(calls
+            .iter()
+            .all(|(method, _)| method.eq_ignore_ascii_case("getUpdates")));
+    }
+}
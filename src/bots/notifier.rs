@@ -0,0 +1,453 @@
+//! Structured alerts and the `Notifier` trait implemented by each notification
+//! channel, so radios build an alert once and deliver it to any configured channel.
+
+use std::fmt;
+
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use teloxide::prelude::*;
+use teloxide::types::{ChatId, ParseMode};
+use thiserror::Error;
+
+use crate::graphql::endpoint::Secret;
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    fn emoji(&self) -> &'static str {
+        match self {
+            Severity::Info => "ℹ️",
+            Severity::Warning => "⚠️",
+            Severity::Critical => "🚨",
+        }
+    }
+
+    /// Embed color of Discord messages
+    fn color(&self) -> u32 {
+        match self {
+            Severity::Info => 0x3498db,
+            Severity::Warning => 0xf1c40f,
+            Severity::Critical => 0xe74c3c,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// Embed color of Discord messages for resolved alerts
+const RESOLVED_COLOR: u32 = 0x2ecc71;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlertLink {
+    pub label: String,
+    pub url: String,
+}
+
+/// Notification raised by a radio
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Alert {
+    pub severity: Severity,
+    /// Name of the radio raising the alert
+    pub radio: String,
+    pub title: String,
+    /// Details as ordered name and value pairs, such as the deployment and block
+    #[serde(default)]
+    pub fields: Vec<(String, String)>,
+    #[serde(default)]
+    pub links: Vec<AlertLink>,
+    /// Identity of the condition alerted on, for deduplication and resolving. Defaults
+    /// to the radio and title
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Whether this notifies that the condition cleared
+    #[serde(default)]
+    pub resolved: bool,
+}
+
+impl Alert {
+    pub fn new(severity: Severity, radio: impl Into<String>, title: impl Into<String>) -> Self {
+        Alert {
+            severity,
+            radio: radio.into(),
+            title: title.into(),
+            fields: vec![],
+            links: vec![],
+            key: None,
+            resolved: false,
+        }
+    }
+
+    pub fn with_key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    /// Identity of the alerted condition
+    pub fn key(&self) -> String {
+        self.key
+            .clone()
+            .unwrap_or_else(|| format!("{}/{}", self.radio, self.title))
+    }
+
+    /// Notification that the alerted condition cleared
+    pub fn resolve(mut self) -> Self {
+        self.resolved = true;
+        self
+    }
+
+    fn emoji(&self) -> &'static str {
+        match self.resolved {
+            true => "✅",
+            false => self.severity.emoji(),
+        }
+    }
+
+    /// Severity, or that the alert is resolved
+    fn status(&self) -> String {
+        match self.resolved {
+            true => String::from("resolved"),
+            false => self.severity.to_string(),
+        }
+    }
+
+    pub fn with_field(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.fields.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn with_link(mut self, label: impl Into<String>, url: impl Into<String>) -> Self {
+        self.links.push(AlertLink {
+            label: label.into(),
+            url: url.into(),
+        });
+        self
+    }
+
+    /// Plain text rendering, one field or link per line
+    pub fn text(&self) -> String {
+        let mut text = format!(
+            "{} [{}] {} (radio '{}')",
+            self.emoji(),
+            self.status(),
+            self.title,
+            self.radio
+        );
+        for (name, value) in &self.fields {
+            text.push_str(&format!("\n{name}: {value}"));
+        }
+        for link in &self.links {
+            text.push_str(&format!("\n{}: {}", link.label, link.url));
+        }
+        text
+    }
+
+    /// Telegram HTML rendering
+    pub fn html(&self) -> String {
+        let mut html = format!(
+            "{} <b>{}</b>\nRadio <i>{}</i>, {}",
+            self.emoji(),
+            escape_html(&self.title),
+            escape_html(&self.radio),
+            self.status()
+        );
+        for (name, value) in &self.fields {
+            html.push_str(&format!(
+                "\n<b>{}</b>: {}",
+                escape_html(name),
+                escape_html(value)
+            ));
+        }
+        for link in &self.links {
+            html.push_str(&format!(
+                "\n<a href=\"{}\">{}</a>",
+                escape_html(&link.url),
+                escape_html(&link.label)
+            ));
+        }
+        html
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[derive(Error, Debug)]
+pub enum NotifierError {
+    #[error("Request error: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("Telegram request error: {0}")]
+    Telegram(#[from] teloxide::RequestError),
+    #[error("Channel responded with status {status}: {body}")]
+    Status { status: u16, body: String },
+    #[error("Slack API error: {0}")]
+    Slack(String),
+    #[error("Invalid notification config: {0}")]
+    Config(String),
+    #[error("Notifier panicked: {0}")]
+    Panicked(String),
+}
+
+/// Channel alerts are delivered to
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Name of the channel in reports and logs
+    fn name(&self) -> &str;
+
+    async fn notify(&self, alert: &Alert) -> Result<(), NotifierError>;
+}
+
+/// Post a JSON payload and fail on unsuccessful statuses. Webhook URLs carry their
+/// credentials, so they are left out of request errors
+pub(crate) async fn post_json(
+    client: &reqwest::Client,
+    url: &str,
+    payload: &Value,
+) -> Result<(), NotifierError> {
+    let response = client
+        .post(url)
+        .json(payload)
+        .send()
+        .await
+        .map_err(|e| e.without_url())?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(NotifierError::Status {
+            status: status.as_u16(),
+            body: response.text().await.unwrap_or_default(),
+        });
+    }
+    Ok(())
+}
+
+/// Discord incoming webhook, posting alerts as embeds
+#[derive(Debug, Clone)]
+pub struct DiscordNotifier {
+    name: String,
+    webhook_url: Secret,
+    client: reqwest::Client,
+}
+
+impl DiscordNotifier {
+    pub fn new(name: impl Into<String>, webhook_url: impl Into<String>) -> Self {
+        DiscordNotifier {
+            name: name.into(),
+            webhook_url: Secret::new(webhook_url),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn payload(alert: &Alert) -> Value {
+        let mut fields: Vec<Value> = alert
+            .fields
+            .iter()
+            .map(|(name, value)| json!({"name": name, "value": value, "inline": true}))
+            .collect();
+        fields.extend(
+            alert
+                .links
+                .iter()
+                .map(|link| json!({"name": link.label, "value": link.url, "inline": false})),
+        );
+        json!({
+            "embeds": [{
+                "title": format!("{} {}", alert.emoji(), alert.title),
+                "description": format!("Radio '{}', {}", alert.radio, alert.status()),
+                "color": if alert.resolved { RESOLVED_COLOR } else { alert.severity.color() },
+                "fields": fields,
+            }]
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, alert: &Alert) -> Result<(), NotifierError> {
+        post_json(
+            &self.client,
+            self.webhook_url.expose(),
+            &Self::payload(alert),
+        )
+        .await
+    }
+}
+
+/// Slack incoming webhook, posting alerts as text
+#[derive(Debug, Clone)]
+pub struct SlackNotifier {
+    name: String,
+    webhook_url: Secret,
+    client: reqwest::Client,
+}
+
+impl SlackNotifier {
+    pub fn new(name: impl Into<String>, webhook_url: impl Into<String>) -> Self {
+        SlackNotifier {
+            name: name.into(),
+            webhook_url: Secret::new(webhook_url),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, alert: &Alert) -> Result<(), NotifierError> {
+        post_json(
+            &self.client,
+            self.webhook_url.expose(),
+            &json!({ "text": alert.text() }),
+        )
+        .await
+    }
+}
+
+/// Telegram chat, posting alerts as HTML messages
+#[derive(Clone)]
+pub struct TelegramNotifier {
+    name: String,
+    bot: Bot,
+    chat_id: i64,
+}
+
+// `Bot`'s own Debug prints the token
+impl fmt::Debug for TelegramNotifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TelegramNotifier")
+            .field("name", &self.name)
+            .field("bot_token", &"[REDACTED]")
+            .field("chat_id", &self.chat_id)
+            .finish()
+    }
+}
+
+impl TelegramNotifier {
+    pub fn new(name: impl Into<String>, bot_token: impl Into<String>, chat_id: i64) -> Self {
+        TelegramNotifier {
+            name: name.into(),
+            bot: Bot::new(bot_token),
+            chat_id,
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, alert: &Alert) -> Result<(), NotifierError> {
+        self.bot
+            .send_message(ChatId(self.chat_id), alert.html())
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{routing::post, Json, Router};
+
+    use super::*;
+    use crate::graphql::http_client::tests::spawn_stub;
+
+    fn alert() -> Alert {
+        Alert::new(Severity::Critical, "poi-radio", "Divergent POI")
+            .with_field("deployment", "Qm<1>")
+            .with_link("Dashboard", "https://example.com/d")
+    }
+
+    #[test]
+    fn test_alert_rendering() {
+        assert_eq!(
+            alert().text(),
+            "🚨 [critical] Divergent POI (radio 'poi-radio')\ndeployment: Qm<1>\nDashboard: https://example.com/d"
+        );
+        assert!(alert().html().contains("<b>deployment</b>: Qm&lt;1&gt;"));
+        assert!(alert()
+            .resolve()
+            .text()
+            .starts_with("✅ [resolved] Divergent POI"));
+    }
+
+    #[test]
+    fn test_notifier_debug_redacts_credentials() {
+        let discord = DiscordNotifier::new("ops", "https://discord.com/api/webhooks/1/abc");
+        let telegram = TelegramNotifier::new("on-call", "123:bot-token", -1001234);
+        assert!(!format!("{discord:?}").contains("discord.com"));
+        assert!(!format!("{telegram:?}").contains("bot-token"));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_notifiers() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let recorded = received.clone();
+        let app = Router::new()
+            .route(
+                "/ok",
+                post(move |Json(body): Json<Value>| async move {
+                    recorded.lock().unwrap().push(body);
+                }),
+            )
+            .route(
+                "/fail",
+                post(|| async { (axum::http::StatusCode::BAD_REQUEST, "invalid payload") }),
+            );
+        let url = spawn_stub(app).await;
+
+        DiscordNotifier::new("discord", format!("{url}/ok"))
+            .notify(&alert())
+            .await
+            .unwrap();
+        SlackNotifier::new("slack", format!("{url}/ok"))
+            .notify(&alert())
+            .await
+            .unwrap();
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received[0]["embeds"][0]["color"], 0xe74c3c);
+        assert_eq!(received[0]["embeds"][0]["fields"][0]["value"], "Qm<1>");
+        assert_eq!(received[1]["text"], alert().text());
+
+        assert!(matches!(
+            SlackNotifier::new("slack", format!("{url}/fail"))
+                .notify(&alert())
+                .await,
+            Err(NotifierError::Status { status: 400, .. })
+        ));
+    }
+}
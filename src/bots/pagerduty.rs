@@ -0,0 +1,135 @@
+//! PagerDuty Events API v2 channel. Alerts trigger incidents deduplicated by the alert
+//! key, and resolved alerts resolve the incident with the same key.
+
+use async_trait::async_trait;
+use serde_json::{json, Map, Value};
+
+use super::notifier::{post_json, Alert, Notifier, NotifierError, Severity};
+use crate::graphql::endpoint::Secret;
+
+pub const PAGERDUTY_EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+#[derive(Debug, Clone)]
+pub struct PagerDutyNotifier {
+    name: String,
+    routing_key: Secret,
+    events_url: String,
+    client: reqwest::Client,
+}
+
+impl PagerDutyNotifier {
+    pub fn new(name: impl Into<String>, routing_key: impl Into<String>) -> Self {
+        PagerDutyNotifier {
+            name: name.into(),
+            routing_key: Secret::new(routing_key),
+            events_url: PAGERDUTY_EVENTS_URL.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Send events to another Events API endpoint, such as a local stub
+    pub fn with_events_url(mut self, events_url: impl Into<String>) -> Self {
+        self.events_url = events_url.into();
+        self
+    }
+
+    /// Events API v2 event for the alert
+    pub fn event(&self, alert: &Alert) -> Value {
+        if alert.resolved {
+            return json!({
+                "routing_key": self.routing_key.expose(),
+                "event_action": "resolve",
+                "dedup_key": alert.key(),
+            });
+        }
+        let severity = match alert.severity {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        };
+        let details: Map<String, Value> = alert
+            .fields
+            .iter()
+            .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+            .collect();
+        let links: Vec<Value> = alert
+            .links
+            .iter()
+            .map(|link| json!({"href": link.url, "text": link.label}))
+            .collect();
+        json!({
+            "routing_key": self.routing_key.expose(),
+            "event_action": "trigger",
+            "dedup_key": alert.key(),
+            "payload": {
+                "summary": alert.title,
+                "source": alert.radio,
+                "severity": severity,
+                "custom_details": details,
+            },
+            "links": links,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for PagerDutyNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, alert: &Alert) -> Result<(), NotifierError> {
+        post_json(&self.client, &self.events_url, &self.event(alert)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{http::StatusCode, routing::post, Json, Router};
+
+    use super::*;
+    use crate::graphql::http_client::tests::spawn_stub;
+
+    #[tokio::test]
+    async fn test_trigger_and_resolve() {
+        let events: Arc<Mutex<Vec<Value>>> = Arc::default();
+        let recorded = events.clone();
+        let app = Router::new().route(
+            "/v2/enqueue",
+            post(move |Json(event): Json<Value>| async move {
+                recorded.lock().unwrap().push(event.clone());
+                (
+                    StatusCode::ACCEPTED,
+                    Json(json!({"status": "success", "dedup_key": event["dedup_key"]})),
+                )
+            }),
+        );
+        let url = spawn_stub(app).await;
+
+        let notifier = PagerDutyNotifier::new("on-call", "routing-key")
+            .with_events_url(format!("{url}/v2/enqueue"));
+        let alert = Alert::new(Severity::Critical, "poi-radio", "Divergent POI")
+            .with_key("poi/Qm1")
+            .with_field("deployment", "Qm1")
+            .with_link("Dashboard", "https://example.com/d");
+        notifier.notify(&alert).await.unwrap();
+        notifier.notify(&alert.resolve()).await.unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events[0]["event_action"], "trigger");
+        assert_eq!(events[0]["dedup_key"], "poi/Qm1");
+        assert_eq!(events[0]["payload"]["severity"], "critical");
+        assert_eq!(events[0]["payload"]["custom_details"]["deployment"], "Qm1");
+        assert_eq!(events[0]["links"][0]["href"], "https://example.com/d");
+        assert_eq!(
+            events[1],
+            json!({
+                "routing_key": "routing-key",
+                "event_action": "resolve",
+                "dedup_key": "poi/Qm1",
+            })
+        );
+    }
+}
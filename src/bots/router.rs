@@ -0,0 +1,463 @@
+//! Fan-out of alerts to the notification channels whose routing rules match, with
+//! channels and rules configured from TOML, such as
+//!
+//! ```toml
+//! [[channels]]
+//! name = "ops-slack"
+//! type = "slack"
+//! webhook_url = "https://hooks.slack.com/services/..."
+//! min_severity = "warning"
+//! radios = ["poi-radio"]
+//!
+//! [[channels]]
+//! name = "on-call"
+//! type = "telegram"
+//! bot_token = "..."
+//! chat_id = -1001234
+//! min_severity = "critical"
+//! ```
+//!
+//! Credentials also load from a file or an environment variable, as in
+//! `bot_token = { env = "TELEGRAM_TOKEN" }`.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{trace, warn};
+
+use super::notifier::{
+    Alert, DiscordNotifier, Notifier, NotifierError, Severity, SlackNotifier, TelegramNotifier,
+};
+use super::pagerduty::PagerDutyNotifier;
+use super::slack::SlackApiNotifier;
+use super::webhook::WebhookNotifier;
+use crate::graphql::endpoint::Secret;
+
+/// Which alerts a channel receives
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteRule {
+    /// Lowest severity delivered
+    #[serde(default)]
+    pub min_severity: Severity,
+    /// Radios whose alerts are delivered, every radio when empty
+    #[serde(default)]
+    pub radios: Vec<String>,
+}
+
+impl RouteRule {
+    pub fn matches(&self, alert: &Alert) -> bool {
+        alert.severity >= self.min_severity
+            && (self.radios.is_empty() || self.radios.contains(&alert.radio))
+    }
+}
+
+/// Notification channel and its credentials
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ChannelKind {
+    Discord {
+        webhook_url: Secret,
+    },
+    Slack {
+        webhook_url: Secret,
+    },
+    Telegram {
+        bot_token: Secret,
+        chat_id: i64,
+    },
+    /// Slack Web API with a bot token, threading follow-ups of an alert
+    #[serde(rename = "slack_api")]
+    SlackApi {
+        bot_token: Secret,
+        channel: String,
+        /// User id mentioned in the messages
+        #[serde(default)]
+        mention: Option<String>,
+    },
+    /// Generic JSON webhook, see `WebhookNotifier` for the template placeholders
+    Webhook {
+        url: Secret,
+        #[serde(default)]
+        template: Option<Value>,
+        /// HMAC-SHA256 key signing the payloads
+        #[serde(default)]
+        secret: Option<Secret>,
+        #[serde(default)]
+        signature_header: Option<String>,
+    },
+    Pagerduty {
+        routing_key: Secret,
+        #[serde(default)]
+        events_url: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ChannelKind,
+    #[serde(flatten)]
+    pub rule: RouteRule,
+}
+
+impl ChannelConfig {
+    pub fn notifier(&self) -> Arc<dyn Notifier> {
+        let name = self.name.clone();
+        match &self.kind {
+            ChannelKind::Discord { webhook_url } => {
+                Arc::new(DiscordNotifier::new(name, webhook_url.expose()))
+            }
+            ChannelKind::Slack { webhook_url } => {
+                Arc::new(SlackNotifier::new(name, webhook_url.expose()))
+            }
+            ChannelKind::Telegram { bot_token, chat_id } => {
+                Arc::new(TelegramNotifier::new(name, bot_token.expose(), *chat_id))
+            }
+            ChannelKind::SlackApi {
+                bot_token,
+                channel,
+                mention,
+            } => {
+                let notifier = SlackApiNotifier::new(name, bot_token.expose(), channel.clone());
+                match mention {
+                    Some(user_id) => Arc::new(notifier.with_mention(user_id.clone())),
+                    None => Arc::new(notifier),
+                }
+            }
+            ChannelKind::Webhook {
+                url,
+                template,
+                secret,
+                signature_header,
+            } => {
+                let notifier = WebhookNotifier::new(name, url.expose());
+                let notifier = match template {
+                    Some(template) => notifier.with_template(template.clone()),
+                    None => notifier,
+                };
+                let notifier = match secret {
+                    Some(secret) => notifier.with_secret(secret.expose()),
+                    None => notifier,
+                };
+                match signature_header {
+                    Some(header) => Arc::new(notifier.with_signature_header(header.clone())),
+                    None => Arc::new(notifier),
+                }
+            }
+            ChannelKind::Pagerduty {
+                routing_key,
+                events_url,
+            } => {
+                let notifier = PagerDutyNotifier::new(name, routing_key.expose());
+                match events_url {
+                    Some(url) => Arc::new(notifier.with_events_url(url.clone())),
+                    None => Arc::new(notifier),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotificationConfig {
+    #[serde(default)]
+    pub channels: Vec<ChannelConfig>,
+}
+
+impl NotificationConfig {
+    pub fn from_toml(toml: &str) -> Result<Self, NotifierError> {
+        toml::from_str(toml).map_err(|e| NotifierError::Config(e.to_string()))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, NotifierError> {
+        let toml = std::fs::read_to_string(path)
+            .map_err(|e| NotifierError::Config(format!("Could not read config file: {e}")))?;
+        Self::from_toml(&toml)
+    }
+}
+
+/// Outcome of delivering an alert, by channel name
+#[derive(Debug, Default)]
+pub struct NotificationReport {
+    pub delivered: Vec<String>,
+    pub failed: Vec<(String, NotifierError)>,
+    /// Channels skipped for exceeding their rate limit
+    pub throttled: Vec<String>,
+}
+
+impl NotificationReport {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Delivers alerts to every channel whose rule matches
+#[derive(Clone, Default)]
+pub struct NotificationRouter {
+    routes: Vec<(Arc<dyn Notifier>, RouteRule)>,
+}
+
+impl NotificationRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(config: &NotificationConfig) -> Self {
+        config
+            .channels
+            .iter()
+            .fold(NotificationRouter::new(), |router, channel| {
+                router.with_notifier(channel.notifier(), channel.rule.clone())
+            })
+    }
+
+    pub fn with_notifier(mut self, notifier: Arc<dyn Notifier>, rule: RouteRule) -> Self {
+        self.routes.push((notifier, rule));
+        self
+    }
+
+    /// Names of the channels an alert would be delivered to
+    pub fn channels_for(&self, alert: &Alert) -> Vec<&str> {
+        self.routes
+            .iter()
+            .filter(|(_, rule)| rule.matches(alert))
+            .map(|(notifier, _)| notifier.name())
+            .collect()
+    }
+
+    /// Channels an alert would be delivered to
+    pub fn notifiers_for(&self, alert: &Alert) -> Vec<Arc<dyn Notifier>> {
+        self.routes
+            .iter()
+            .filter(|(_, rule)| rule.matches(alert))
+            .map(|(notifier, _)| notifier.clone())
+            .collect()
+    }
+
+    /// Deliver the alert to the matching channels concurrently. Failures are logged and
+    /// reported per channel without affecting the other channels
+    pub async fn notify(&self, alert: &Alert) -> NotificationReport {
+        deliver(self.notifiers_for(alert), alert).await
+    }
+}
+
+/// Deliver an alert to each channel concurrently and report the outcome per channel
+pub(crate) async fn deliver(
+    notifiers: Vec<Arc<dyn Notifier>>,
+    alert: &Alert,
+) -> NotificationReport {
+    let deliveries: Vec<_> = notifiers
+        .into_iter()
+        .map(|notifier| {
+            let alert = alert.clone();
+            let channel = notifier.name().to_string();
+            (
+                channel,
+                tokio::spawn(async move { notifier.notify(&alert).await }),
+            )
+        })
+        .collect();
+
+    let mut report = NotificationReport::default();
+    for (channel, delivery) in deliveries {
+        let result = delivery
+            .await
+            .unwrap_or_else(|e| Err(NotifierError::Panicked(e.to_string())));
+        match result {
+            Ok(()) => {
+                trace!(channel, title = alert.title, "Delivered alert");
+                report.delivered.push(channel);
+            }
+            Err(e) => {
+                warn!(
+                    channel,
+                    err = tracing::field::debug(&e),
+                    "Could not deliver alert"
+                );
+                report.failed.push((channel, e));
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use super::*;
+
+    /// Channel recording the alerts it receives, or failing every delivery
+    pub(crate) struct RecordingNotifier {
+        name: String,
+        fail: bool,
+        pub alerts: Mutex<Vec<Alert>>,
+    }
+
+    impl RecordingNotifier {
+        pub(crate) fn new(name: &str, fail: bool) -> Arc<Self> {
+            Arc::new(RecordingNotifier {
+                name: name.to_string(),
+                fail,
+                alerts: Mutex::new(vec![]),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        async fn notify(&self, alert: &Alert) -> Result<(), NotifierError> {
+            if self.fail {
+                return Err(NotifierError::Config("unreachable".to_string()));
+            }
+            self.alerts.lock().unwrap().push(alert.clone());
+            Ok(())
+        }
+    }
+
+    /// Channel whose deliveries panic
+    struct PanickingNotifier;
+
+    #[async_trait]
+    impl Notifier for PanickingNotifier {
+        fn name(&self) -> &str {
+            "panicking"
+        }
+
+        async fn notify(&self, _: &Alert) -> Result<(), NotifierError> {
+            panic!("notifier bug")
+        }
+    }
+
+    #[test]
+    fn test_config_from_toml() {
+        let config = NotificationConfig::from_toml(
+            r#"
+            [[channels]]
+            name = "ops-slack"
+            type = "slack"
+            webhook_url = "https://hooks.slack.com/services/x"
+            min_severity = "warning"
+            radios = ["poi-radio"]
+
+            [[channels]]
+            name = "on-call"
+            type = "telegram"
+            bot_token = "token"
+            chat_id = -1001234
+
+            [[channels]]
+            name = "ops-threads"
+            type = "slack_api"
+            bot_token = "xoxb-token"
+            channel = "C123"
+            min_severity = "warning"
+
+            [[channels]]
+            name = "tooling"
+            type = "webhook"
+            url = "https://tooling.internal/alerts"
+            secret = "s3cret"
+            template = { summary = "{{title}}", details = "{{fields}}" }
+
+            [[channels]]
+            name = "pager"
+            type = "pagerduty"
+            routing_key = "key"
+            min_severity = "critical"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.channels[0].rule,
+            RouteRule {
+                min_severity: Severity::Warning,
+                radios: vec!["poi-radio".to_string()],
+            }
+        );
+        assert_eq!(
+            config.channels[1].kind,
+            ChannelKind::Telegram {
+                bot_token: Secret::new("token"),
+                chat_id: -1001234
+            }
+        );
+        let debug = format!("{:?}", config.channels);
+        for credential in [
+            "hooks.slack.com",
+            "xoxb-token",
+            "tooling.internal",
+            "s3cret",
+        ] {
+            assert!(!debug.contains(credential), "{credential} leaked");
+        }
+
+        std::env::set_var("GRAPHCAST_TEST_ROUTING_KEY", "from-env");
+        let from_env = NotificationConfig::from_toml(
+            "[[channels]]\nname = \"pager\"\ntype = \"pagerduty\"\nrouting_key = { env = \"GRAPHCAST_TEST_ROUTING_KEY\" }",
+        )
+        .unwrap();
+        assert!(matches!(
+            &from_env.channels[0].kind,
+            ChannelKind::Pagerduty { routing_key, .. } if routing_key.expose() == "from-env"
+        ));
+        assert_eq!(config.channels[1].rule, RouteRule::default());
+
+        let router = NotificationRouter::from_config(&config);
+        let alert = Alert::new(Severity::Info, "poi-radio", "Started");
+        assert_eq!(router.channels_for(&alert), vec!["on-call", "tooling"]);
+        assert!(matches!(
+            &config.channels[3].kind,
+            ChannelKind::Webhook { template: Some(template), .. } if template["details"] == "{{fields}}"
+        ));
+
+        assert!(
+            NotificationConfig::from_toml("[[channels]]\nname = \"x\"\ntype = \"fax\"").is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_router_fan_out() {
+        let ops = RecordingNotifier::new("ops", false);
+        let on_call = RecordingNotifier::new("on-call", false);
+        let broken = RecordingNotifier::new("broken", true);
+        let router = NotificationRouter::new()
+            .with_notifier(ops.clone(), RouteRule::default())
+            .with_notifier(
+                on_call.clone(),
+                RouteRule {
+                    min_severity: Severity::Critical,
+                    radios: vec![],
+                },
+            )
+            .with_notifier(broken, RouteRule::default())
+            .with_notifier(Arc::new(PanickingNotifier), RouteRule::default());
+
+        let report = router
+            .notify(&Alert::new(Severity::Warning, "poi-radio", "Behind"))
+            .await;
+        assert_eq!(report.delivered, vec!["ops"]);
+        assert_eq!(report.failed[0].0, "broken");
+        assert!(matches!(
+            &report.failed[1],
+            (channel, NotifierError::Panicked(_)) if channel == "panicking"
+        ));
+        assert!(!report.is_success());
+        assert!(on_call.alerts.lock().unwrap().is_empty());
+
+        router
+            .notify(&Alert::new(Severity::Critical, "poi-radio", "Failed"))
+            .await;
+        assert_eq!(ops.alerts.lock().unwrap().len(), 2);
+        assert_eq!(on_call.alerts.lock().unwrap()[0].title, "Failed");
+    }
+}
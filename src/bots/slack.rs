@@ -0,0 +1,380 @@
+//! Slack Web API channel posting alerts with a bot token. Alerts are rendered with
+//! `AlertMessageTemplateParams`, follow-ups of an active alert are threaded under its
+//! first message, and that message is updated once the alert resolves. Slash commands
+//! to the radio are served by `slack_command_router`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use axum::{
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use serde::Serialize;
+use serde_derive::Deserialize;
+use slack_morphism::prelude::*;
+use slack_morphism::signature_verifier::SlackEventSignatureVerifier;
+use tracing::warn;
+
+use super::commands::{BotCommand, CommandError, CommandHandler};
+use super::notifier::{Alert, Notifier, NotifierError};
+use super::AlertMessageTemplateParams;
+use crate::graphql::endpoint::Secret;
+
+pub const SLACK_API_URL: &str = "https://slack.com/api";
+
+/// Path slash commands are posted to by `slack_command_router`
+pub const SLACK_COMMANDS_PATH: &str = "/slack/commands";
+
+/// Oldest request timestamp accepted, against replayed commands
+const MAX_REQUEST_AGE_SECS: u64 = 5 * 60;
+
+/// Fields of Slack Web API responses used by the notifier
+#[derive(Debug, Deserialize)]
+struct SlackApiResponse {
+    ok: bool,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    ts: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SlackApiNotifier {
+    name: String,
+    bot_token: Secret,
+    channel: SlackChannelId,
+    mention: Option<SlackUserId>,
+    api_url: String,
+    client: reqwest::Client,
+    /// Timestamp of the first message of each active alert, by alert key
+    threads: Arc<Mutex<HashMap<String, SlackTs>>>,
+}
+
+impl SlackApiNotifier {
+    pub fn new(
+        name: impl Into<String>,
+        bot_token: impl Into<String>,
+        channel: impl Into<String>,
+    ) -> Self {
+        SlackApiNotifier {
+            name: name.into(),
+            bot_token: Secret::new(bot_token),
+            channel: SlackChannelId::new(channel.into()),
+            mention: None,
+            api_url: SLACK_API_URL.to_string(),
+            client: reqwest::Client::new(),
+            threads: Arc::default(),
+        }
+    }
+
+    /// Mention the user in the messages
+    pub fn with_mention(mut self, user_id: impl Into<String>) -> Self {
+        self.mention = Some(SlackUserId::new(user_id.into()));
+        self
+    }
+
+    /// Call another Web API endpoint, such as a local stub
+    pub fn with_api_url(mut self, api_url: impl Into<String>) -> Self {
+        self.api_url = api_url.into();
+        self
+    }
+
+    /// Timestamp of the message an active alert is threaded under
+    pub fn thread(&self, alert_key: &str) -> Option<SlackTs> {
+        self.threads.lock().unwrap().get(alert_key).cloned()
+    }
+
+    fn content(&self, alert: &Alert) -> SlackMessageContent {
+        let params = AlertMessageTemplateParams::from_alert(alert);
+        let params = match &self.mention {
+            Some(user_id) => params.with_user_id(user_id.clone()),
+            None => params,
+        };
+        params.render_template()
+    }
+
+    /// Call a Web API method and fail on Slack errors. The bot token is only sent in
+    /// the authorization header, and left out of errors
+    async fn call<T: Serialize>(
+        &self,
+        method: &str,
+        request: &T,
+    ) -> Result<SlackApiResponse, NotifierError> {
+        let response = self
+            .client
+            .post(format!("{}/{}", self.api_url, method))
+            .bearer_auth(self.bot_token.expose())
+            .json(request)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(NotifierError::Status {
+                status: status.as_u16(),
+                body: response.text().await.unwrap_or_default(),
+            });
+        }
+        let response: SlackApiResponse = response.json().await?;
+        match response.ok {
+            true => Ok(response),
+            false => Err(NotifierError::Slack(
+                response
+                    .error
+                    .unwrap_or_else(|| String::from("unknown error")),
+            )),
+        }
+    }
+
+    async fn post_message(
+        &self,
+        alert: &Alert,
+        thread_ts: Option<SlackTs>,
+    ) -> Result<Option<SlackTs>, NotifierError> {
+        let request =
+            SlackApiChatPostMessageRequest::new(self.channel.clone(), self.content(alert))
+                .opt_thread_ts(thread_ts);
+        let response = self.call("chat.postMessage", &request).await?;
+        Ok(response.ts.map(SlackTs::new))
+    }
+}
+
+#[async_trait]
+impl Notifier for SlackApiNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, alert: &Alert) -> Result<(), NotifierError> {
+        let key = alert.key();
+        let thread = self.thread(&key);
+        match (alert.resolved, thread) {
+            (true, Some(ts)) => {
+                let request =
+                    SlackApiChatUpdateRequest::new(self.channel.clone(), self.content(alert), ts);
+                self.call("chat.update", &request).await?;
+                self.threads.lock().unwrap().remove(&key);
+            }
+            (true, None) => {
+                self.post_message(alert, None).await?;
+            }
+            (false, Some(ts)) => {
+                self.post_message(alert, Some(ts)).await?;
+            }
+            (false, None) => {
+                if let Some(ts) = self.post_message(alert, None).await? {
+                    self.threads.lock().unwrap().insert(key, ts);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Router serving the radio's slash commands at `SLACK_COMMANDS_PATH`, to be merged
+/// into the radio's HTTP server. Requests are verified with the app's signing secret.
+/// Both a slash command per radio command, such as `/status`, and a single command
+/// taking the radio command as its text, such as `/radio status`, are answered
+pub fn slack_command_router(handler: CommandHandler, signing_secret: impl Into<String>) -> Router {
+    let verifier = Arc::new(SlackEventSignatureVerifier::new(&SlackSigningSecret::new(
+        signing_secret.into(),
+    )));
+    Router::new().route(
+        SLACK_COMMANDS_PATH,
+        post(move |headers: HeaderMap, body: String| {
+            let handler = handler.clone();
+            let verifier = verifier.clone();
+            async move {
+                if let Err(e) = verify_request(&verifier, &headers, &body) {
+                    warn!(err = e, "Rejected Slack command request");
+                    return Err(StatusCode::UNAUTHORIZED);
+                }
+                let event: SlackCommandEvent =
+                    serde_urlencoded::from_str(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+                let text = event.text.unwrap_or_default();
+                let line = match BotCommand::parse(&event.command.0) {
+                    Some(Err(CommandError::Unknown(_))) => format!("/{text}"),
+                    _ => format!("{} {text}", event.command.0),
+                };
+                let reply = handler
+                    .handle(&event.channel_id.0, &event.user_id.0, &line)
+                    .await
+                    .unwrap_or_else(|| String::from("Not a radio command"));
+                Ok(Json(
+                    SlackCommandEventResponse::new(SlackMessageContent::new().with_text(reply))
+                        .with_response_type(SlackMessageResponseType::Ephemeral),
+                ))
+            }
+        }),
+    )
+}
+
+fn verify_request(
+    verifier: &SlackEventSignatureVerifier,
+    headers: &HeaderMap,
+    body: &str,
+) -> Result<(), String> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| format!("Missing header {name}"))
+    };
+    let timestamp = header(SlackEventSignatureVerifier::SLACK_SIGNED_TIMESTAMP)?;
+    let signature = header(SlackEventSignatureVerifier::SLACK_SIGNED_HASH_HEADER)?;
+    let sent: u64 = timestamp
+        .parse()
+        .map_err(|_| String::from("Invalid request timestamp"))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    if now.abs_diff(sent) > MAX_REQUEST_AGE_SECS {
+        return Err(String::from("Request timestamp too old"));
+    }
+    verifier
+        .verify(signature, body, timestamp)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Path, http::HeaderMap, routing::post, Json, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::bots::notifier::Severity;
+    use crate::graphql::http_client::tests::spawn_stub;
+
+    #[tokio::test]
+    async fn test_threads_and_resolves() {
+        let calls: Arc<Mutex<Vec<(String, Value)>>> = Arc::default();
+        let recorded = calls.clone();
+        let app =
+            Router::new().route(
+                "/api/:method",
+                post(
+                    move |Path(method): Path<String>,
+                          headers: HeaderMap,
+                          Json(body): Json<Value>| async move {
+                        if headers["authorization"] != "Bearer xoxb-token" {
+                            return Json(json!({"ok": false, "error": "invalid_auth"}));
+                        }
+                        let mut calls = recorded.lock().unwrap();
+                        calls.push((method, body));
+                        Json(json!({"ok": true, "ts": format!("1700000000.00000{}", calls.len())}))
+                    },
+                ),
+            );
+        let url = spawn_stub(app).await;
+
+        let notifier = SlackApiNotifier::new("ops", "xoxb-token", "C123")
+            .with_mention("U42")
+            .with_api_url(format!("{url}/api"));
+        let alert = Alert::new(Severity::Critical, "poi-radio", "Divergent POI")
+            .with_key("poi/Qm1")
+            .with_field("deployment", "Qm1");
+        notifier.notify(&alert).await.unwrap();
+        notifier
+            .notify(&alert.clone().with_field("Repeats", "3 more"))
+            .await
+            .unwrap();
+        notifier.notify(&alert.clone().resolve()).await.unwrap();
+        assert!(notifier.thread("poi/Qm1").is_none());
+
+        let calls = calls.lock().unwrap().clone();
+        assert_eq!(calls[0].0, "chat.postMessage");
+        assert_eq!(calls[0].1["channel"], "C123");
+        assert!(calls[0].1.get("thread_ts").is_none());
+        assert_eq!(
+            calls[0].1["blocks"][0]["text"]["text"],
+            "🚨 Hello <@U42>!, Notification from Radio 'poi-radio'"
+        );
+        assert_eq!(
+            calls[0].1["blocks"][2]["text"]["text"],
+            "[critical] Divergent POI\ndeployment: Qm1"
+        );
+        assert_eq!(calls[1].0, "chat.postMessage");
+        assert_eq!(calls[1].1["thread_ts"], "1700000000.000001");
+        assert_eq!(calls[2].0, "chat.update");
+        assert_eq!(calls[2].1["ts"], "1700000000.000001");
+        assert!(calls[2].1["blocks"][0]["text"]["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("✅"));
+
+        let unauthorized =
+            SlackApiNotifier::new("ops", "wrong", "C123").with_api_url(format!("{url}/api"));
+        assert!(matches!(
+            unauthorized.notify(&alert).await,
+            Err(NotifierError::Slack(e)) if e == "invalid_auth"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_slack_commands() {
+        use crate::bots::commands::{tests::TestRadio, CommandAllowlist};
+
+        let handler = CommandHandler::new(
+            Arc::new(TestRadio::default()),
+            CommandAllowlist {
+                chat_ids: vec![],
+                user_ids: vec![String::from("U42")],
+            },
+        );
+        let url = spawn_stub(slack_command_router(handler, "signing-secret")).await;
+
+        let client = reqwest::Client::new();
+        let send = |command: &str, text: &str, secret: &str| {
+            let body = serde_urlencoded::to_string([
+                ("team_id", "T1"),
+                ("channel_id", "C1"),
+                ("user_id", "U42"),
+                ("command", command),
+                ("text", text),
+                ("response_url", "https://hooks.slack.com/commands/1"),
+                ("trigger_id", "1.2"),
+            ])
+            .unwrap();
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                .to_string();
+            let signature = format!(
+                "v0={}",
+                crate::bots::webhook::sign_payload(
+                    secret,
+                    format!("v0:{timestamp}:{body}").as_bytes()
+                )
+            );
+            client
+                .post(format!("{url}{SLACK_COMMANDS_PATH}"))
+                .header("x-slack-request-timestamp", timestamp)
+                .header("x-slack-signature", signature)
+                .body(body)
+                .send()
+        };
+
+        let reply: Value = send("/peers", "", "signing-secret")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(reply["text"], "2 peers:\n- 0xe9a1\n- 0x7f3c");
+        assert_eq!(reply["response_type"], "ephemeral");
+        let reply: Value = send("/radio", "mute 1h", "signing-secret")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(reply["text"], "Muted for 3600s");
+        let response = send("/peers", "", "wrong-secret").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
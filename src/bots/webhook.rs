@@ -0,0 +1,264 @@
+//! Generic JSON webhook channel for internal tooling. The payload is built from a JSON
+//! template with alert placeholders, and signed with HMAC-SHA256 so receivers can
+//! verify it came from the radio.
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use serde_json::{Map, Value};
+use sha2::Sha256;
+
+use super::notifier::{Alert, Notifier, NotifierError};
+use crate::graphql::endpoint::Secret;
+
+pub const DEFAULT_SIGNATURE_HEADER: &str = "X-Graphcast-Signature";
+
+/// Webhook posting alerts rendered from a JSON template.
+///
+/// String values of the template can hold the placeholders `{{title}}`, `{{radio}}`,
+/// `{{severity}}`, `{{status}}`, `{{key}}` and `{{text}}`. A string that is exactly
+/// `{{fields}}` becomes an object of the alert fields, `{{links}}` an array of links and
+/// `{{resolved}}` a boolean. Without a template the alert is posted as serialized
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    name: String,
+    url: Secret,
+    template: Option<Value>,
+    secret: Option<Secret>,
+    signature_header: String,
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(name: impl Into<String>, url: impl Into<String>) -> Self {
+        WebhookNotifier {
+            name: name.into(),
+            url: Secret::new(url),
+            template: None,
+            secret: None,
+            signature_header: DEFAULT_SIGNATURE_HEADER.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_template(mut self, template: Value) -> Self {
+        self.template = Some(template);
+        self
+    }
+
+    /// Sign payloads with the secret, in the `sha256=<hex>` format of `signature_header`
+    pub fn with_secret(mut self, secret: impl Into<String>) -> Self {
+        self.secret = Some(Secret::new(secret));
+        self
+    }
+
+    pub fn with_signature_header(mut self, signature_header: impl Into<String>) -> Self {
+        self.signature_header = signature_header.into();
+        self
+    }
+
+    pub fn payload(&self, alert: &Alert) -> Value {
+        match &self.template {
+            Some(template) => render_template(template, alert),
+            None => serde_json::to_value(alert).unwrap_or_default(),
+        }
+    }
+}
+
+/// Hex encoded HMAC-SHA256 of the payload
+pub fn sign_payload(secret: &str, payload: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(payload);
+    ethers::utils::hex::encode(mac.finalize().into_bytes())
+}
+
+fn render_template(template: &Value, alert: &Alert) -> Value {
+    match template {
+        Value::String(s) => match s.as_str() {
+            "{{fields}}" => Value::Object(
+                alert
+                    .fields
+                    .iter()
+                    .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+                    .collect::<Map<_, _>>(),
+            ),
+            "{{links}}" => serde_json::to_value(&alert.links).unwrap_or_default(),
+            "{{resolved}}" => Value::Bool(alert.resolved),
+            _ => Value::String(render_placeholders(s, alert)),
+        },
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| render_template(value, alert))
+                .collect(),
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(name, value)| (name.clone(), render_template(value, alert)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// Substitute the `{{name}}` placeholders of a string in a single pass, so placeholders
+/// within alert content are never expanded. Unknown placeholders are kept as they are
+fn render_placeholders(text: &str, alert: &Alert) -> String {
+    let mut rendered = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after
+            .find("}}")
+            .and_then(|end| Some((placeholder(&after[..end], alert)?, end)));
+        match value {
+            Some((value, end)) => {
+                rendered.push_str(&value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                rendered.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+fn placeholder(name: &str, alert: &Alert) -> Option<String> {
+    let value = match name {
+        "title" => alert.title.clone(),
+        "radio" => alert.radio.clone(),
+        "severity" => alert.severity.to_string(),
+        "status" => String::from(if alert.resolved {
+            "resolved"
+        } else {
+            "triggered"
+        }),
+        "key" => alert.key(),
+        "text" => alert.text(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, alert: &Alert) -> Result<(), NotifierError> {
+        let body = serde_json::to_vec(&self.payload(alert))
+            .map_err(|e| NotifierError::Config(e.to_string()))?;
+        let mut request = self
+            .client
+            .post(self.url.expose())
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(secret) = &self.secret {
+            request = request.header(
+                self.signature_header.as_str(),
+                format!("sha256={}", sign_payload(secret.expose(), &body)),
+            );
+        }
+        let response = request
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.without_url())?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(NotifierError::Status {
+                status: status.as_u16(),
+                body: response.text().await.unwrap_or_default(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{body::Bytes, http::HeaderMap, routing::post, Router};
+    use serde_json::json;
+
+    use super::*;
+    use crate::bots::notifier::Severity;
+    use crate::graphql::http_client::tests::spawn_stub;
+
+    #[tokio::test]
+    async fn test_signed_webhook() {
+        let received: Arc<Mutex<Vec<(HeaderMap, Bytes)>>> = Arc::default();
+        let recorded = received.clone();
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: Bytes| async move {
+                recorded.lock().unwrap().push((headers, body));
+            }),
+        );
+        let url = spawn_stub(app).await;
+
+        let alert = Alert::new(Severity::Critical, "poi-radio", "Divergent POI")
+            .with_key("poi/Qm1")
+            .with_field("deployment", "Qm1");
+        let notifier = WebhookNotifier::new("tooling", format!("{url}/hook"))
+            .with_secret("s3cret")
+            .with_template(json!({
+                "event": "{{status}}",
+                "summary": "[{{severity}}] {{title}} from {{radio}}",
+                "dedup": "{{key}}",
+                "details": "{{fields}}",
+                "resolved": "{{resolved}}",
+                "tags": ["graphcast", "{{radio}}"],
+                "version": 1
+            }));
+        notifier.notify(&alert).await.unwrap();
+        notifier.notify(&alert.clone().resolve()).await.unwrap();
+
+        let received = received.lock().unwrap();
+        let (headers, body) = &received[0];
+        assert_eq!(
+            headers[DEFAULT_SIGNATURE_HEADER],
+            format!("sha256={}", sign_payload("s3cret", body))
+        );
+        let payload: Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(
+            payload,
+            json!({
+                "event": "triggered",
+                "summary": "[critical] Divergent POI from poi-radio",
+                "dedup": "poi/Qm1",
+                "details": {"deployment": "Qm1"},
+                "resolved": false,
+                "tags": ["graphcast", "poi-radio"],
+                "version": 1
+            })
+        );
+        let payload: Value = serde_json::from_slice(&received[1].1).unwrap();
+        assert_eq!(payload["event"], "resolved");
+    }
+
+    #[test]
+    fn test_placeholders_in_alert_content() {
+        let alert = Alert::new(Severity::Warning, "poi-radio", "Injected {{radio}} {{key}")
+            .with_key("poi/Qm1");
+        let template = json!({"summary": "{{title}} from {{radio}} {{unknown}}"});
+        assert_eq!(
+            render_template(&template, &alert),
+            json!({"summary": "Injected {{radio}} {{key} from poi-radio {{unknown}}"})
+        );
+    }
+
+    #[test]
+    fn test_sign_payload() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign_payload("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
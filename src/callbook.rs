@@ -1,22 +1,98 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
 use derive_getters::Getters;
 use serde_derive::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, trace, warn};
 
+use crate::block_scheduler::{BlockScheduler, BlockSchedulerConfig, BlockSchedulerEvent};
+use crate::data_source::{GraphcastDataSource, SharedDataSource};
+use crate::graphcast_agent::identity_cache::IdentityCache;
+use crate::graphcast_agent::registry_snapshot::RegistrySnapshotStore;
+use crate::graphql::client_epoch::{query_current_epoch, query_epoch_blocks, ProtocolEpoch};
+use crate::graphql::client_graph_account::{query_graph_account, subgraph_hash_by_id};
 use crate::graphql::client_graph_node::indexing_statuses::IndexingStatusesIndexingStatuses;
 use crate::graphql::client_graph_node::{
-    get_indexing_statuses, query_graph_node_network_block_hash,
+    get_indexing_statuses, merge_indexing_statuses, query_entity_changes_in_block,
+    query_graph_node_network_block_hash, query_proof_of_indexing, query_public_pois,
+    query_subgraph_features, update_network_chainheads, EntityChanges, PublicPoi, PublicPoiRequest,
+    SubgraphFeatures,
+};
+use crate::graphql::client_network::{
+    query_network_subgraph, resolve_stake, AccountResolution, Network, NetworkLayer,
+    NetworkResolution, NetworkSubgraph, StakeResolution,
+};
+use crate::graphql::client_prometheus::{
+    query_prometheus_instant, query_prometheus_range, InstantSample, RangeSeries,
 };
-use crate::graphql::client_network::{query_network_subgraph, Network};
 use crate::graphql::client_registry::query_registry;
+use crate::graphql::client_registry_contract::{RegistryContractConfig, RegistryContractReader};
+use crate::graphql::client_rpc::{
+    rpc_block_hash, rpc_chainhead, same_block_hash, BlockSource, RpcConfig,
+};
+use crate::graphql::deployment_status::{
+    DeploymentStatus, DeploymentStatusEvent, DeploymentWatcher, DeploymentWatcherConfig,
+};
+use crate::graphql::endpoint::{Endpoint, EndpointAuth};
+use crate::graphql::endpoint_health::{EndpointHealth, EndpointHealthTracker};
+use crate::graphql::http_client::{QueryClient, QueryClientConfig};
 use crate::graphql::QueryError;
+use crate::networks::NetworkName;
+use crate::{Account, BlockPointer, ClockTick, EpochPointer};
 
-#[derive(Clone, Debug, Getters, Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Getters, Serialize, Deserialize)]
 pub struct CallBook {
     /// A constant defining Graphcast registry subgraph endpoint
-    graphcast_registry: String,
+    graphcast_registry: Endpoint,
+    /// Registry contract read over JSON-RPC in place of the registry subgraph
+    #[serde(default)]
+    registry_contract: Option<RegistryContractReader>,
     /// A constant defining The Graph network subgraph endpoint
-    graph_network: String,
+    graph_network: Endpoint,
+    /// Network subgraphs of each protocol layer, resolving identities in place of
+    /// `graph_network` when configured
+    #[serde(default)]
+    network_subgraphs: Vec<NetworkSubgraph>,
+    /// How identities are resolved across `network_subgraphs`
+    #[serde(default)]
+    network_resolution: NetworkResolution,
     /// A constant defining the graph node endpoint
-    graph_node_status: String,
+    graph_node_status: Endpoint,
+    /// Graph node status endpoints of other index or query nodes, merged with and
+    /// failed over to from `graph_node_status`
+    #[serde(default)]
+    additional_graph_nodes: Vec<Endpoint>,
+    /// Health of each graph node status endpoint, shared by every clone of the callbook
+    #[serde(skip)]
+    #[getter(skip)]
+    graph_node_health: EndpointHealthTracker,
+    /// Epoch block oracle subgraph mapping protocol epochs to the blocks of each network
+    #[serde(default)]
+    epoch_block_oracle: Option<Endpoint>,
+    /// Prometheus server exposing local indexer metrics
+    #[serde(default)]
+    prometheus: Option<Endpoint>,
+    /// JSON-RPC providers for block hashes and chainheads
+    #[serde(default)]
+    rpc: RpcConfig,
+    /// HTTP client shared by all queries made through the callbook
+    #[serde(default)]
+    query_client: QueryClient,
+    /// Sender identity verification results, shared by every clone of the callbook
+    #[serde(skip)]
+    identity_cache: IdentityCache,
+    /// Data source answering lookups in place of the HTTP endpoints
+    #[serde(skip)]
+    #[getter(skip)]
+    data_source: Option<SharedDataSource>,
+    /// Local index of registrations and stakes answering identity lookups while fresh
+    #[serde(skip)]
+    #[getter(skip)]
+    registry_snapshot: Option<RegistrySnapshotStore>,
 }
 
 impl CallBook {
@@ -26,26 +102,864 @@ impl CallBook {
         graph_node_status: Option<String>,
     ) -> CallBook {
         CallBook {
-            graphcast_registry,
-            graph_network,
-            graph_node_status: graph_node_status.unwrap_or("none".to_string()),
+            graphcast_registry: Endpoint::new(graphcast_registry),
+            graph_network: Endpoint::new(graph_network),
+            graph_node_status: Endpoint::new(graph_node_status.unwrap_or("none".to_string())),
+            registry_contract: None,
+            network_subgraphs: vec![],
+            network_resolution: NetworkResolution::default(),
+            additional_graph_nodes: vec![],
+            graph_node_health: EndpointHealthTracker::default(),
+            epoch_block_oracle: None,
+            prometheus: None,
+            rpc: RpcConfig::default(),
+            query_client: QueryClient::default(),
+            identity_cache: IdentityCache::default(),
+            data_source: None,
+            registry_snapshot: None,
         }
     }
+
+    /// Authenticate requests to the Graphcast registry subgraph
+    pub fn with_graphcast_registry_auth(mut self, auth: EndpointAuth) -> CallBook {
+        self.graphcast_registry.auth = auth;
+        self
+    }
+
+    /// Authenticate requests to the network subgraph
+    pub fn with_graph_network_auth(mut self, auth: EndpointAuth) -> CallBook {
+        self.graph_network.auth = auth;
+        self
+    }
+
+    /// Authenticate requests to the graph node status endpoint
+    pub fn with_graph_node_auth(mut self, auth: EndpointAuth) -> CallBook {
+        self.graph_node_status.auth = auth;
+        self
+    }
+
+    /// Resolve Graphcast IDs from the registry contract instead of the registry subgraph
+    pub fn with_registry_contract(mut self, registry_contract: RegistryContractConfig) -> CallBook {
+        self.registry_contract = Some(RegistryContractReader::new(registry_contract));
+        self
+    }
+
+    /// Add a network subgraph to resolve identities across protocol layers
+    pub fn with_network_subgraph(mut self, network_subgraph: NetworkSubgraph) -> CallBook {
+        self.network_subgraphs.push(network_subgraph);
+        self
+    }
+
+    /// How identities and stakes are resolved across the network subgraphs
+    pub fn with_network_resolution(mut self, network_resolution: NetworkResolution) -> CallBook {
+        self.network_resolution = network_resolution;
+        self
+    }
+
+    /// Add a graph node status endpoint to merge indexing statuses from and fail over to
+    pub fn with_additional_graph_node(mut self, endpoint: Endpoint) -> CallBook {
+        self.additional_graph_nodes.push(endpoint);
+        self
+    }
+
+    /// Query the blocks each network's epochs started at from the epoch block oracle subgraph
+    pub fn with_epoch_block_oracle(mut self, endpoint: Endpoint) -> CallBook {
+        self.epoch_block_oracle = Some(endpoint);
+        self
+    }
+
+    /// Query local metrics from the Prometheus server at the endpoint
+    pub fn with_prometheus(mut self, endpoint: Endpoint) -> CallBook {
+        self.prometheus = Some(endpoint);
+        self
+    }
+
+    /// Query block hashes and chainheads from JSON-RPC providers as set by the config
+    pub fn with_rpc_config(mut self, rpc: RpcConfig) -> CallBook {
+        self.rpc = rpc;
+        self
+    }
+
+    /// Replace the query client with one built from the given timeouts and retry policy
+    pub fn with_query_client_config(mut self, config: QueryClientConfig) -> CallBook {
+        self.query_client = QueryClient::new(config);
+        self
+    }
+
+    /// Use the given identity cache, typically to share it with other callbooks
+    pub fn with_identity_cache(mut self, identity_cache: IdentityCache) -> CallBook {
+        self.identity_cache = identity_cache;
+        self
+    }
+
+    /// Answer lookups from the given data source instead of querying the configured endpoints
+    pub fn with_data_source(mut self, data_source: SharedDataSource) -> CallBook {
+        self.data_source = Some(data_source);
+        self
+    }
+
+    /// Data source used in place of the HTTP endpoints, if any
+    pub fn data_source(&self) -> Option<&SharedDataSource> {
+        self.data_source.as_ref()
+    }
+
+    /// Answer registry and stake lookups from the snapshot while it is within its staleness
+    /// limit, falling back to live queries for entries it does not contain
+    pub fn with_registry_snapshot(mut self, registry_snapshot: RegistrySnapshotStore) -> CallBook {
+        self.registry_snapshot = Some(registry_snapshot);
+        self
+    }
+
+    pub fn registry_snapshot(&self) -> Option<&RegistrySnapshotStore> {
+        self.registry_snapshot.as_ref()
+    }
+
+    /// Hash of a block, from graph node and the network's JSON-RPC provider as set
+    /// by the configured `BlockSource`
     pub async fn block_hash(&self, network: &str, block_number: u64) -> Result<String, QueryError> {
-        query_graph_node_network_block_hash(&self.graph_node_status, network, block_number).await
+        if let Some(data_source) = &self.data_source {
+            return data_source.block_hash(network, block_number).await;
+        }
+        let graph_node = self.graph_node_block_hash(network, block_number);
+        let Some(rpc) = self.rpc.provider(network) else {
+            return graph_node.await;
+        };
+        let rpc = self.provider_block_hash(network, rpc, block_number);
+        match self.rpc.block_source {
+            BlockSource::GraphNode => graph_node.await,
+            BlockSource::RpcFallback => with_fallback(network, "graph node", graph_node, rpc).await,
+            BlockSource::RpcPrimary => with_fallback(network, "JSON-RPC", rpc, graph_node).await,
+            BlockSource::CrossCheck => {
+                let (graph_node, rpc) = tokio::join!(graph_node, rpc);
+                cross_check(network, block_number, graph_node, rpc)
+            }
+        }
+    }
+
+    /// Latest block of a network, from graph node and the network's JSON-RPC provider
+    /// as set by the configured `BlockSource`. Cross-checking verifies graph node's
+    /// chainhead hash against the provider's block at the same number
+    pub async fn chainhead(&self, network: &str) -> Result<BlockPointer, QueryError> {
+        let graph_node = self.graph_node_chainhead(network);
+        let Some(rpc) = self.rpc.provider(network) else {
+            return graph_node.await;
+        };
+        match self.rpc.block_source {
+            BlockSource::GraphNode => graph_node.await,
+            BlockSource::RpcFallback => {
+                let rpc = self.provider_chainhead(network, rpc);
+                with_fallback(network, "graph node", graph_node, rpc).await
+            }
+            BlockSource::RpcPrimary => {
+                let rpc = self.provider_chainhead(network, rpc);
+                with_fallback(network, "JSON-RPC", rpc, graph_node).await
+            }
+            BlockSource::CrossCheck => {
+                let head = match graph_node.await {
+                    Ok(head) => head,
+                    Err(e) => {
+                        warn!(
+                            network,
+                            err = tracing::field::debug(&e),
+                            "Could not cross-check chainhead, graph node failed"
+                        );
+                        return self.provider_chainhead(network, rpc).await;
+                    }
+                };
+                let rpc_hash = self.provider_block_hash(network, rpc, head.number).await;
+                cross_check(network, head.number, Ok(head.hash.clone()), rpc_hash)?;
+                Ok(head)
+            }
+        }
+    }
+
+    async fn provider_block_hash(
+        &self,
+        network: &str,
+        endpoint: &Endpoint,
+        block_number: u64,
+    ) -> Result<String, QueryError> {
+        let provider = self.rpc.json_rpc(&self.query_client, network)?;
+        rpc_block_hash(&provider, endpoint, block_number).await
+    }
+
+    async fn provider_chainhead(
+        &self,
+        network: &str,
+        endpoint: &Endpoint,
+    ) -> Result<BlockPointer, QueryError> {
+        let provider = self.rpc.json_rpc(&self.query_client, network)?;
+        rpc_chainhead(&provider, endpoint).await
+    }
+
+    async fn graph_node_block_hash(
+        &self,
+        network: &str,
+        block_number: u64,
+    ) -> Result<String, QueryError> {
+        self.with_graph_node_failover(|endpoint| {
+            query_graph_node_network_block_hash(&self.query_client, endpoint, network, block_number)
+        })
+        .await
+    }
+
+    async fn graph_node_chainhead(&self, network: &str) -> Result<BlockPointer, QueryError> {
+        update_network_chainheads(self.indexing_statuses().await?)
+            .remove(&NetworkName::from_string(network))
+            .ok_or(QueryError::ParseResponseError(format!(
+                "No {network} chainhead from graph node"
+            )))
+    }
+
+    /// Graph node status endpoints, the primary one first
+    pub fn graph_node_endpoints(&self) -> impl Iterator<Item = &Endpoint> {
+        std::iter::once(&self.graph_node_status)
+            .chain(&self.additional_graph_nodes)
+            .filter(|endpoint| endpoint.url != "none")
+    }
+
+    /// Health of the graph node status endpoints queried so far, by URL
+    pub fn graph_node_health(&self) -> HashMap<String, EndpointHealth> {
+        self.graph_node_health.all()
+    }
+
+    /// Run `query` against the graph node endpoints from the healthiest until one succeeds
+    async fn with_graph_node_failover<'a, T, F, Fut>(&'a self, query: F) -> Result<T, QueryError>
+    where
+        F: Fn(&'a Endpoint) -> Fut,
+        Fut: Future<Output = Result<T, QueryError>>,
+    {
+        let mut last_error = None;
+        for endpoint in self
+            .graph_node_health
+            .by_health(self.graph_node_endpoints())
+        {
+            match query(endpoint).await {
+                Ok(value) => {
+                    self.graph_node_health.record_success(endpoint);
+                    return Ok(value);
+                }
+                Err(e) => {
+                    warn!(
+                        endpoint = %endpoint,
+                        err = tracing::field::debug(&e),
+                        "Graph node query failed, try the next endpoint"
+                    );
+                    self.graph_node_health.record_failure(endpoint, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or(QueryError::Other(anyhow::anyhow!(
+            "No graph node endpoint configured"
+        ))))
     }
 
     pub async fn registered_indexer(&self, wallet_address: &str) -> Result<String, QueryError> {
-        query_registry(&self.graphcast_registry, wallet_address).await
+        let from_snapshot = self.registry_snapshot.as_ref().and_then(|store| {
+            store
+                .fresh(|snapshot| {
+                    snapshot
+                        .registered_indexer(wallet_address)
+                        .map(String::from)
+                })
+                .flatten()
+        });
+        if let Some(indexer) = from_snapshot {
+            return Ok(indexer);
+        }
+        if let Some(data_source) = &self.data_source {
+            return data_source.registered_indexer(wallet_address).await;
+        }
+        match &self.registry_contract {
+            Some(registry_contract) => {
+                registry_contract
+                    .registered_indexer(&self.query_client, wallet_address)
+                    .await
+            }
+            None => {
+                query_registry(&self.query_client, &self.graphcast_registry, wallet_address).await
+            }
+        }
     }
 
+    /// Indexing statuses merged across the graph node endpoints that answered
     pub async fn indexing_statuses(
         &self,
     ) -> Result<Vec<IndexingStatusesIndexingStatuses>, QueryError> {
-        get_indexing_statuses(&self.graph_node_status).await
+        if let Some(data_source) = &self.data_source {
+            return data_source.indexing_statuses().await;
+        }
+        let mut statuses = vec![];
+        let mut last_error = None;
+        for endpoint in self.graph_node_endpoints() {
+            match get_indexing_statuses(&self.query_client, endpoint).await {
+                Ok(s) => {
+                    self.graph_node_health.record_success(endpoint);
+                    statuses.push(s);
+                }
+                Err(e) => {
+                    warn!(
+                        endpoint = %endpoint,
+                        err = tracing::field::debug(&e),
+                        "Could not get indexing statuses from graph node"
+                    );
+                    self.graph_node_health.record_failure(endpoint, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) if statuses.is_empty() => Err(e),
+            None if statuses.is_empty() => Err(QueryError::Other(anyhow::anyhow!(
+                "No graph node endpoint configured"
+            ))),
+            _ => Ok(merge_indexing_statuses(statuses)),
+        }
+    }
+
+    /// Proof of indexing of a deployment at a block, for `indexer` if given
+    pub async fn proof_of_indexing(
+        &self,
+        deployment: &str,
+        block: &BlockPointer,
+        indexer: Option<&str>,
+    ) -> Result<String, QueryError> {
+        self.with_graph_node_failover(|endpoint| {
+            query_proof_of_indexing(&self.query_client, endpoint, deployment, block, indexer)
+        })
+        .await
+    }
+
+    /// Public proofs of indexing for several deployments and blocks
+    pub async fn public_pois(
+        &self,
+        requests: &[PublicPoiRequest],
+    ) -> Result<Vec<PublicPoi>, QueryError> {
+        self.with_graph_node_failover(|endpoint| {
+            query_public_pois(&self.query_client, endpoint, requests)
+        })
+        .await
+    }
+
+    pub async fn entity_changes_in_block(
+        &self,
+        deployment: &str,
+        block_number: u64,
+    ) -> Result<EntityChanges, QueryError> {
+        self.with_graph_node_failover(|endpoint| {
+            query_entity_changes_in_block(&self.query_client, endpoint, deployment, block_number)
+        })
+        .await
+    }
+
+    pub async fn subgraph_features(
+        &self,
+        deployment: &str,
+    ) -> Result<SubgraphFeatures, QueryError> {
+        self.with_graph_node_failover(|endpoint| {
+            query_subgraph_features(&self.query_client, endpoint, deployment)
+        })
+        .await
+    }
+
+    /// Indexing statuses of the deployments on the configured graph node
+    pub async fn deployment_statuses(&self) -> Result<Vec<DeploymentStatus>, QueryError> {
+        Ok(self
+            .indexing_statuses()
+            .await?
+            .into_iter()
+            .map(DeploymentStatus::from)
+            .collect())
+    }
+
+    /// Poll deployment statuses every `poll_interval` in a background task and send the
+    /// resulting events. The task stops once the receiver is dropped; failed polls are
+    /// logged and retried on the next tick
+    pub fn watch_deployments(
+        &self,
+        config: DeploymentWatcherConfig,
+    ) -> mpsc::UnboundedReceiver<DeploymentStatusEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let callbook = self.clone();
+        tokio::spawn(async move {
+            let mut watcher = DeploymentWatcher::new(config.max_blocks_behind);
+            let mut interval = tokio::time::interval(config.poll_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = sender.closed() => return,
+                }
+                let statuses = match callbook.deployment_statuses().await {
+                    Ok(statuses) => statuses,
+                    Err(e) => {
+                        warn!(
+                            err = tracing::field::debug(&e),
+                            "Could not poll deployment statuses"
+                        );
+                        continue;
+                    }
+                };
+                for event in watcher.observe(statuses) {
+                    trace!(
+                        event = tracing::field::debug(&event),
+                        "Deployment status event"
+                    );
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        receiver
+    }
+
+    /// Poll chainheads every `poll_interval` in a background task and send an event each
+    /// time a network crosses its examination interval, with the message block's hash.
+    /// Intervals whose block hash cannot be resolved are retried on the next poll. The
+    /// task stops once the receiver is dropped
+    pub fn schedule_message_blocks(
+        &self,
+        config: BlockSchedulerConfig,
+    ) -> mpsc::UnboundedReceiver<BlockSchedulerEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let callbook = self.clone();
+        tokio::spawn(async move {
+            let mut scheduler = BlockScheduler::new(config.networks);
+            let mut interval = tokio::time::interval(config.poll_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = sender.closed() => return,
+                }
+                let chainheads = match callbook.indexing_statuses().await {
+                    Ok(statuses) => update_network_chainheads(statuses),
+                    Err(e) => {
+                        warn!(err = tracing::field::debug(&e), "Could not poll chainheads");
+                        continue;
+                    }
+                };
+                let before = scheduler.clone();
+                for (network, tick) in scheduler.observe(&chainheads) {
+                    let event = match tick {
+                        ClockTick::Unchanged => continue,
+                        ClockTick::Regressed { previous, .. } => {
+                            BlockSchedulerEvent::ChainheadRegressed {
+                                chainhead: chainheads[&network].number,
+                                network,
+                                previous,
+                            }
+                        }
+                        ClockTick::Crossed {
+                            message_block,
+                            missed,
+                        } => match callbook.block_hash(network.as_str(), message_block).await {
+                            Ok(hash) => BlockSchedulerEvent::MessageBlock {
+                                block: BlockPointer::new(message_block, hash),
+                                network,
+                                missed,
+                            },
+                            Err(e) => {
+                                warn!(
+                                    err = tracing::field::debug(&e),
+                                    network = %network,
+                                    message_block,
+                                    "Could not resolve message block hash, retry on next poll"
+                                );
+                                let compare_block = before
+                                    .clock(&network)
+                                    .map(|clock| clock.compare_block)
+                                    .unwrap_or_default();
+                                scheduler.rewind(&network, compare_block);
+                                continue;
+                            }
+                        },
+                    };
+                    trace!(event = %event, "Block scheduler event");
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        receiver
     }
 
     pub async fn network_subgraph(&self, indexer_address: &str) -> Result<Network, QueryError> {
-        query_network_subgraph(&self.graph_network, indexer_address).await
+        if let Some(data_source) = &self.data_source {
+            return data_source.indexer_status(indexer_address).await;
+        }
+        query_network_subgraph(&self.query_client, &self.graph_network, indexer_address).await
+    }
+
+    /// Check the indexer's stake against the network minimum. With a single network
+    /// subgraph, the registry snapshot answers when it is fresh and knows the indexer
+    pub async fn stake_satisfy_requirement(
+        &self,
+        indexer_address: &str,
+    ) -> Result<bool, QueryError> {
+        if !self.network_subgraphs.is_empty() {
+            return Ok(self.resolve_indexer_stake(indexer_address).await?.satisfied);
+        }
+        let from_snapshot = self.registry_snapshot.as_ref().and_then(|store| {
+            store
+                .fresh(|snapshot| snapshot.stake_satisfy_requirement(indexer_address))
+                .flatten()
+        });
+        match from_snapshot {
+            Some(satisfied) => Ok(satisfied),
+            None => Ok(self
+                .network_subgraph(indexer_address)
+                .await?
+                .stake_satisfy_requirement()),
+        }
+    }
+
+    /// Network subgraphs identities are resolved against, in resolution order.
+    /// Without configured `network_subgraphs`, only `graph_network`
+    pub fn resolution_networks(&self) -> Vec<NetworkSubgraph> {
+        if self.network_subgraphs.is_empty() {
+            return vec![NetworkSubgraph::new(
+                "network",
+                NetworkLayer::L1,
+                self.graph_network.clone(),
+            )];
+        }
+        self.network_resolution
+            .order(&self.network_subgraphs)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Networks queried to resolve identities and stakes. A data source answers for
+    /// every network at once, so it is queried a single time as the first network
+    fn queried_networks(&self) -> Vec<NetworkSubgraph> {
+        let mut networks = self.resolution_networks();
+        if self.data_source.is_some() {
+            networks.truncate(1);
+        }
+        networks
+    }
+
+    /// Current protocol epoch and epoch length, from the first network subgraph that
+    /// answers in resolution order
+    pub async fn current_epoch(&self) -> Result<ProtocolEpoch, QueryError> {
+        if let Some(data_source) = &self.data_source {
+            return data_source.current_epoch().await;
+        }
+        let mut last_error = None;
+        for network in self.resolution_networks() {
+            match query_current_epoch(&self.query_client, &network.endpoint).await {
+                Ok(epoch) => return Ok(epoch),
+                Err(e) => {
+                    debug!(
+                        network = %network.name,
+                        err = tracing::field::debug(&e),
+                        "Could not query current epoch"
+                    );
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or(QueryError::Other(anyhow::anyhow!(
+            "No network subgraph configured"
+        ))))
+    }
+
+    /// Block `epoch` started at on each network, from the epoch block oracle subgraph
+    pub async fn epoch_blocks(
+        &self,
+        epoch: u64,
+    ) -> Result<HashMap<NetworkName, EpochPointer>, QueryError> {
+        let endpoint =
+            self.epoch_block_oracle
+                .as_ref()
+                .ok_or(QueryError::Other(anyhow::anyhow!(
+                    "No epoch block oracle subgraph configured"
+                )))?;
+        query_epoch_blocks(&self.query_client, endpoint, epoch).await
+    }
+
+    /// Match the agent with the graph account on the network subgraphs following the
+    /// `NetworkResolution`, reporting the networks that matched
+    pub async fn resolve_graph_account(
+        &self,
+        agent_address: &str,
+        graph_account: &str,
+    ) -> Result<AccountResolution, QueryError> {
+        let require_all = self.network_resolution == NetworkResolution::All;
+        let mut resolved: Option<AccountResolution> = None;
+        let mut last_error = None;
+        for network in self.queried_networks() {
+            let result = match &self.data_source {
+                Some(data_source) => {
+                    data_source
+                        .graph_account(agent_address, graph_account)
+                        .await
+                }
+                None => {
+                    query_graph_account(
+                        &self.query_client,
+                        &network.endpoint,
+                        agent_address,
+                        graph_account,
+                    )
+                    .await
+                }
+            };
+            match result {
+                Ok(account) => {
+                    let resolution = resolved.get_or_insert(AccountResolution {
+                        account,
+                        networks: vec![],
+                    });
+                    resolution.networks.push(network.name);
+                    if !require_all {
+                        break;
+                    }
+                }
+                Err(e) if require_all => return Err(e),
+                Err(e) => {
+                    debug!(
+                        network = %network.name,
+                        err = tracing::field::debug(&e),
+                        "Graph account did not match on network subgraph"
+                    );
+                    last_error = Some(e);
+                }
+            }
+        }
+        resolved.ok_or(last_error.unwrap_or(QueryError::Other(anyhow::anyhow!(
+            "No network subgraph configured"
+        ))))
+    }
+
+    /// Resolve the indexer's stake on the network subgraphs following the
+    /// `NetworkResolution`. Networks that cannot be queried are skipped, unless all
+    /// networks are required
+    pub async fn resolve_indexer_stake(
+        &self,
+        indexer_address: &str,
+    ) -> Result<StakeResolution, QueryError> {
+        let networks = self.queried_networks();
+        let mut statuses = vec![];
+        let mut last_error = None;
+        for network in &networks {
+            let result = match &self.data_source {
+                Some(data_source) => data_source.indexer_status(indexer_address).await,
+                None => {
+                    query_network_subgraph(&self.query_client, &network.endpoint, indexer_address)
+                        .await
+                }
+            };
+            match result {
+                Ok(status) => statuses.push((network, status)),
+                Err(e) if self.network_resolution == NetworkResolution::All => return Err(e),
+                Err(e) => {
+                    warn!(
+                        network = %network.name,
+                        err = tracing::field::debug(&e),
+                        "Could not query network subgraph for indexer stake"
+                    );
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) if statuses.is_empty() => Err(e),
+            _ => Ok(resolve_stake(self.network_resolution, &statuses)),
+        }
+    }
+
+    /// Evaluate a PromQL expression now on the configured Prometheus server
+    pub async fn prometheus_query(&self, query: &str) -> Result<Vec<InstantSample>, QueryError> {
+        query_prometheus_instant(&self.query_client, self.prometheus_endpoint()?, query, None).await
+    }
+
+    /// Evaluate a PromQL expression over `[start, end]` (Unix seconds) every `step`
+    /// on the configured Prometheus server
+    pub async fn prometheus_query_range(
+        &self,
+        query: &str,
+        start: i64,
+        end: i64,
+        step: Duration,
+    ) -> Result<Vec<RangeSeries>, QueryError> {
+        query_prometheus_range(
+            &self.query_client,
+            self.prometheus_endpoint()?,
+            query,
+            start,
+            end,
+            step,
+        )
+        .await
+    }
+
+    fn prometheus_endpoint(&self) -> Result<&Endpoint, QueryError> {
+        self.prometheus
+            .as_ref()
+            .ok_or(QueryError::Other(anyhow::anyhow!(
+                "No Prometheus endpoint configured"
+            )))
+    }
+
+    pub async fn graph_account(
+        &self,
+        agent_address: &str,
+        graph_account: &str,
+    ) -> Result<Account, QueryError> {
+        if let Some(data_source) = &self.data_source {
+            return data_source
+                .graph_account(agent_address, graph_account)
+                .await;
+        }
+        query_graph_account(
+            &self.query_client,
+            &self.graph_network,
+            agent_address,
+            graph_account,
+        )
+        .await
+    }
+
+    pub async fn subgraph_hash_by_id(
+        &self,
+        graph_account: &str,
+        subgraph_id: &str,
+    ) -> Result<String, QueryError> {
+        if let Some(data_source) = &self.data_source {
+            return data_source
+                .subgraph_hash_by_id(graph_account, subgraph_id)
+                .await;
+        }
+        subgraph_hash_by_id(
+            &self.query_client,
+            &self.graph_network,
+            graph_account,
+            subgraph_id,
+        )
+        .await
+    }
+}
+
+/// Await `primary`, and `fallback` if it fails
+async fn with_fallback<T>(
+    network: &str,
+    primary_name: &str,
+    primary: impl Future<Output = Result<T, QueryError>>,
+    fallback: impl Future<Output = Result<T, QueryError>>,
+) -> Result<T, QueryError> {
+    match primary.await {
+        Ok(value) => Ok(value),
+        Err(e) => {
+            warn!(
+                network,
+                err = tracing::field::debug(&e),
+                "Could not query {primary_name}, use the fallback block source"
+            );
+            fallback.await
+        }
+    }
+}
+
+/// Compare the block hash from graph node with the JSON-RPC provider's. When only one
+/// of them answered, its hash is used
+fn cross_check(
+    network: &str,
+    block_number: u64,
+    graph_node: Result<String, QueryError>,
+    rpc: Result<String, QueryError>,
+) -> Result<String, QueryError> {
+    match (graph_node, rpc) {
+        (Ok(graph_node), Ok(rpc)) if same_block_hash(&graph_node, &rpc) => Ok(graph_node),
+        (Ok(graph_node), Ok(rpc)) => {
+            warn!(
+                network,
+                block_number, graph_node, rpc, "Graph node and JSON-RPC block hashes differ"
+            );
+            Err(QueryError::BlockHashMismatch {
+                network: network.to_string(),
+                block_number,
+                graph_node,
+                rpc,
+            })
+        }
+        (Ok(hash), Err(e)) | (Err(e), Ok(hash)) => {
+            warn!(
+                network,
+                block_number,
+                err = tracing::field::debug(&e),
+                "Could not cross-check block hash, use the one source that answered"
+            );
+            Ok(hash)
+        }
+        (Err(e), Err(_)) => Err(e),
+    }
+}
+
+impl PartialEq for CallBook {
+    fn eq(&self, other: &Self) -> bool {
+        self.graphcast_registry == other.graphcast_registry
+            && self.registry_contract == other.registry_contract
+            && self.graph_network == other.graph_network
+            && self.network_subgraphs == other.network_subgraphs
+            && self.network_resolution == other.network_resolution
+            && self.graph_node_status == other.graph_node_status
+            && self.additional_graph_nodes == other.additional_graph_nodes
+            && self.epoch_block_oracle == other.epoch_block_oracle
+            && self.prometheus == other.prometheus
+            && self.rpc == other.rpc
+            && self.query_client == other.query_client
+            && self.identity_cache == other.identity_cache
+            && match (&self.data_source, &other.data_source) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+            && self.registry_snapshot == other.registry_snapshot
+    }
+}
+
+/// Queries the configured HTTP endpoints, or the overriding data source when set
+#[async_trait]
+impl GraphcastDataSource for CallBook {
+    async fn registered_indexer(&self, graphcast_id: &str) -> Result<String, QueryError> {
+        CallBook::registered_indexer(self, graphcast_id).await
+    }
+
+    async fn graph_account(
+        &self,
+        agent_address: &str,
+        graph_account: &str,
+    ) -> Result<Account, QueryError> {
+        CallBook::graph_account(self, agent_address, graph_account).await
+    }
+
+    async fn indexer_status(&self, indexer_address: &str) -> Result<Network, QueryError> {
+        self.network_subgraph(indexer_address).await
+    }
+
+    async fn subgraph_hash_by_id(
+        &self,
+        graph_account: &str,
+        subgraph_id: &str,
+    ) -> Result<String, QueryError> {
+        CallBook::subgraph_hash_by_id(self, graph_account, subgraph_id).await
+    }
+
+    async fn indexing_statuses(&self) -> Result<Vec<IndexingStatusesIndexingStatuses>, QueryError> {
+        CallBook::indexing_statuses(self).await
+    }
+
+    async fn block_hash(&self, network: &str, block_number: u64) -> Result<String, QueryError> {
+        CallBook::block_hash(self, network, block_number).await
+    }
+
+    async fn current_epoch(&self) -> Result<ProtocolEpoch, QueryError> {
+        CallBook::current_epoch(self).await
     }
 }
@@ -0,0 +1,292 @@
+//! Data sources used to resolve identities and chain state.
+//!
+//! `GraphcastDataSource` abstracts the Graphcast registry, the Graph network subgraph
+//! and graph node's status endpoint. `CallBook` implements it over HTTP by default,
+//! and can be pointed at any other implementation, such as `FixtureDataSource` for
+//! deterministic tests without live endpoints.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::graphql::client_epoch::ProtocolEpoch;
+use crate::graphql::client_graph_node::indexing_statuses::IndexingStatusesIndexingStatuses;
+use crate::graphql::client_network::{GraphNetwork, Indexer, Network};
+use crate::graphql::QueryError;
+use crate::Account;
+
+/// Lookups needed to verify message senders and attest to blocks
+#[async_trait]
+pub trait GraphcastDataSource: Send + Sync + fmt::Debug {
+    /// Resolve the indexer address registered for a Graphcast ID
+    async fn registered_indexer(&self, graphcast_id: &str) -> Result<String, QueryError>;
+
+    /// Resolve a Graph account, requiring an operator relationship when the agent is not the account
+    async fn graph_account(
+        &self,
+        agent_address: &str,
+        graph_account: &str,
+    ) -> Result<Account, QueryError>;
+
+    /// Indexer stake and allocations, along with the network minimum stake
+    async fn indexer_status(&self, indexer_address: &str) -> Result<Network, QueryError>;
+
+    /// Current subgraph deployment hash of a subgraph owned by the account
+    async fn subgraph_hash_by_id(
+        &self,
+        graph_account: &str,
+        subgraph_id: &str,
+    ) -> Result<String, QueryError>;
+
+    /// Indexing statuses of all deployments on graph node
+    async fn indexing_statuses(&self) -> Result<Vec<IndexingStatusesIndexingStatuses>, QueryError>;
+
+    /// Block hash of a block on a network
+    async fn block_hash(&self, network: &str, block_number: u64) -> Result<String, QueryError>;
+
+    /// Current protocol epoch and epoch length
+    async fn current_epoch(&self) -> Result<ProtocolEpoch, QueryError>;
+}
+
+pub type SharedDataSource = Arc<dyn GraphcastDataSource>;
+
+/// In-memory data source answering from fixed data, mirroring the not-found
+/// behavior of the subgraph queries
+#[derive(Clone, Debug, Default)]
+pub struct FixtureDataSource {
+    /// Graphcast ID -> indexer address
+    registry: HashMap<String, String>,
+    /// Graph account -> operator addresses
+    graph_accounts: HashMap<String, HashSet<String>>,
+    /// Indexer address -> indexer data
+    indexers: HashMap<String, Indexer>,
+    graph_network: GraphNetwork,
+    /// (Graph account, subgraph id) -> current deployment hash
+    subgraphs: HashMap<(String, String), String>,
+    indexing_statuses: Vec<IndexingStatusesIndexingStatuses>,
+    /// (network, block number) -> block hash
+    block_hashes: HashMap<(String, u64), String>,
+    current_epoch: Option<ProtocolEpoch>,
+}
+
+impl FixtureDataSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_registered_indexer(mut self, graphcast_id: &str, indexer: &str) -> Self {
+        self.registry
+            .insert(graphcast_id.to_lowercase(), indexer.to_lowercase());
+        self
+    }
+
+    pub fn with_graph_account(mut self, graph_account: &str, operators: &[&str]) -> Self {
+        self.graph_accounts
+            .entry(graph_account.to_lowercase())
+            .or_default()
+            .extend(operators.iter().map(|o| o.to_lowercase()));
+        self
+    }
+
+    pub fn with_indexer(mut self, indexer_address: &str, indexer: Indexer) -> Self {
+        self.indexers
+            .insert(indexer_address.to_lowercase(), indexer);
+        self
+    }
+
+    pub fn with_graph_network(mut self, graph_network: GraphNetwork) -> Self {
+        self.graph_network = graph_network;
+        self
+    }
+
+    pub fn with_subgraph(mut self, graph_account: &str, subgraph_id: &str, hash: &str) -> Self {
+        self.subgraphs.insert(
+            (graph_account.to_lowercase(), subgraph_id.to_string()),
+            hash.to_string(),
+        );
+        self
+    }
+
+    pub fn with_indexing_statuses(
+        mut self,
+        indexing_statuses: Vec<IndexingStatusesIndexingStatuses>,
+    ) -> Self {
+        self.indexing_statuses = indexing_statuses;
+        self
+    }
+
+    pub fn with_block_hash(mut self, network: &str, block_number: u64, hash: &str) -> Self {
+        self.block_hashes
+            .insert((network.to_string(), block_number), hash.to_string());
+        self
+    }
+
+    pub fn with_current_epoch(mut self, current_epoch: ProtocolEpoch) -> Self {
+        self.current_epoch = Some(current_epoch);
+        self
+    }
+}
+
+#[async_trait]
+impl GraphcastDataSource for FixtureDataSource {
+    async fn registered_indexer(&self, graphcast_id: &str) -> Result<String, QueryError> {
+        self.registry
+            .get(&graphcast_id.to_lowercase())
+            .cloned()
+            .ok_or(QueryError::ParseResponseError(format!(
+                "No indexer data queried from registry for GraphcastID: {graphcast_id}"
+            )))
+    }
+
+    async fn graph_account(
+        &self,
+        agent_address: &str,
+        graph_account: &str,
+    ) -> Result<Account, QueryError> {
+        let agent = agent_address.to_lowercase();
+        let account = graph_account.to_lowercase();
+        let operators = self.graph_accounts.get(&account).ok_or_else(|| {
+            QueryError::ParseResponseError(String::from(
+                "Network subgraph does not have a match for graph account",
+            ))
+        })?;
+        if agent != account && !operators.contains(&agent) {
+            return Err(QueryError::ParseResponseError(String::from(
+                "Network subgraph does not have a match for agent account and graph account",
+            )));
+        }
+        Ok(Account::new(agent, account))
+    }
+
+    async fn indexer_status(&self, indexer_address: &str) -> Result<Network, QueryError> {
+        Ok(Network {
+            indexer: self.indexers.get(&indexer_address.to_lowercase()).cloned(),
+            graph_network: self.graph_network.clone(),
+        })
+    }
+
+    async fn subgraph_hash_by_id(
+        &self,
+        graph_account: &str,
+        subgraph_id: &str,
+    ) -> Result<String, QueryError> {
+        self.subgraphs
+            .get(&(graph_account.to_lowercase(), subgraph_id.to_string()))
+            .cloned()
+            .ok_or(QueryError::ParseResponseError(String::from(
+                "Network subgraph does not have subgraph id match for the owner",
+            )))
+    }
+
+    async fn indexing_statuses(&self) -> Result<Vec<IndexingStatusesIndexingStatuses>, QueryError> {
+        Ok(self.indexing_statuses.clone())
+    }
+
+    async fn block_hash(&self, network: &str, block_number: u64) -> Result<String, QueryError> {
+        self.block_hashes
+            .get(&(network.to_string(), block_number))
+            .cloned()
+            .ok_or(QueryError::ParseResponseError(format!(
+                "No data for {network} blockHash at block {block_number}"
+            )))
+    }
+
+    async fn current_epoch(&self) -> Result<ProtocolEpoch, QueryError> {
+        self.current_epoch
+            .clone()
+            .ok_or(QueryError::ParseResponseError(String::from(
+                "Missing epoch data from network subgraph",
+            )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callbook::CallBook;
+    use crate::graphql::client_network::{NetworkLayer, NetworkSubgraph};
+    use crate::graphql::endpoint::Endpoint;
+    use crate::graphql::GrtAmount;
+
+    #[tokio::test]
+    async fn test_fixture_graph_account_operators() {
+        let fixture = FixtureDataSource::new().with_graph_account("0xAccount", &["0xOperator"]);
+        assert_eq!(
+            fixture
+                .graph_account("0xoperator", "0xaccount")
+                .await
+                .unwrap(),
+            Account::new("0xoperator".to_string(), "0xaccount".to_string())
+        );
+        assert!(fixture
+            .graph_account("0xaccount", "0xaccount")
+            .await
+            .is_ok());
+        assert!(fixture
+            .graph_account("0xstranger", "0xaccount")
+            .await
+            .is_err());
+        assert!(fixture
+            .graph_account("0xoperator", "0xother")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_callbook_uses_data_source() {
+        let fixture = FixtureDataSource::new()
+            .with_registered_indexer("0xGraphcastId", "0xIndexer")
+            .with_block_hash("mainnet", 17_000_000, "0xhash");
+        let callbook =
+            CallBook::new(String::new(), String::new(), None).with_data_source(Arc::new(fixture));
+
+        assert_eq!(
+            callbook.registered_indexer("0xgraphcastid").await.unwrap(),
+            "0xindexer"
+        );
+        assert_eq!(
+            callbook.block_hash("mainnet", 17_000_000).await.unwrap(),
+            "0xhash"
+        );
+        assert!(callbook.block_hash("mainnet", 1).await.is_err());
+        assert!(callbook
+            .network_subgraph("0xindexer")
+            .await
+            .unwrap()
+            .indexer
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_data_source_across_networks() {
+        let fixture = FixtureDataSource::new()
+            .with_graph_account("0xAccount", &["0xOperator"])
+            .with_indexer(
+                "0xAccount",
+                Indexer::new(GrtAmount::from_grt(60_000), vec![]),
+            )
+            .with_graph_network(GraphNetwork {
+                minimum_indexer_stake: GrtAmount::from_grt(100_000),
+            });
+        let callbook = ["mainnet", "arbitrum-one"]
+            .into_iter()
+            .map(|name| NetworkSubgraph::new(name, NetworkLayer::L1, Endpoint::new("")))
+            .fold(
+                CallBook::new(String::new(), String::new(), None),
+                CallBook::with_network_subgraph,
+            )
+            .with_data_source(Arc::new(fixture));
+
+        // The data source is counted once, not once per configured network
+        let stake = callbook.resolve_indexer_stake("0xaccount").await.unwrap();
+        assert_eq!(stake.stake, GrtAmount::from_grt(60_000));
+        assert!(!stake.satisfied);
+        let account = callbook
+            .resolve_graph_account("0xoperator", "0xaccount")
+            .await
+            .unwrap();
+        assert_eq!(account.networks, vec!["mainnet"]);
+    }
+}
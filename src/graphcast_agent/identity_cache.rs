@@ -0,0 +1,389 @@
+//! Time-bounded cache for sender identity verification results.
+//!
+//! Identity checks resolve the sender through the Graphcast registry and/or the
+//! Graph network subgraph, costing one or two queries for every incoming message.
+//! Results are cached per (agent address, graph account, validation mechanism), with a
+//! longer lifetime for successful verifications than for rejected ones.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as SyncMutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use crate::graphql::QueryError;
+use crate::Account;
+
+use super::message_typing::{IdentityValidation, MessageError};
+
+/// Lifetimes and size bound of the identity cache
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityCacheConfig {
+    /// How long a successful verification is reused
+    pub positive_ttl: Duration,
+    /// How long a rejected identity stays rejected without re-querying
+    pub negative_ttl: Duration,
+    /// Maximum number of cached identities
+    pub max_entries: usize,
+}
+
+impl Default for IdentityCacheConfig {
+    fn default() -> Self {
+        IdentityCacheConfig {
+            positive_ttl: Duration::from_secs(600),
+            negative_ttl: Duration::from_secs(60),
+            max_entries: 10_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    agent: String,
+    graph_account: String,
+    id_validation: IdentityValidation,
+}
+
+#[derive(Clone, Debug)]
+enum CachedResult {
+    Verified(Account),
+    Rejected(String),
+}
+
+#[derive(Clone, Debug)]
+struct CacheEntry {
+    result: CachedResult,
+    /// Insertion sequence number, matching the entry's latest item in the eviction queue
+    seq: u64,
+    expires_at: Instant,
+}
+
+/// Cached entries with their keys in insertion order. Replaced and removed entries
+/// leave stale queue items behind, skipped on eviction by their sequence number
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, CacheEntry>,
+    order: VecDeque<(CacheKey, u64)>,
+    next_seq: u64,
+}
+
+impl CacheState {
+    /// Remove the entry inserted first
+    fn evict_oldest(&mut self) -> Option<CacheKey> {
+        while let Some((key, seq)) = self.order.pop_front() {
+            if self.entries.get(&key).is_some_and(|entry| entry.seq == seq) {
+                self.entries.remove(&key);
+                return Some(key);
+            }
+        }
+        None
+    }
+
+    /// Drop stale queue items once they outnumber the entries
+    fn compact(&mut self) {
+        if self.order.len() > 2 * self.entries.len().max(1) {
+            let entries = &self.entries;
+            self.order
+                .retain(|(key, seq)| entries.get(key).is_some_and(|entry| entry.seq == *seq));
+        }
+    }
+}
+
+/// Hit and miss counts of the identity cache
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// Identity verification cache, cheap to clone and shared between all clones
+#[derive(Clone, Debug, Default)]
+pub struct IdentityCache {
+    config: IdentityCacheConfig,
+    state: Arc<SyncMutex<CacheState>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl IdentityCache {
+    pub fn new(config: IdentityCacheConfig) -> Self {
+        IdentityCache {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn config(&self) -> &IdentityCacheConfig {
+        &self.config
+    }
+
+    /// Look up a live cached result for the account under the validation mechanism
+    pub fn get(
+        &self,
+        account: &Account,
+        id_validation: &IdentityValidation,
+    ) -> Option<Result<Account, MessageError>> {
+        let key = CacheKey::new(account, id_validation);
+        let mut state = self.state.lock().unwrap();
+        let cached = match state.entries.get(&key) {
+            Some(entry) if entry.expires_at > Instant::now() => Some(entry.result.clone()),
+            Some(_) => {
+                state.entries.remove(&key);
+                None
+            }
+            None => None,
+        };
+        drop(state);
+
+        match cached {
+            Some(CachedResult::Verified(a)) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(Ok(a))
+            }
+            Some(CachedResult::Rejected(reason)) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(Err(MessageError::InvalidFields(anyhow::anyhow!(
+                    "Identity previously rejected: {reason}"
+                ))))
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Record a verification result. Errors caused by unavailable data sources are
+    /// not cached so that the next message retries the lookup
+    pub fn insert(
+        &self,
+        account: &Account,
+        id_validation: &IdentityValidation,
+        result: &Result<Account, MessageError>,
+    ) {
+        let (cached, ttl) = match result {
+            Ok(a) => (CachedResult::Verified(a.clone()), self.config.positive_ttl),
+            Err(e) if is_transient(e) => return,
+            Err(e) => (
+                CachedResult::Rejected(e.to_string()),
+                self.config.negative_ttl,
+            ),
+        };
+        if self.config.max_entries == 0 || ttl.is_zero() {
+            return;
+        }
+
+        let now = Instant::now();
+        let key = CacheKey::new(account, id_validation);
+        let mut state = self.state.lock().unwrap();
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.config.max_entries {
+            if let Some(oldest) = state.evict_oldest() {
+                trace!(
+                    agent = oldest.agent,
+                    "Identity cache is full, evict the oldest entry"
+                );
+            }
+        }
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.order.push_back((key.clone(), seq));
+        state.entries.insert(
+            key,
+            CacheEntry {
+                result: cached,
+                seq,
+                expires_at: now + ttl,
+            },
+        );
+        state.compact();
+    }
+
+    /// Drop every cached result, for example after a registry change
+    pub fn invalidate_all(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        state.order.clear();
+    }
+
+    /// Drop cached results for an agent address regardless of graph account and validation
+    pub fn invalidate_agent(&self, agent: &str) {
+        let agent = agent.to_lowercase();
+        let mut state = self.state.lock().unwrap();
+        state.entries.retain(|key, _| key.agent != agent);
+        state.compact();
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> IdentityCacheStats {
+        IdentityCacheStats {
+            hits: self.hits(),
+            misses: self.misses(),
+            entries: self.len(),
+        }
+    }
+}
+
+impl PartialEq for IdentityCache {
+    fn eq(&self, other: &Self) -> bool {
+        self.config == other.config
+    }
+}
+
+impl CacheKey {
+    fn new(account: &Account, id_validation: &IdentityValidation) -> Self {
+        CacheKey {
+            agent: account.agent.to_lowercase(),
+            graph_account: account.account.to_lowercase(),
+            id_validation: id_validation.clone(),
+        }
+    }
+}
+
+/// Failures to reach the data sources say nothing about the identity itself
+fn is_transient(error: &MessageError) -> bool {
+    matches!(
+        error,
+        MessageError::FieldDerivations(
+            QueryError::Transport(_)
+                | QueryError::Timeout(_)
+                | QueryError::IndexingError
+                | QueryError::PrometheusError(_)
+                | QueryError::Rpc(_)
+                | QueryError::BlockHashMismatch { .. }
+                | QueryError::Other(_)
+        )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account() -> Account {
+        Account::new(
+            "0xe9a1cabd57700b17945fd81feefba82340d9568f".to_string(),
+            "0x6121d1036d7016b125f019268b0406a4c15bb99d".to_string(),
+        )
+    }
+
+    fn rejection() -> Result<Account, MessageError> {
+        Err(MessageError::InvalidFields(anyhow::anyhow!(
+            "not an indexer"
+        )))
+    }
+
+    #[test]
+    fn test_hit_and_miss() {
+        let cache = IdentityCache::default();
+        let id_validation = IdentityValidation::Indexer;
+        assert!(cache.get(&account(), &id_validation).is_none());
+
+        cache.insert(&account(), &id_validation, &Ok(account()));
+        assert_eq!(
+            cache.get(&account(), &id_validation).unwrap().unwrap(),
+            account()
+        );
+        // Another validation mechanism is a separate entry
+        assert!(cache
+            .get(&account(), &IdentityValidation::RegisteredIndexer)
+            .is_none());
+
+        assert_eq!(
+            cache.stats(),
+            IdentityCacheStats {
+                hits: 1,
+                misses: 2,
+                entries: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_negative_ttl() {
+        let cache = IdentityCache::new(IdentityCacheConfig {
+            negative_ttl: Duration::from_millis(20),
+            ..Default::default()
+        });
+        let id_validation = IdentityValidation::Indexer;
+        cache.insert(&account(), &id_validation, &rejection());
+        assert!(cache.get(&account(), &id_validation).unwrap().is_err());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(cache.get(&account(), &id_validation).is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_transient_errors_not_cached() {
+        let cache = IdentityCache::default();
+        let id_validation = IdentityValidation::GraphcastRegistered;
+        cache.insert(
+            &account(),
+            &id_validation,
+            &Err(MessageError::FieldDerivations(QueryError::Timeout(
+                "registry".to_string(),
+            ))),
+        );
+        cache.insert(
+            &account(),
+            &id_validation,
+            &Err(MessageError::FieldDerivations(QueryError::Rpc(
+                "connection refused".to_string(),
+            ))),
+        );
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_size_bound_and_invalidation() {
+        let cache = IdentityCache::new(IdentityCacheConfig {
+            max_entries: 2,
+            ..Default::default()
+        });
+        let id_validation = IdentityValidation::Indexer;
+        let accounts: Vec<Account> = (0..3)
+            .map(|i| Account::new(format!("0xagent{i}"), format!("0xaccount{i}")))
+            .collect();
+        for a in &accounts {
+            cache.insert(a, &id_validation, &Ok(a.clone()));
+        }
+        assert_eq!(cache.len(), 2);
+        // Oldest entry got evicted
+        assert!(cache.get(&accounts[0], &id_validation).is_none());
+        assert!(cache.get(&accounts[2], &id_validation).is_some());
+        // Inserting again makes an entry the newest
+        cache.insert(&accounts[1], &id_validation, &Ok(accounts[1].clone()));
+        cache.insert(&accounts[0], &id_validation, &Ok(accounts[0].clone()));
+        assert!(cache.get(&accounts[2], &id_validation).is_none());
+        assert!(cache.get(&accounts[1], &id_validation).is_some());
+
+        cache.invalidate_agent("0xagent1");
+        assert!(cache.get(&accounts[1], &id_validation).is_none());
+        cache.insert(&accounts[2], &id_validation, &Ok(accounts[2].clone()));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&accounts[0], &id_validation).is_some());
+
+        cache.invalidate_agent("0xagent2");
+        assert!(cache.get(&accounts[2], &id_validation).is_none());
+        cache.invalidate_all();
+        assert!(cache.is_empty());
+    }
+}
@@ -10,13 +10,13 @@ use prost::Message;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fmt, str::FromStr, sync::Arc};
 use tokio::sync::Mutex;
-use tracing::{debug, error, trace};
+use tracing::{debug, trace};
 use waku::{Running, WakuContentTopic, WakuMessage, WakuNodeHandle, WakuPubSubTopic};
 
 use crate::{
     callbook::CallBook,
-    graphql::{client_network::query_network_subgraph, QueryError},
-    Account, NetworkBlockError, NoncesMap,
+    graphql::{GrtAmount, QueryError},
+    metrics, Account, NetworkBlockError, NoncesMap,
 };
 
 use super::{waku_handling::WakuHandlingError, MSG_REPLAY_LIMIT};
@@ -35,9 +35,10 @@ fn prepare_nonces(
 
 pub async fn get_indexer_stake(
     indexer_address: &str,
-    network_subgraph: &str,
-) -> Result<f32, QueryError> {
-    Ok(query_network_subgraph(network_subgraph, indexer_address)
+    callbook: &CallBook,
+) -> Result<GrtAmount, QueryError> {
+    Ok(callbook
+        .network_subgraph(indexer_address)
         .await?
         .indexer_stake())
 }
@@ -147,8 +148,7 @@ impl<T: RadioPayload> GraphcastMessage<T> {
     /// Check message from valid sender: resolve indexer address and self stake
     pub async fn valid_sender(
         &self,
-        registry_subgraph: &str,
-        network_subgraph: &str,
+        callbook: &CallBook,
         local_sender_id: String,
         id_validation: &IdentityValidation,
     ) -> Result<&Self, MessageError> {
@@ -159,7 +159,7 @@ impl<T: RadioPayload> GraphcastMessage<T> {
 
         let _ = self
             .remote_account(local_sender_id)?
-            .verify(network_subgraph, registry_subgraph, id_validation)
+            .verify(callbook, id_validation)
             .await?;
         Ok(self)
     }
@@ -275,6 +275,8 @@ impl<T: RadioPayload> GraphcastMessage<T> {
 /// Time check verifies that message was from within the acceptable timestamp
 /// Block hash check verifies sender's access to valid Ethereum node provider and blocks
 /// Nonce check ensures the ordering of the messages and avoids past messages
+///
+/// Validated messages and rejections by validation stage are counted in `metrics`
 pub async fn check_message_validity<T: RadioPayload>(
     graphcast_message: GraphcastMessage<T>,
     nonces: &Arc<Mutex<NoncesMap>>,
@@ -282,17 +284,22 @@ pub async fn check_message_validity<T: RadioPayload>(
     local_sender_id: String,
     id_validation: &IdentityValidation,
 ) -> Result<GraphcastMessage<T>, MessageError> {
+    let rejected = |stage: &'static str| {
+        move |e: MessageError| {
+            metrics::record_rejection(&e, stage);
+            e
+        }
+    };
     graphcast_message
-        .valid_sender(
-            callbook.graphcast_registry(),
-            callbook.graph_network(),
-            local_sender_id,
-            id_validation,
-        )
-        .await?
-        .valid_time()?
+        .valid_sender(&callbook, local_sender_id, id_validation)
+        .await
+        .map_err(rejected("sender"))?
+        .valid_time()
+        .map_err(rejected("time"))?
         .valid_nonce(nonces)
-        .await?;
+        .await
+        .map_err(rejected("nonce"))?;
+    metrics::MESSAGES_VALIDATED.inc();
 
     trace!(
         message = tracing::field::debug(&graphcast_message),
@@ -337,7 +344,7 @@ impl MessageError {
 }
 
 /// Identity validation for a Graphcast Message
-#[derive(Clone, Debug, Eq, PartialEq, Default, clap::ValueEnum, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Default, clap::ValueEnum, Serialize, Deserialize)]
 pub enum IdentityValidation {
     // no checks
     NoCheck,
@@ -373,6 +380,8 @@ impl fmt::Display for IdentityValidation {
 
 #[cfg(test)]
 mod tests {
+    use crate::data_source::FixtureDataSource;
+    use crate::graphql::client_network::{GraphNetwork, Indexer};
     use crate::wallet_address;
 
     use super::*;
@@ -474,6 +483,28 @@ mod tests {
         }
     }
 
+    /// Goerli identities behind the signed test messages: both graph accounts are indexers
+    /// meeting the minimum stake, and the third signer is a Graphcast ID registered to the first
+    fn fixture_callbook() -> CallBook {
+        let graphcast_id = graphcast_id_message().recover_sender_address().unwrap();
+        let fixture = FixtureDataSource::new()
+            .with_registered_indexer(&graphcast_id, "0xe9a1cabd57700b17945fd81feefba82340d9568f")
+            .with_graph_account("0xe9a1cabd57700b17945fd81feefba82340d9568f", &[])
+            .with_graph_account("0x6121d1036d7016b125f019268b0406a4c15bb99d", &[])
+            .with_indexer(
+                "0xe9a1cabd57700b17945fd81feefba82340d9568f",
+                Indexer::new(GrtAmount::from_grt(100_000), vec![]),
+            )
+            .with_indexer(
+                "0x6121d1036d7016b125f019268b0406a4c15bb99d",
+                Indexer::new(GrtAmount::from_grt(100_000), vec![]),
+            )
+            .with_graph_network(GraphNetwork {
+                minimum_indexer_stake: GrtAmount::from_grt(100_000),
+            });
+        CallBook::new(String::new(), String::new(), None).with_data_source(Arc::new(fixture))
+    }
+
     #[tokio::test]
     async fn test_signature() {
         let wallet = dummy_wallet();
@@ -495,10 +526,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_standard_message() {
-        let registry_subgraph =
-            "https://api.thegraph.com/subgraphs/name/hopeyen/gossip-registry-test";
-        let network_subgraph =
-            "https://api.thegraph.com/subgraphs/name/graphprotocol/graph-network-goerli";
+        let callbook = fixture_callbook();
 
         let hash: String = "table".to_string();
         let content: String = "Ping".to_string();
@@ -512,8 +540,7 @@ mod tests {
 
         assert!(msg
             .valid_sender(
-                registry_subgraph,
-                network_subgraph,
+                &callbook,
                 "x".to_string(),
                 &IdentityValidation::ValidAddress
             )
@@ -521,8 +548,7 @@ mod tests {
             .is_ok());
         assert!(msg
             .valid_sender(
-                registry_subgraph,
-                network_subgraph,
+                &callbook,
                 "x".to_string(),
                 &IdentityValidation::RegisteredIndexer
             )
@@ -539,10 +565,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_validate_graph_network() {
-        let registry_subgraph =
-            "https://api.thegraph.com/subgraphs/name/hopeyen/gossip-registry-test";
-        let network_subgraph =
-            "https://api.thegraph.com/subgraphs/name/graphprotocol/graph-network-goerli";
+        let callbook = fixture_callbook();
         // graph_account_message is by a valid eth address that is not registered as a graphcast_id but is a graph account and valid indexer
         let msg = graph_account_message();
         assert_eq!(
@@ -550,47 +573,30 @@ mod tests {
             String::from("0xe9a1cabd57700b17945fd81feefba82340d9568f")
         );
         assert!(msg
-            .valid_sender(
-                registry_subgraph,
-                network_subgraph,
-                "".to_string(),
-                &IdentityValidation::NoCheck
-            )
+            .valid_sender(&callbook, "".to_string(), &IdentityValidation::NoCheck)
             .await
             .is_ok());
         assert!(msg
-            .valid_sender(
-                registry_subgraph,
-                network_subgraph,
-                "".to_string(),
-                &IdentityValidation::ValidAddress
-            )
+            .valid_sender(&callbook, "".to_string(), &IdentityValidation::ValidAddress)
             .await
             .is_ok());
         assert!(msg
             .valid_sender(
-                registry_subgraph,
-                network_subgraph,
+                &callbook,
                 "".to_string(),
                 &IdentityValidation::GraphNetworkAccount
             )
             .await
             .is_ok());
         assert!(msg
-            .valid_sender(
-                registry_subgraph,
-                network_subgraph,
-                "".to_string(),
-                &IdentityValidation::Indexer
-            )
+            .valid_sender(&callbook, "".to_string(), &IdentityValidation::Indexer)
             .await
             .is_ok());
 
         // Message should fail to validate if registry is required
         assert!(msg
             .valid_sender(
-                registry_subgraph,
-                network_subgraph,
+                &callbook,
                 "".to_string(),
                 &IdentityValidation::GraphcastRegistered
             )
@@ -598,8 +604,7 @@ mod tests {
             .is_err());
         assert!(msg
             .valid_sender(
-                registry_subgraph,
-                network_subgraph,
+                &callbook,
                 "".to_string(),
                 &IdentityValidation::RegisteredIndexer
             )
@@ -609,10 +614,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_validate_indexer() {
-        let registry_subgraph =
-            "https://thegraph.com/hosted-service/subgraph/hopeyen/graphcast-registry-goerli";
-        let network_subgraph =
-            "https://api.thegraph.com/subgraphs/name/graphprotocol/graph-network-goerli";
+        let callbook = fixture_callbook();
         // graph_account_message is by a valid eth address that is not registered as a graphcast_id but is a graph account and valid indexer
         let msg = indexer_message();
         assert_eq!(
@@ -620,47 +622,30 @@ mod tests {
             String::from("0x6121d1036d7016b125f019268b0406a4c15bb99d")
         );
         assert!(msg
-            .valid_sender(
-                registry_subgraph,
-                network_subgraph,
-                "".to_string(),
-                &IdentityValidation::NoCheck
-            )
+            .valid_sender(&callbook, "".to_string(), &IdentityValidation::NoCheck)
             .await
             .is_ok());
         assert!(msg
-            .valid_sender(
-                registry_subgraph,
-                network_subgraph,
-                "".to_string(),
-                &IdentityValidation::ValidAddress
-            )
+            .valid_sender(&callbook, "".to_string(), &IdentityValidation::ValidAddress)
             .await
             .is_ok());
         assert!(msg
             .valid_sender(
-                registry_subgraph,
-                network_subgraph,
+                &callbook,
                 "".to_string(),
                 &IdentityValidation::GraphNetworkAccount
             )
             .await
             .is_ok());
         assert!(msg
-            .valid_sender(
-                registry_subgraph,
-                network_subgraph,
-                "".to_string(),
-                &IdentityValidation::Indexer
-            )
+            .valid_sender(&callbook, "".to_string(), &IdentityValidation::Indexer)
             .await
             .is_ok());
 
         // Message should fail to validate if registry is required
         assert!(msg
             .valid_sender(
-                registry_subgraph,
-                network_subgraph,
+                &callbook,
                 "".to_string(),
                 &IdentityValidation::GraphcastRegistered
             )
@@ -668,8 +653,7 @@ mod tests {
             .is_err());
         assert!(msg
             .valid_sender(
-                registry_subgraph,
-                network_subgraph,
+                &callbook,
                 "".to_string(),
                 &IdentityValidation::RegisteredIndexer
             )
@@ -679,46 +663,27 @@ mod tests {
 
     #[tokio::test]
     async fn test_validate_registry() {
-        let registry_subgraph =
-            "https://api.thegraph.com/subgraphs/name/hopeyen/gossip-registry-test";
-        let network_subgraph =
-            "https://api.thegraph.com/subgraphs/name/graphprotocol/graph-network-goerli";
+        let callbook = fixture_callbook();
         // graph_account_message is by a valid eth address that is not registered as a graphcast_id but is a graph account and valid indexer
         let msg = graphcast_id_message();
         assert!(msg
-            .valid_sender(
-                registry_subgraph,
-                network_subgraph,
-                "".to_string(),
-                &IdentityValidation::NoCheck
-            )
+            .valid_sender(&callbook, "".to_string(), &IdentityValidation::NoCheck)
             .await
             .is_ok());
         assert!(msg
-            .valid_sender(
-                registry_subgraph,
-                network_subgraph,
-                "".to_string(),
-                &IdentityValidation::ValidAddress
-            )
+            .valid_sender(&callbook, "".to_string(), &IdentityValidation::ValidAddress)
             .await
             .is_ok());
 
         assert!(msg
-            .valid_sender(
-                registry_subgraph,
-                network_subgraph,
-                "".to_string(),
-                &IdentityValidation::Indexer
-            )
+            .valid_sender(&callbook, "".to_string(), &IdentityValidation::Indexer)
             .await
             .is_ok());
 
         // Message should fail to validate if only Graph network account is checked
         assert!(msg
             .valid_sender(
-                registry_subgraph,
-                network_subgraph,
+                &callbook,
                 "".to_string(),
                 &IdentityValidation::GraphNetworkAccount
             )
@@ -728,8 +693,7 @@ mod tests {
         // Should success for checks at Graphcast registry
         assert!(msg
             .valid_sender(
-                registry_subgraph,
-                network_subgraph,
+                &callbook,
                 "".to_string(),
                 &IdentityValidation::GraphcastRegistered
             )
@@ -737,8 +701,7 @@ mod tests {
             .is_ok());
         assert!(msg
             .valid_sender(
-                registry_subgraph,
-                network_subgraph,
+                &callbook,
                 "".to_string(),
                 &IdentityValidation::RegisteredIndexer
             )
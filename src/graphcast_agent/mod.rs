@@ -9,7 +9,9 @@
 //! Graphcast agent shall be able to construct, send, receive, validate, and attest
 //! Graphcast messages regardless of specific radio use cases
 //!
+use self::identity_cache::{IdentityCache, IdentityCacheConfig};
 use self::message_typing::{GraphcastMessage, IdentityValidation, MessageError, RadioPayload};
+use self::registry_snapshot::{RegistrySnapshotConfig, RegistrySnapshotStore};
 use self::waku_handling::{
     build_content_topics, handle_signal, pubsub_topic, setup_node_handle, WakuHandlingError,
 };
@@ -19,6 +21,7 @@ use serde::{Deserialize, Serialize};
 
 use async_graphql::{self, Result, SimpleObject};
 use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
 use std::str::FromStr;
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex as SyncMutex};
@@ -36,12 +39,24 @@ use crate::Account;
 use crate::{
     build_wallet,
     callbook::CallBook,
+    data_source::SharedDataSource,
     graphcast_agent::waku_handling::relay_subscribe,
-    graphql::{client_graph_node::get_indexing_statuses, QueryError},
+    graphql::{
+        client_network::{NetworkResolution, NetworkSubgraph},
+        client_registry_contract::RegistryContractConfig,
+        client_rpc::RpcConfig,
+        endpoint::{Endpoint, EndpointAuth},
+        http_client::QueryClientConfig,
+        QueryError,
+    },
+    metrics::{self, serve_metrics},
+    networks::{set_network_registry, NetworkRegistry},
     wallet_address, GraphcastIdentity, NoncesMap,
 };
 
+pub mod identity_cache;
 pub mod message_typing;
+pub mod registry_snapshot;
 pub mod waku_handling;
 
 /// A constant defining a message expiration limit.
@@ -79,6 +94,43 @@ pub struct GraphcastAgentConfig {
     pub discv5_port: Option<u16>,
     dns_discovery_urls: Vec<String>,
     dns_discovery_nameserver: Option<String>,
+    /// Authentication for registry subgraph queries, such as a gateway API key
+    pub registry_subgraph_auth: EndpointAuth,
+    /// Registry contract read over JSON-RPC in place of the registry subgraph
+    pub registry_contract: Option<RegistryContractConfig>,
+    /// Authentication for network subgraph queries, such as a gateway API key
+    pub network_subgraph_auth: EndpointAuth,
+    /// Network subgraphs of each protocol layer, resolving identities in place of
+    /// `network_subgraph` when not empty
+    pub network_subgraphs: Vec<NetworkSubgraph>,
+    /// How identities are resolved across `network_subgraphs`
+    pub network_resolution: NetworkResolution,
+    /// Authentication for graph node status queries
+    pub graph_node_auth: EndpointAuth,
+    /// Status endpoints of other index or query nodes, merged with `graph_node_endpoint`
+    pub additional_graph_node_endpoints: Vec<Endpoint>,
+    /// Epoch block oracle subgraph mapping protocol epochs to the blocks of each network
+    pub epoch_block_oracle: Option<Endpoint>,
+    /// Prometheus server exposing local indexer metrics
+    pub prometheus_endpoint: Option<String>,
+    /// Authentication for Prometheus queries
+    pub prometheus_auth: EndpointAuth,
+    /// JSON-RPC providers by network, used with graph node for block hashes and chainheads
+    pub rpc_config: RpcConfig,
+    /// Timeouts and retry policy for subgraph and graph node queries
+    pub query_client_config: QueryClientConfig,
+    /// Lifetimes and size of the sender identity verification cache
+    pub identity_cache_config: IdentityCacheConfig,
+    /// Data source used instead of the registry, network subgraph and graph node endpoints
+    pub data_source: Option<SharedDataSource>,
+    /// Keep a local snapshot of registrations and indexer stakes for identity checks
+    pub registry_snapshot_config: Option<RegistrySnapshotConfig>,
+    /// Supported networks, such as the built-in networks extended with
+    /// `NetworkRegistry::with_file`. Replaces the process-wide registry when set
+    pub network_registry: Option<NetworkRegistry>,
+    /// Address of the Prometheus exporter serving the agent's and radio's metrics at
+    /// `/metrics`, not served when unset
+    pub metrics_address: Option<SocketAddr>,
 }
 
 impl GraphcastAgentConfig {
@@ -130,15 +182,81 @@ impl GraphcastAgentConfig {
             discv5_port,
             dns_discovery_urls,
             dns_discovery_nameserver,
+            registry_subgraph_auth: EndpointAuth::default(),
+            network_subgraph_auth: EndpointAuth::default(),
+            registry_contract: None,
+            network_subgraphs: vec![],
+            network_resolution: NetworkResolution::default(),
+            graph_node_auth: EndpointAuth::default(),
+            additional_graph_node_endpoints: vec![],
+            epoch_block_oracle: None,
+            prometheus_endpoint: None,
+            prometheus_auth: EndpointAuth::default(),
+            rpc_config: RpcConfig::default(),
+            query_client_config: QueryClientConfig::default(),
+            identity_cache_config: IdentityCacheConfig::default(),
+            data_source: None,
+            registry_snapshot_config: None,
+            network_registry: None,
+            metrics_address: None,
         };
 
-        if let Err(e) = config.validate_set_up().await {
-            panic!("Could not validate the supplied configurations: {e}")
-        }
-
         Ok(config)
     }
 
+    /// Build the callbook used to query the configured subgraphs and graph node
+    pub fn callbook(&self) -> CallBook {
+        let callbook = CallBook::new(
+            self.registry_subgraph.clone(),
+            self.network_subgraph.clone(),
+            self.graph_node_endpoint.clone(),
+        )
+        .with_graphcast_registry_auth(self.registry_subgraph_auth.clone())
+        .with_graph_network_auth(self.network_subgraph_auth.clone())
+        .with_graph_node_auth(self.graph_node_auth.clone())
+        .with_network_resolution(self.network_resolution)
+        .with_rpc_config(self.rpc_config.clone())
+        .with_query_client_config(self.query_client_config.clone())
+        .with_identity_cache(IdentityCache::new(self.identity_cache_config.clone()));
+        let callbook = match &self.registry_contract {
+            Some(registry_contract) => callbook.with_registry_contract(registry_contract.clone()),
+            None => callbook,
+        };
+        let callbook = self
+            .network_subgraphs
+            .iter()
+            .cloned()
+            .fold(callbook, CallBook::with_network_subgraph);
+        let callbook = self
+            .additional_graph_node_endpoints
+            .iter()
+            .cloned()
+            .fold(callbook, CallBook::with_additional_graph_node);
+        let callbook = match &self.epoch_block_oracle {
+            Some(endpoint) => callbook.with_epoch_block_oracle(endpoint.clone()),
+            None => callbook,
+        };
+        let callbook = match &self.prometheus_endpoint {
+            Some(url) => callbook.with_prometheus(
+                Endpoint::new(url.clone()).with_auth(self.prometheus_auth.clone()),
+            ),
+            None => callbook,
+        };
+        let callbook = match &self.data_source {
+            Some(data_source) => callbook.with_data_source(data_source.clone()),
+            None => callbook,
+        };
+        match &self.registry_snapshot_config {
+            Some(config) => {
+                callbook.with_registry_snapshot(RegistrySnapshotStore::new(config.clone()))
+            }
+            None => callbook,
+        }
+    }
+
+    /// Check the local identity against the configured id validation and that graph node
+    /// serves indexing statuses. Run by `GraphcastAgent::new`, once the authentication and
+    /// other endpoint fields have been set on the config
     pub async fn validate_set_up(&self) -> Result<(), ConfigError> {
         let wallet = build_wallet(&self.wallet_key).map_err(|e| {
             ConfigError::ValidateInput(format!(
@@ -147,16 +265,10 @@ impl GraphcastAgentConfig {
         })?;
         let graphcast_id = wallet_address(&wallet);
         let account = Account::new(graphcast_id, self.graph_account.clone());
+        let callbook = self.callbook();
 
         // Check if messages sent by configured wallet and graph_account will pass the configured id validation
-        match account
-            .verify(
-                &self.network_subgraph,
-                &self.registry_subgraph,
-                &self.id_validation,
-            )
-            .await
-        {
+        match account.verify(&callbook, &self.id_validation).await {
             Ok(a) => debug!(
                 account = tracing::field::debug(&a),
                 id_validation = tracing::field::debug(&self.id_validation),
@@ -165,11 +277,11 @@ impl GraphcastAgentConfig {
             Err(e) => warn!(
                 err = tracing::field::debug(&e),
                 id_validation = tracing::field::debug(&self.id_validation),
-                "Identity used by local sender can not be verified"
+                "Identity used by local sender can not be verified, register the Graphcast ID with `register-graphcast-id` if it is not registered yet"
             ),
         };
-        if let Some(graph_node) = &self.graph_node_endpoint {
-            let _ = get_indexing_statuses(graph_node).await.map_err(|e| {
+        if self.graph_node_endpoint.is_some() || self.data_source.is_some() {
+            let _ = callbook.indexing_statuses().await.map_err(|e| {
                 ConfigError::ValidateInput(format!(
                     "Graph node endpoint must be able to serve indexing statuses query: {e}"
                 ))
@@ -252,6 +364,9 @@ impl GraphcastAgent {
     /// use default values. Similarly, if the `graphcast_namespace` field is not provided, the agent
     /// will default to using the IPFS hashes of the subgraphs that the indexer is allocating to.
     ///
+    /// The configuration is validated with `GraphcastAgentConfig::validate_set_up` first,
+    /// returning `GraphcastAgentError::ConfigValidation` if graph node cannot be queried.
+    ///
     /// # Examples
     ///
     /// ```ignore
@@ -276,13 +391,18 @@ impl GraphcastAgent {
     /// let agent = GraphcastAgent::new(config).await?;
     /// ```
     pub async fn new(
-        GraphcastAgentConfig {
+        config: GraphcastAgentConfig,
+        sender: Sender<WakuMessage>,
+    ) -> Result<GraphcastAgent, GraphcastAgentError> {
+        config.validate_set_up().await?;
+        let callbook = config.callbook();
+        if let Some(store) = callbook.registry_snapshot() {
+            store.spawn_refresh(callbook.clone());
+        }
+        let GraphcastAgentConfig {
             wallet_key,
             graph_account,
             radio_name,
-            registry_subgraph,
-            network_subgraph,
-            graph_node_endpoint,
             boot_node_addresses,
             graphcast_namespace,
             subtopics,
@@ -296,10 +416,17 @@ impl GraphcastAgent {
             id_validation,
             dns_discovery_nameserver,
             dns_discovery_urls,
-        }: GraphcastAgentConfig,
-        sender: Sender<WakuMessage>,
-    ) -> Result<GraphcastAgent, GraphcastAgentError> {
+            network_registry,
+            metrics_address,
+            ..
+        } = config;
         let graphcast_identity = GraphcastIdentity::new(wallet_key, graph_account.clone()).await?;
+        if let Some(network_registry) = network_registry {
+            set_network_registry(network_registry);
+        }
+        if let Some(address) = metrics_address {
+            serve_metrics(address);
+        }
         let pubsub_topic: WakuPubSubTopic = pubsub_topic(graphcast_namespace.as_deref());
 
         let host = waku_host.as_deref();
@@ -338,8 +465,6 @@ impl GraphcastAgent {
                 .expect("Could not connect and subscribe to the subtopics");
         }
 
-        let callbook = CallBook::new(registry_subgraph, network_subgraph, graph_node_endpoint);
-
         let seen_msg_ids = Arc::new(SyncMutex::new(HashSet::new()));
         let content_topics = Arc::new(SyncMutex::new(content_topics));
         register_handler(sender, seen_msg_ids.clone(), content_topics.clone())
@@ -363,6 +488,9 @@ impl GraphcastAgent {
     pub fn stop(self) -> Result<(), GraphcastAgentError> {
         trace!("Set an empty event callback");
         waku_set_event_callback(|_| {});
+        if let Some(registry_snapshot) = self.callbook.registry_snapshot() {
+            registry_snapshot.stop_refresh();
+        }
         debug!("Stop Waku gossip node");
         self.node_handle
             .stop()
@@ -485,7 +613,9 @@ impl GraphcastAgent {
         .map_err(GraphcastAgentError::WakuNodeError)
         .map(|id| {
             self.seen_msg_ids.lock().unwrap().insert(id.clone());
+            metrics::MESSAGES_SENT.inc();
             trace!(id = id, "Sent message");
+            self.refresh_connected_peers();
             id
         })
     }
@@ -529,7 +659,8 @@ impl GraphcastAgent {
             .collect())
     }
 
-    /// Check for peer connectivity, try to reconnect if there are disconnected peers
+    /// Check for peer connectivity, try to reconnect if there are disconnected peers,
+    /// and refresh `metrics::CONNECTED_PEERS`
     pub fn network_check(&self) -> Result<(), WakuHandlingError> {
         let peers = self.peers_data()?;
 
@@ -553,18 +684,31 @@ impl GraphcastAgent {
                     .unwrap();
             }
         }
+        self.refresh_connected_peers();
         Ok(())
     }
 
-    /// Get connected peers
+    /// Update `metrics::CONNECTED_PEERS` when the peers can be read
+    fn refresh_connected_peers(&self) {
+        if let Err(e) = self.connected_peer_count() {
+            trace!(
+                err = tracing::field::debug(&e),
+                "Could not count connected peers"
+            );
+        }
+    }
+
+    /// Get connected peers, updating `metrics::CONNECTED_PEERS`
     pub fn connected_peer_count(&self) -> Result<usize, WakuHandlingError> {
-        Ok(self
+        let count = self
             .peers_data()?
             .into_iter()
             // filter for nodes that are not self and disconnected
             .filter(|peer| peer.connected())
             .collect::<Vec<WakuPeerData>>()
-            .len())
+            .len();
+        metrics::CONNECTED_PEERS.set(count as i64);
+        Ok(count)
     }
 }
 
@@ -0,0 +1,471 @@
+//! Local snapshot of the Graphcast registry and indexer stakes.
+//!
+//! Instead of resolving every sender with a registry and a network subgraph query,
+//! the agent can keep a full index of Graphcast ID registrations and indexer stakes,
+//! refreshed periodically with paginated queries. Identity checks are then answered
+//! in memory and keep working through short gateway outages, as long as the snapshot
+//! is younger than the configured staleness limit. The snapshot can be persisted to
+//! disk so that a restarted agent does not start from an empty index.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as SyncMutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{debug, trace, warn};
+
+use crate::callbook::CallBook;
+use crate::graphql::client_network::query_indexer_stakes;
+use crate::graphql::client_registry::query_all_graphcast_ids;
+use crate::graphql::client_registry_contract::RegistryContractReader;
+use crate::graphql::endpoint::Endpoint;
+use crate::graphql::http_client::QueryClient;
+use crate::graphql::{GrtAmount, QueryError};
+
+/// Refresh schedule, staleness limit and persistence of the registry snapshot
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistrySnapshotConfig {
+    /// Interval between two refreshes of the snapshot
+    pub refresh_interval: Duration,
+    /// Snapshots older than this are not used; lookups fall back to live queries
+    pub max_staleness: Duration,
+    /// Number of entities requested per page
+    pub page_size: usize,
+    /// File the snapshot is loaded from at startup and written to after each refresh
+    pub path: Option<PathBuf>,
+}
+
+impl Default for RegistrySnapshotConfig {
+    fn default() -> Self {
+        RegistrySnapshotConfig {
+            refresh_interval: Duration::from_secs(300),
+            max_staleness: Duration::from_secs(1800),
+            page_size: 1000,
+            path: None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RegistrySnapshotError {
+    #[error(transparent)]
+    Query(#[from] QueryError),
+    #[error("Could not access the registry snapshot file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse the registry snapshot file: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// Full index of Graphcast ID registrations and indexer stakes at a point in time
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RegistrySnapshot {
+    /// Lowercased Graphcast ID -> lowercased indexer address
+    pub graphcast_ids: HashMap<String, String>,
+    /// Lowercased indexer address -> staked tokens
+    pub indexer_stakes: HashMap<String, GrtAmount>,
+    pub minimum_indexer_stake: GrtAmount,
+    /// Unix timestamp in seconds at which the snapshot was queried
+    pub updated_at: u64,
+}
+
+impl RegistrySnapshot {
+    /// Query the registry and network subgraphs for all registrations and stakes
+    pub async fn fetch(
+        client: &QueryClient,
+        registry_subgraph: &Endpoint,
+        network_subgraph: &Endpoint,
+        page_size: usize,
+    ) -> Result<Self, QueryError> {
+        let updated_at = unix_now();
+        let graphcast_ids =
+            query_all_graphcast_ids(client, registry_subgraph, page_size.max(1)).await?;
+        Self::with_stakes(
+            client,
+            graphcast_ids,
+            network_subgraph,
+            page_size,
+            updated_at,
+        )
+        .await
+    }
+
+    /// Read all registrations from the registry contract, and query the network subgraph
+    /// for stakes
+    pub async fn fetch_from_contract(
+        client: &QueryClient,
+        registry_contract: &RegistryContractReader,
+        network_subgraph: &Endpoint,
+        page_size: usize,
+    ) -> Result<Self, QueryError> {
+        let updated_at = unix_now();
+        let graphcast_ids = registry_contract.graphcast_ids(client).await?;
+        Self::with_stakes(
+            client,
+            graphcast_ids,
+            network_subgraph,
+            page_size,
+            updated_at,
+        )
+        .await
+    }
+
+    async fn with_stakes(
+        client: &QueryClient,
+        graphcast_ids: HashMap<String, String>,
+        network_subgraph: &Endpoint,
+        page_size: usize,
+        updated_at: u64,
+    ) -> Result<Self, QueryError> {
+        let (indexer_stakes, graph_network) =
+            query_indexer_stakes(client, network_subgraph, page_size.max(1)).await?;
+        Ok(RegistrySnapshot {
+            graphcast_ids,
+            indexer_stakes,
+            minimum_indexer_stake: graph_network.minimum_indexer_stake,
+            updated_at,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RegistrySnapshotError> {
+        let content = fs::read(path)?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    /// Write the snapshot through a temporary file so a crash never leaves a partial file
+    pub fn save(&self, path: &Path) -> Result<(), RegistrySnapshotError> {
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Time elapsed since the snapshot was queried
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(unix_now().saturating_sub(self.updated_at))
+    }
+
+    /// Indexer address registered for the Graphcast ID
+    pub fn registered_indexer(&self, graphcast_id: &str) -> Option<&str> {
+        self.graphcast_ids
+            .get(&graphcast_id.to_lowercase())
+            .map(String::as_str)
+    }
+
+    pub fn indexer_stake(&self, indexer_address: &str) -> Option<&GrtAmount> {
+        self.indexer_stakes.get(&indexer_address.to_lowercase())
+    }
+
+    /// Whether the indexer meets the minimum stake, `None` if the indexer is not in the snapshot
+    pub fn stake_satisfy_requirement(&self, indexer_address: &str) -> Option<bool> {
+        self.indexer_stake(indexer_address)
+            .map(|stake| stake >= &self.minimum_indexer_stake)
+    }
+}
+
+/// Shared registry snapshot, cheap to clone and shared between all clones
+#[derive(Clone, Debug, Default)]
+pub struct RegistrySnapshotStore {
+    config: RegistrySnapshotConfig,
+    snapshot: Arc<RwLock<Option<RegistrySnapshot>>>,
+    refresh_task: Arc<SyncMutex<Option<JoinHandle<()>>>>,
+}
+
+impl RegistrySnapshotStore {
+    /// Create a store, loading the persisted snapshot if the configured file exists
+    pub fn new(config: RegistrySnapshotConfig) -> Self {
+        let snapshot = config
+            .path
+            .as_deref()
+            .filter(|path| path.exists())
+            .and_then(|path| match RegistrySnapshot::load(path) {
+                Ok(snapshot) => {
+                    debug!(
+                        path = tracing::field::debug(path),
+                        age = tracing::field::debug(snapshot.age()),
+                        "Loaded registry snapshot"
+                    );
+                    Some(snapshot)
+                }
+                Err(e) => {
+                    warn!(
+                        path = tracing::field::debug(path),
+                        err = tracing::field::debug(&e),
+                        "Could not load registry snapshot, start from an empty one"
+                    );
+                    None
+                }
+            });
+        RegistrySnapshotStore {
+            config,
+            snapshot: Arc::new(RwLock::new(snapshot)),
+            refresh_task: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> &RegistrySnapshotConfig {
+        &self.config
+    }
+
+    /// Copy of the current snapshot regardless of its age
+    pub fn snapshot(&self) -> Option<RegistrySnapshot> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    /// Replace the current snapshot, persisting it when a file is configured. A failed
+    /// save is logged, the new snapshot is still used
+    pub fn replace(&self, snapshot: RegistrySnapshot) {
+        if let Some(path) = &self.config.path {
+            if let Err(e) = snapshot.save(path) {
+                warn!(
+                    path = tracing::field::debug(path),
+                    err = tracing::field::debug(&e),
+                    "Could not persist registry snapshot"
+                );
+            }
+        }
+        *self.snapshot.write().unwrap() = Some(snapshot);
+    }
+
+    /// Run the lookup against the snapshot if there is one within the staleness limit
+    pub fn fresh<R>(&self, lookup: impl FnOnce(&RegistrySnapshot) -> R) -> Option<R> {
+        let snapshot = self.snapshot.read().unwrap();
+        match snapshot.as_ref() {
+            Some(s) if s.age() <= self.config.max_staleness => Some(lookup(s)),
+            Some(s) => {
+                trace!(
+                    age = tracing::field::debug(s.age()),
+                    "Registry snapshot is stale"
+                );
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        self.fresh(|_| ()).is_some()
+    }
+
+    /// Query a new snapshot through the callbook's endpoints and replace the current one.
+    /// Registrations are read from the registry contract when the callbook has one configured
+    pub async fn refresh(&self, callbook: &CallBook) -> Result<(), RegistrySnapshotError> {
+        let snapshot = match callbook.registry_contract() {
+            Some(registry_contract) => {
+                RegistrySnapshot::fetch_from_contract(
+                    callbook.query_client(),
+                    registry_contract,
+                    callbook.graph_network(),
+                    self.config.page_size,
+                )
+                .await?
+            }
+            None => {
+                RegistrySnapshot::fetch(
+                    callbook.query_client(),
+                    callbook.graphcast_registry(),
+                    callbook.graph_network(),
+                    self.config.page_size,
+                )
+                .await?
+            }
+        };
+        debug!(
+            registrations = snapshot.graphcast_ids.len(),
+            indexers = snapshot.indexer_stakes.len(),
+            "Refreshed registry snapshot"
+        );
+        self.replace(snapshot);
+        Ok(())
+    }
+
+    /// Refresh the snapshot every `refresh_interval` in a background task, replacing
+    /// any previously started one. Failed refreshes keep the last snapshot
+    pub fn spawn_refresh(&self, callbook: CallBook) {
+        let store = self.clone();
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(store.config.refresh_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if let Err(e) = store.refresh(&callbook).await {
+                    warn!(
+                        err = tracing::field::debug(&e),
+                        "Could not refresh registry snapshot, keep the last one"
+                    );
+                }
+            }
+        });
+        if let Some(previous) = self.refresh_task.lock().unwrap().replace(handle) {
+            previous.abort();
+        }
+    }
+
+    /// Stop the background refresh task, if any
+    pub fn stop_refresh(&self) {
+        if let Some(handle) = self.refresh_task.lock().unwrap().take() {
+            handle.abort();
+        }
+    }
+}
+
+impl PartialEq for RegistrySnapshotStore {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.snapshot, &other.snapshot)
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::post, Json, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::data_source::FixtureDataSource;
+    use crate::graphql::http_client::tests::spawn_stub;
+
+    fn snapshot(updated_at: u64) -> RegistrySnapshot {
+        RegistrySnapshot {
+            graphcast_ids: HashMap::from([("0xgraphcastid".to_string(), "0xindexer".to_string())]),
+            indexer_stakes: HashMap::from([(
+                "0xindexer".to_string(),
+                GrtAmount::from_grt(100_000),
+            )]),
+            minimum_indexer_stake: GrtAmount::from_grt(100_000),
+            updated_at,
+        }
+    }
+
+    /// Stub subgraph serving `entities` in pages, keyed by the `id_gt` cursor
+    fn page(entities: &[Value], body: &Value) -> Vec<Value> {
+        let first = body["variables"]["first"].as_u64().unwrap() as usize;
+        let last_id = body["variables"]["lastId"].as_str().unwrap();
+        entities
+            .iter()
+            .filter(|e| e["id"].as_str().unwrap() > last_id)
+            .take(first)
+            .cloned()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_fetch_pages() {
+        let mut registrations: Vec<Value> = (0..5)
+            .map(|i| json!({"id": format!("0x0{i}"), "indexer": format!("0xIndexer{i}"), "graphcastID": format!("0xId{i}"), "blockNumber": "100"}))
+            .collect();
+        // Indexer 1 replaced its Graphcast ID in a later block, in an entity of lower id
+        registrations[1]["blockNumber"] = json!("200");
+        registrations[1]["graphcastID"] = json!("0xNewId1");
+        registrations.push(json!({"id": "0x05", "indexer": "0xIndexer1", "graphcastID": "0xId1", "blockNumber": "150"}));
+        let indexers: Vec<Value> = (0..3)
+            .map(|i| json!({"id": format!("0xindexer{i}"), "stakedTokens": "100000000000000000000000"}))
+            .collect();
+        let app = Router::new()
+            .route(
+                "/registry",
+                post(move |Json(body): Json<Value>| async move {
+                    Json(json!({"data": {"graphcast_ids": page(&registrations, &body)}}))
+                }),
+            )
+            .route(
+                "/network",
+                post(move |Json(body): Json<Value>| async move {
+                    Json(json!({"data": {
+                        "indexers": page(&indexers, &body),
+                        "graphNetwork": {"minimumIndexerStake": "100000000000000000000000"}
+                    }}))
+                }),
+            );
+        let url = spawn_stub(app).await;
+
+        let snapshot = RegistrySnapshot::fetch(
+            &QueryClient::default(),
+            &Endpoint::new(format!("{url}/registry")),
+            &Endpoint::new(format!("{url}/network")),
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(snapshot.graphcast_ids.len(), 5);
+        assert_eq!(snapshot.registered_indexer("0xID4"), Some("0xindexer4"));
+        assert_eq!(snapshot.registered_indexer("0xNewId1"), Some("0xindexer1"));
+        assert_eq!(snapshot.registered_indexer("0xId1"), None);
+        assert_eq!(snapshot.indexer_stakes.len(), 3);
+        assert_eq!(snapshot.stake_satisfy_requirement("0xindexer2"), Some(true));
+        assert_eq!(snapshot.stake_satisfy_requirement("0xindexer3"), None);
+    }
+
+    #[test]
+    fn test_persist_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("registry.json");
+        let store = RegistrySnapshotStore::new(RegistrySnapshotConfig {
+            path: Some(path.clone()),
+            ..Default::default()
+        });
+        assert!(store.snapshot().is_none());
+        store.replace(snapshot(unix_now()));
+
+        let reloaded = RegistrySnapshotStore::new(RegistrySnapshotConfig {
+            path: Some(path),
+            ..Default::default()
+        });
+        assert_eq!(reloaded.snapshot(), store.snapshot());
+        assert!(reloaded.is_fresh());
+
+        // A snapshot that cannot be saved is still used
+        let unwritable = RegistrySnapshotStore::new(RegistrySnapshotConfig {
+            path: Some(dir.path().join("missing").join("registry.json")),
+            ..Default::default()
+        });
+        unwritable.replace(snapshot(unix_now()));
+        assert!(unwritable.is_fresh());
+    }
+
+    #[tokio::test]
+    async fn test_callbook_staleness_limit() {
+        let store = RegistrySnapshotStore::new(RegistrySnapshotConfig {
+            max_staleness: Duration::from_secs(60),
+            ..Default::default()
+        });
+        let fallback = FixtureDataSource::new()
+            .with_registered_indexer("0xGraphcastId", "0xLiveIndexer")
+            .with_registered_indexer("0xUnknownToSnapshot", "0xLiveIndexer");
+        let callbook = CallBook::new(String::new(), String::new(), None)
+            .with_data_source(Arc::new(fallback))
+            .with_registry_snapshot(store.clone());
+
+        store.replace(snapshot(unix_now()));
+        assert_eq!(
+            callbook.registered_indexer("0xGraphcastId").await.unwrap(),
+            "0xindexer"
+        );
+        // Registrations made since the last refresh are resolved live
+        assert_eq!(
+            callbook
+                .registered_indexer("0xUnknownToSnapshot")
+                .await
+                .unwrap(),
+            "0xliveindexer"
+        );
+        assert!(callbook
+            .stake_satisfy_requirement("0xindexer")
+            .await
+            .unwrap());
+
+        store.replace(snapshot(unix_now() - 120));
+        assert_eq!(
+            callbook.registered_indexer("0xGraphcastId").await.unwrap(),
+            "0xliveindexer"
+        );
+    }
+}
@@ -11,7 +11,7 @@ use waku::{
     WakuNodeConfig, WakuNodeHandle, WakuPeerData, WakuPubSubTopic,
 };
 
-use crate::{app_name, cf_nameserver, discovery_url, graphql::QueryError};
+use crate::{app_name, cf_nameserver, discovery_url, graphql::QueryError, metrics};
 
 pub const SDK_VERSION: &str = "0";
 
@@ -400,10 +400,12 @@ pub fn handle_signal(
         waku::Event::WakuMessage(event) => {
             let msg_id = event.message_id();
             trace!(msg_id, "Received message id",);
+            metrics::MESSAGES_RECEIVED.inc();
             let mut ids = seen_msg_ids.lock().unwrap();
             // Check if message has been received before or sent from local node
             if ids.contains(msg_id) {
                 trace!(msg_id, "Skip repeated message");
+                metrics::MESSAGES_DUPLICATE.inc();
                 return Err(WakuHandlingError::InvalidMessage(format!(
                     "Skip repeated message: {:#?}",
                     msg_id
@@ -417,6 +419,7 @@ pub fn handle_signal(
                     topic = tracing::field::debug(content_topic),
                     "Skip irrelevant content topic"
                 );
+                metrics::MESSAGES_IRRELEVANT_TOPIC.inc();
                 return Err(WakuHandlingError::InvalidMessage(format!(
                     "Skip irrelevant content topic: {:#?}",
                     content_topic
@@ -0,0 +1,249 @@
+//! Protocol epochs from the network subgraph, and the block each epoch starts at on
+//! every network from the epoch block oracle subgraph, so radios can align messages
+//! and rounds to epochs instead of block intervals.
+
+use std::collections::HashMap;
+
+use graphql_client::{GraphQLQuery, Response};
+use serde_derive::{Deserialize, Serialize};
+use tracing::trace;
+
+use super::{endpoint::Endpoint, http_client::QueryClient, QueryError};
+use crate::networks::{NetworkName, NETWORKS};
+use crate::EpochPointer;
+
+type BigInt = String;
+
+/// Derived GraphQL query for the current epoch of the network subgraph
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema_network.graphql",
+    query_path = "src/graphql/query_current_epoch.graphql",
+    response_derives = "Debug, Serialize, Deserialize"
+)]
+pub struct CurrentEpoch;
+
+/// Derived GraphQL query for the epoch start blocks of the epoch block oracle subgraph
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema_epoch_block_oracle.graphql",
+    query_path = "src/graphql/query_epoch_blocks.graphql",
+    response_derives = "Debug, Serialize, Deserialize"
+)]
+pub struct EpochBlocks;
+
+/// Current protocol epoch, with block numbers of the chain the protocol is deployed on
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolEpoch {
+    pub number: u64,
+    /// Blocks per epoch
+    pub length: u64,
+    /// Block the epoch started at
+    pub start_block: u64,
+}
+
+impl ProtocolEpoch {
+    /// First block of the next epoch
+    pub fn end_block(&self) -> u64 {
+        self.start_block + self.length
+    }
+
+    /// Epoch of a protocol chain block, assuming the epoch length does not change
+    pub fn epoch_at(&self, block_number: u64) -> u64 {
+        if block_number >= self.start_block {
+            self.number + (block_number - self.start_block) / self.length.max(1)
+        } else {
+            let behind = (self.start_block - block_number).div_ceil(self.length.max(1));
+            self.number.saturating_sub(behind)
+        }
+    }
+}
+
+/// Query the network subgraph for the current epoch and epoch length
+pub async fn query_current_epoch(
+    client: &QueryClient,
+    endpoint: &Endpoint,
+) -> Result<ProtocolEpoch, QueryError> {
+    let request_body = CurrentEpoch::build_query(current_epoch::Variables {});
+    let response = client.post_json(endpoint, &request_body).await?;
+    let response_body: Response<current_epoch::ResponseData> = response.json().await?;
+    trace!(
+        endpoint = %endpoint,
+        result = tracing::field::debug(&response_body),
+        "Queried current epoch"
+    );
+    if let Some(errors) = response_body.errors.as_deref() {
+        return Err(QueryError::Other(anyhow::anyhow!("{}", errors[0].message)));
+    }
+    let network = response_body
+        .data
+        .ok_or(QueryError::ParseResponseError(String::from(
+            "Missing epoch data from network subgraph",
+        )))?
+        .graph_network;
+    let number = network.current_epoch as u64;
+    let length = network.epoch_length as u64;
+    // Epochs have been `length` blocks long since the last length update
+    let start_block = network.last_length_update_block as u64
+        + number.saturating_sub(network.last_length_update_epoch as u64) * length;
+    Ok(ProtocolEpoch {
+        number,
+        length,
+        start_block,
+    })
+}
+
+/// Query the epoch block oracle subgraph for the block `epoch` started at on each
+/// network. Networks are named as in the network registry when their CAIP-2 id is
+/// registered, and by the oracle's alias otherwise
+pub async fn query_epoch_blocks(
+    client: &QueryClient,
+    endpoint: &Endpoint,
+    epoch: u64,
+) -> Result<HashMap<NetworkName, EpochPointer>, QueryError> {
+    let request_body = EpochBlocks::build_query(epoch_blocks::Variables {
+        epoch: epoch.to_string(),
+    });
+    let response = client.post_json(endpoint, &request_body).await?;
+    let response_body: Response<epoch_blocks::ResponseData> = response.json().await?;
+    trace!(
+        endpoint = %endpoint,
+        epoch,
+        result = tracing::field::debug(&response_body),
+        "Queried epoch blocks"
+    );
+    if let Some(errors) = response_body.errors.as_deref() {
+        return Err(QueryError::Other(anyhow::anyhow!("{}", errors[0].message)));
+    }
+    let blocks = response_body
+        .data
+        .and_then(|data| data.epoch)
+        .ok_or(QueryError::ParseResponseError(format!(
+            "No block numbers for epoch {epoch} from epoch block oracle"
+        )))?
+        .block_numbers;
+
+    let registry = NETWORKS.read().unwrap();
+    blocks
+        .into_iter()
+        .map(|block| {
+            let start_block = block.block_number.parse::<u64>().map_err(|_| {
+                QueryError::ParseResponseError(format!(
+                    "Invalid epoch block number {}",
+                    block.block_number
+                ))
+            })?;
+            let network = registry
+                .by_caip2(&block.network.id)
+                .map(|n| n.name.clone())
+                .unwrap_or(NetworkName::from(block.network.alias));
+            Ok((network, EpochPointer::new(epoch, start_block)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::post, Json, Router};
+    use serde_json::{json, Value};
+
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::callbook::CallBook;
+    use crate::data_source::FixtureDataSource;
+    use crate::graphql::http_client::tests::spawn_stub;
+
+    /// Stub serving the network subgraph and epoch block oracle on one endpoint
+    async fn stub_subgraphs() -> String {
+        let app = Router::new().route(
+            "/",
+            post(|Json(body): Json<Value>| async move {
+                let data = if body["query"].as_str().unwrap().contains("graphNetwork") {
+                    json!({"graphNetwork": {
+                        "currentEpoch": 105,
+                        "epochLength": 100,
+                        "lastLengthUpdateEpoch": 100,
+                        "lastLengthUpdateBlock": 5_000,
+                    }})
+                } else if body["variables"]["epoch"] == "105" {
+                    json!({"epoch": {
+                        "epochNumber": "105",
+                        "blockNumbers": [
+                            {"blockNumber": "18000000", "network": {"id": "eip155:1", "alias": "ethereum"}},
+                            {"blockNumber": "42", "network": {"id": "eip155:999999", "alias": "new-chain"}},
+                        ]
+                    }})
+                } else {
+                    json!({"epoch": null})
+                };
+                Json(json!({ "data": data }))
+            }),
+        );
+        let url = spawn_stub(app).await;
+        format!("{url}/")
+    }
+
+    #[test]
+    fn test_epoch_at() {
+        let epoch = ProtocolEpoch {
+            number: 10,
+            length: 100,
+            start_block: 1_000,
+        };
+        assert_eq!(epoch.end_block(), 1_100);
+        assert_eq!(epoch.epoch_at(1_099), 10);
+        assert_eq!(epoch.epoch_at(1_250), 12);
+        assert_eq!(epoch.epoch_at(999), 9);
+        assert_eq!(epoch.epoch_at(900), 9);
+    }
+
+    #[tokio::test]
+    async fn test_callbook_epochs() {
+        let url = stub_subgraphs().await;
+        let callbook = CallBook::new(String::new(), url.clone(), None)
+            .with_epoch_block_oracle(Endpoint::new(url));
+
+        let epoch = callbook.current_epoch().await.unwrap();
+        assert_eq!(
+            epoch,
+            ProtocolEpoch {
+                number: 105,
+                length: 100,
+                start_block: 5_500,
+            }
+        );
+
+        let blocks = callbook.epoch_blocks(epoch.number).await.unwrap();
+        assert_eq!(
+            blocks[&NetworkName::from("mainnet")],
+            EpochPointer::new(105, 18_000_000)
+        );
+        assert_eq!(
+            blocks[&NetworkName::from("new-chain")],
+            EpochPointer::new(105, 42)
+        );
+        assert!(matches!(
+            callbook.epoch_blocks(106).await,
+            Err(QueryError::ParseResponseError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_callbook_epochs_data_source() {
+        let epoch = ProtocolEpoch {
+            number: 7,
+            length: 6_646,
+            start_block: 46_522,
+        };
+        let callbook = CallBook::new(String::new(), String::new(), None).with_data_source(
+            Arc::new(FixtureDataSource::new().with_current_epoch(epoch.clone())),
+        );
+        assert_eq!(callbook.current_epoch().await.unwrap(), epoch);
+        // No epoch block oracle is configured
+        assert!(matches!(
+            callbook.epoch_blocks(7).await,
+            Err(QueryError::Other(_))
+        ));
+    }
+}
@@ -1,4 +1,12 @@
-use crate::{graphql::QueryError, Account};
+use crate::{
+    graphql::{
+        endpoint::Endpoint,
+        http_client::QueryClient,
+        pagination::{PageCursor, DEFAULT_PAGE_SIZE},
+        QueryError,
+    },
+    Account,
+};
 use graphql_client::{GraphQLQuery, Response};
 use tracing::trace;
 
@@ -13,7 +21,8 @@ pub struct GraphAccount;
 
 /// Query network subgraph for Graph account
 pub async fn query_graph_account(
-    url: &str,
+    client: &QueryClient,
+    url: &Endpoint,
     operator: &str,
     account: &str,
 ) -> Result<Account, QueryError> {
@@ -27,11 +36,7 @@ pub async fn query_graph_account(
         account_addr: account.to_string(),
     };
     let request_body = GraphAccount::build_query(variables);
-    let client = reqwest::Client::builder()
-        .user_agent("network-subgraph")
-        .build()?;
-    let request = client.post(url).json(&request_body);
-    let response = request.send().await?.error_for_status()?;
+    let response = client.post_json(url, &request_body).await?;
     let response_body: Response<graph_account::ResponseData> = response.json().await?;
     trace!(
         result = tracing::field::debug(&response_body),
@@ -80,79 +85,118 @@ pub async fn query_graph_account(
     Ok(account)
 }
 
-/// Query network subgraph for subgraph ownership account
-/// There could be operator relationship between subgraph owner and registered operator
-pub async fn owned_subgraphs(url: &str, account: &str) -> Result<Vec<String>, QueryError> {
-    let variables: graph_account::Variables = graph_account::Variables {
-        // Do not supply operator address if operator is already a graph account
-        operator_addr: vec![],
-        account_addr: account.to_string(),
-    };
-    let request_body = GraphAccount::build_query(variables);
-    let client = reqwest::Client::builder()
-        .user_agent("network-subgraph")
-        .build()?;
-    let request = client.post(url).json(&request_body);
-    let response = request.send().await?.error_for_status()?;
-    let response_body: Response<graph_account::ResponseData> = response.json().await?;
-    trace!(
-        result = tracing::field::debug(&response_body),
-        "Query result for graph network account"
-    );
-    if let Some(errors) = response_body.errors.as_deref() {
-        let e = &errors[0];
-        if e.message == "indexing_error" {
-            return Err(QueryError::IndexingError);
-        } else {
-            return Err(QueryError::Other(anyhow::anyhow!("{}", e.message)));
-        }
-    }
-    let data = response_body.data.ok_or_else(|| {
-        QueryError::ParseResponseError(format!(
-            "Missing response data from network subgraph for account {}",
-            account
-        ))
-    })?;
+/// Derived page of subgraphs owned by a Graph account
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema_graph_account.graphql",
+    query_path = "src/graphql/query_owned_subgraphs.graphql",
+    response_derives = "Debug, Serialize, Deserialize"
+)]
+pub struct OwnedSubgraphs;
 
-    let subgraphs: Vec<String> = data
-        .graph_accounts
-        .first()
-        .map(|x| {
-            x.subgraphs
-                .iter()
-                .map(|s| s.id.clone())
-                .collect::<Vec<String>>()
-        })
-        .ok_or_else(|| {
-            QueryError::ParseResponseError(String::from(
-                "Network subgraph does not have a match for graph account",
+/// Subgraph owned by a Graph account with its current deployment hash, if any
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedSubgraph {
+    pub id: String,
+    pub current_deployment: Option<String>,
+}
+
+/// Derived subgraph owned by a Graph account, filtered by subgraph id
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema_graph_account.graphql",
+    query_path = "src/graphql/query_owned_subgraph_by_id.graphql",
+    response_derives = "Debug, Serialize, Deserialize"
+)]
+pub struct OwnedSubgraphById;
+
+/// Query network subgraph for all subgraphs owned by the account, paging through them by id
+pub async fn query_owned_subgraphs(
+    client: &QueryClient,
+    url: &Endpoint,
+    account: &str,
+    page_size: usize,
+) -> Result<Vec<OwnedSubgraph>, QueryError> {
+    let mut subgraphs = vec![];
+    let mut cursor = PageCursor::new(page_size);
+    while let Some(last_id) = cursor.next_page() {
+        let variables = owned_subgraphs::Variables {
+            account_addr: account.to_string(),
+            first: cursor.first(),
+            last_id,
+        };
+        let request_body = OwnedSubgraphs::build_query(variables);
+        let response = client.post_json(url, &request_body).await?;
+        let response_body: Response<owned_subgraphs::ResponseData> = response.json().await?;
+        trace!(
+            result = tracing::field::debug(&response_body),
+            "Query result for graph network account subgraphs"
+        );
+        if let Some(errors) = response_body.errors.as_deref() {
+            let e = &errors[0];
+            if e.message == "indexing_error" {
+                return Err(QueryError::IndexingError);
+            } else {
+                return Err(QueryError::Other(anyhow::anyhow!("{}", e.message)));
+            }
+        }
+        let data = response_body.data.ok_or_else(|| {
+            QueryError::ParseResponseError(format!(
+                "Missing response data from network subgraph for account {}",
+                account
             ))
         })?;
+        let page = data
+            .graph_account
+            .ok_or_else(|| {
+                QueryError::ParseResponseError(String::from(
+                    "Network subgraph does not have a match for graph account",
+                ))
+            })?
+            .subgraphs;
 
+        cursor.advance(&page, |s| &s.id);
+        subgraphs.extend(page.into_iter().map(|s| OwnedSubgraph {
+            id: s.id,
+            current_deployment: s.current_version.map(|v| v.subgraph_deployment.ipfs_hash),
+        }));
+    }
     Ok(subgraphs)
 }
 
+/// Query network subgraph for subgraph ownership account
+/// There could be operator relationship between subgraph owner and registered operator
+pub async fn owned_subgraphs(
+    client: &QueryClient,
+    url: &Endpoint,
+    account: &str,
+) -> Result<Vec<String>, QueryError> {
+    Ok(
+        query_owned_subgraphs(client, url, account, DEFAULT_PAGE_SIZE)
+            .await?
+            .into_iter()
+            .map(|s| s.id)
+            .collect(),
+    )
+}
+
 /// Query network subgraph to get the latest subgraph deployment hash of a subgraph indexed by id
 pub async fn subgraph_hash_by_id(
-    url: &str,
+    client: &QueryClient,
+    url: &Endpoint,
     account: &str,
     subgraph_id: &str,
 ) -> Result<String, QueryError> {
-    let variables: graph_account::Variables = graph_account::Variables {
-        // Do not supply operator address if operator is already a graph account
-        operator_addr: vec![],
+    let variables = owned_subgraph_by_id::Variables {
         account_addr: account.to_string(),
+        subgraph_id: subgraph_id.to_string(),
     };
-    let request_body = GraphAccount::build_query(variables);
-    let client = reqwest::Client::builder()
-        .user_agent("network-subgraph")
-        .build()?;
-    let request = client.post(url).json(&request_body);
-    let response = request.send().await?.error_for_status()?;
-    let response_body: Response<graph_account::ResponseData> = response.json().await?;
+    let request_body = OwnedSubgraphById::build_query(variables);
+    let response = client.post_json(url, &request_body).await?;
+    let response_body: Response<owned_subgraph_by_id::ResponseData> = response.json().await?;
     trace!(
         result = tracing::field::debug(&response_body),
-        "Query result for graph network account"
+        "Query result for graph network account subgraph"
     );
     if let Some(errors) = response_body.errors.as_deref() {
         let e = &errors[0];
@@ -162,73 +206,107 @@ pub async fn subgraph_hash_by_id(
             return Err(QueryError::Other(anyhow::anyhow!("{}", e.message)));
         }
     }
-    let data = response_body.data.ok_or_else(|| {
-        QueryError::ParseResponseError(format!(
-            "Missing response data from network subgraph for account {}",
-            account
-        ))
-    })?;
-
-    let owned_subgraphs = if let Some(acc) = data.graph_accounts.first() {
-        &acc.subgraphs
-    } else {
-        return Err(QueryError::ParseResponseError(String::from(
-            "Network subgraph does not have match for graph account and operator",
-        )));
-    };
-
-    let entity =
-        if let Some(subgraph) = owned_subgraphs.iter().find(|s| s.id.clone() == subgraph_id) {
-            &subgraph.current_version
-        } else {
-            return Err(QueryError::ParseResponseError(String::from(
-                "Network subgraph does not have subgraph id match for the owner",
-            )));
-        };
+    let entity = response_body
+        .data
+        .and_then(|data| data.graph_account)
+        .and_then(|account| account.subgraphs.into_iter().find(|s| s.id == subgraph_id))
+        .ok_or(QueryError::ParseResponseError(String::from(
+            "Network subgraph does not have subgraph id match for the owner",
+        )))?;
 
-    let hash = if let Some(hash) = entity
-        .as_ref()
-        .map(|v| v.subgraph_deployment.ipfs_hash.clone())
-    {
-        hash
-    } else {
-        return Err(QueryError::ParseResponseError(String::from(
+    entity
+        .current_version
+        .map(|v| v.subgraph_deployment.ipfs_hash)
+        .ok_or(QueryError::ParseResponseError(String::from(
             "Network subgraph does not have a deployment hash match for subgraph id",
-        )));
-    };
-
-    Ok(hash)
+        )))
 }
 
 #[cfg(test)]
 mod tests {
+    use axum::{routing::post, Json, Router};
+    use serde_json::{json, Value};
 
     use super::*;
+    use crate::graphql::http_client::tests::spawn_stub;
+
+    const ACCOUNT: &str = "0xe9a1cabd57700b17945fd81feefba82340d9568f";
+
+    /// Stub network subgraph where the account owns five subgraphs, the last one
+    /// without a current version, answering both the paged and the by-id queries
+    async fn stub_network_subgraph() -> Endpoint {
+        let subgraphs: Vec<Value> = (0..5)
+            .map(|i| {
+                let current_version = (i < 4).then(
+                    || json!({"subgraphDeployment": {"ipfsHash": format!("QmDeployment{i}")}}),
+                );
+                json!({"id": format!("Subgraph{i}"), "currentVersion": current_version})
+            })
+            .collect();
+        let app = Router::new().route(
+            "/",
+            post(move |Json(body): Json<Value>| async move {
+                let variables = &body["variables"];
+                assert_eq!(variables["account_addr"], ACCOUNT);
+                let page: Vec<Value> = match variables["subgraph_id"].as_str() {
+                    Some(subgraph_id) => subgraphs
+                        .iter()
+                        .filter(|s| s["id"] == subgraph_id)
+                        .cloned()
+                        .collect(),
+                    None => {
+                        let first = variables["first"].as_u64().unwrap() as usize;
+                        let last_id = variables["lastId"].as_str().unwrap();
+                        subgraphs
+                            .iter()
+                            .filter(|s| s["id"].as_str().unwrap() > last_id)
+                            .take(first)
+                            .cloned()
+                            .collect()
+                    }
+                };
+                Json(json!({"data": {"graphAccount": {"id": ACCOUNT, "subgraphs": page}}}))
+            }),
+        );
+        let url = spawn_stub(app).await;
+        Endpoint::new(format!("{url}/"))
+    }
 
     #[tokio::test]
     async fn test_owned_subgraphs() {
-        let network_subgraph =
-            "https://api.thegraph.com/subgraphs/name/graphprotocol/graph-network-goerli";
-        let account = "0xe9a1cabd57700b17945fd81feefba82340d9568f";
-        let owned_subgraphs = owned_subgraphs(network_subgraph, account).await;
-
-        assert!(owned_subgraphs.is_ok());
-        // Current subgraph number
-        assert!(owned_subgraphs.unwrap().len() > 5);
+        let network_subgraph = stub_network_subgraph().await;
+        let owned_subgraphs =
+            query_owned_subgraphs(&QueryClient::default(), &network_subgraph, ACCOUNT, 2)
+                .await
+                .unwrap();
+
+        assert_eq!(owned_subgraphs.len(), 5);
+        assert_eq!(
+            owned_subgraphs[3],
+            OwnedSubgraph {
+                id: String::from("Subgraph3"),
+                current_deployment: Some(String::from("QmDeployment3")),
+            }
+        );
+        assert_eq!(owned_subgraphs[4].current_deployment, None);
     }
 
     #[tokio::test]
     async fn test_subgraph_current_hash() {
-        let network_subgraph =
-            "https://api.thegraph.com/subgraphs/name/graphprotocol/graph-network-goerli";
-        let account = "0xe9a1cabd57700b17945fd81feefba82340d9568f";
-        let subgraph_id = "CnJMdCkW3pr619gsJVtUPAWxspALPdCMw6o7obzYBNp3";
-        let hash = subgraph_hash_by_id(network_subgraph, account, subgraph_id)
+        let network_subgraph = stub_network_subgraph().await;
+        let client = QueryClient::default();
+
+        let hash = subgraph_hash_by_id(&client, &network_subgraph, ACCOUNT, "Subgraph2")
             .await
             .unwrap();
-
-        assert!(hash.contains(&String::from(
-            "QmacQnSgia4iDPWHpeY6aWxesRFdb8o5DKZUx96zZqEWrB"
-        )));
+        assert_eq!(hash, "QmDeployment2");
+        assert!(matches!(
+            subgraph_hash_by_id(&client, &network_subgraph, ACCOUNT, "Subgraph4").await,
+            Err(QueryError::ParseResponseError(_))
+        ));
+        assert!(matches!(
+            subgraph_hash_by_id(&client, &network_subgraph, ACCOUNT, "Subgraph9").await,
+            Err(QueryError::ParseResponseError(_))
+        ));
     }
 }
@@ -1,6 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Debug;
 
-use crate::graphql::QueryError;
+use crate::graphql::{endpoint::Endpoint, http_client::QueryClient, QueryError};
 use crate::NetworkPointer;
 use crate::{networks::NetworkName, BlockPointer};
 use graphql_client::{GraphQLQuery, Response};
@@ -9,11 +10,19 @@ use tracing::{debug, trace, warn};
 
 use self::indexing_statuses::IndexingStatusesIndexingStatuses;
 
+/// Custom scalars of the graph node status schema
+type BigInt = String;
+type Bytes = String;
+type JSONObject = serde_json::Value;
+
+/// Number of deployments per `publicProofsOfIndexing` request
+pub const PUBLIC_POI_BATCH_SIZE: usize = 10;
+
 #[derive(GraphQLQuery, Serialize, Deserialize, Debug, Clone, Copy)]
 #[graphql(
     schema_path = "src/graphql/schema_graph_node.graphql",
     query_path = "src/graphql/query_indexing_statuses.graphql",
-    response_derives = "Debug, Serialize, Deserialize",
+    response_derives = "Debug, Clone, Serialize, Deserialize",
     normalization = "rust"
 )]
 pub struct IndexingStatuses;
@@ -26,25 +35,90 @@ pub struct IndexingStatuses;
 )]
 pub struct BlockHashFromNumber;
 
+#[derive(GraphQLQuery, Serialize, Deserialize, Debug)]
+#[graphql(
+    schema_path = "src/graphql/schema_graph_node.graphql",
+    query_path = "src/graphql/query_proof_of_indexing.graphql",
+    response_derives = "Debug, Serialize, Deserialize"
+)]
+pub struct ProofOfIndexing;
+
+#[derive(GraphQLQuery, Serialize, Deserialize, Debug)]
+#[graphql(
+    schema_path = "src/graphql/schema_graph_node.graphql",
+    query_path = "src/graphql/query_public_pois.graphql",
+    response_derives = "Debug, Serialize, Deserialize"
+)]
+pub struct PublicProofsOfIndexing;
+
+#[derive(GraphQLQuery, Serialize, Deserialize, Debug)]
+#[graphql(
+    schema_path = "src/graphql/schema_graph_node.graphql",
+    query_path = "src/graphql/query_entity_changes_in_block.graphql",
+    response_derives = "Debug, Serialize, Deserialize"
+)]
+pub struct EntityChangesInBlock;
+
+#[derive(GraphQLQuery, Serialize, Deserialize, Debug)]
+#[graphql(
+    schema_path = "src/graphql/schema_graph_node.graphql",
+    query_path = "src/graphql/query_subgraph_features.graphql",
+    response_derives = "Debug, Serialize, Deserialize"
+)]
+pub struct SubgraphFeaturesQuery;
+
+/// Public proof of indexing requested for a deployment at a block number
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicPoiRequest {
+    pub deployment: String,
+    pub block_number: u64,
+}
+
+/// Public proof of indexing of a deployment at a block
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicPoi {
+    pub deployment: String,
+    pub block_number: u64,
+    /// Hash of the block, if graph node has it
+    pub block_hash: Option<String>,
+    pub proof_of_indexing: String,
+}
+
+/// Entities written by a deployment in a block, keyed by entity type
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EntityChanges {
+    /// Entity type -> entities created or updated
+    pub updates: BTreeMap<String, Vec<serde_json::Value>>,
+    /// Entity type -> ids of the deleted entities
+    pub deletions: BTreeMap<String, Vec<String>>,
+}
+
+/// Features, data sources and handlers declared by a deployment's manifest
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubgraphFeatures {
+    pub api_version: Option<String>,
+    pub spec_version: String,
+    pub features: Vec<String>,
+    pub data_sources: Vec<String>,
+    pub handlers: Vec<String>,
+    pub network: Option<String>,
+}
+
 /// Query graph node for Block hash
 pub async fn perform_block_hash_from_number(
-    graph_node_endpoint: &str,
+    client: &QueryClient,
+    graph_node_endpoint: &Endpoint,
     variables: block_hash_from_number::Variables,
-) -> Result<reqwest::Response, reqwest::Error> {
+) -> Result<reqwest::Response, QueryError> {
     let request_body = BlockHashFromNumber::build_query(variables);
-    let client = reqwest::Client::new();
-    client
-        .post(graph_node_endpoint)
-        .json(&request_body)
-        .send()
-        .await?
-        .error_for_status()
+    client.post_json(graph_node_endpoint, &request_body).await
 }
 
 /// Construct GraphQL variables and parse result for Proof of Indexing.
 /// For other radio use cases, provide a function that returns a string
 pub async fn query_graph_node_network_block_hash(
-    graph_node_endpoint: &str,
+    client: &QueryClient,
+    graph_node_endpoint: &Endpoint,
     network: &str,
     block_number: u64,
 ) -> Result<String, QueryError> {
@@ -52,14 +126,17 @@ pub async fn query_graph_node_network_block_hash(
         network: network.to_string(),
         block_number: block_number.try_into().unwrap(),
     };
-    let queried_result = perform_block_hash_from_number(graph_node_endpoint, variables).await?;
+    let queried_result =
+        perform_block_hash_from_number(client, graph_node_endpoint, variables).await?;
     trace!(
-        result = tracing::field::debug(&queried_result),
+        endpoint = %graph_node_endpoint,
+        status = tracing::field::debug(queried_result.status()),
         "Query result for graph node network block hash"
     );
     if !queried_result.status().is_success() {
         warn!(
-            result = tracing::field::debug(&queried_result),
+            endpoint = %graph_node_endpoint,
+            status = tracing::field::debug(queried_result.status()),
             "Unsuccessful query"
         );
     }
@@ -82,27 +159,24 @@ pub async fn query_graph_node_network_block_hash(
 
 /// Query graph node for Indexing Statuses
 pub async fn perform_indexing_statuses(
-    graph_node_endpoint: &str,
+    client: &QueryClient,
+    graph_node_endpoint: &Endpoint,
     variables: indexing_statuses::Variables,
-) -> Result<reqwest::Response, reqwest::Error> {
+) -> Result<reqwest::Response, QueryError> {
     let request_body = IndexingStatuses::build_query(variables);
-    let client = reqwest::Client::new();
-    client
-        .post(graph_node_endpoint)
-        .json(&request_body)
-        .send()
-        .await?
-        .error_for_status()
+    client.post_json(graph_node_endpoint, &request_body).await
 }
 
 /// This function get all indexing statuses from Graph node status endpoint
 pub async fn get_indexing_statuses(
-    graph_node_endpoint: &str,
+    client: &QueryClient,
+    graph_node_endpoint: &Endpoint,
 ) -> Result<Vec<IndexingStatusesIndexingStatuses>, QueryError> {
     let variables: indexing_statuses::Variables = indexing_statuses::Variables {};
-    let queried_result = perform_indexing_statuses(graph_node_endpoint, variables).await?;
+    let queried_result = perform_indexing_statuses(client, graph_node_endpoint, variables).await?;
     trace!(
-        result = tracing::field::debug(&queried_result),
+        endpoint = %graph_node_endpoint,
+        status = tracing::field::debug(queried_result.status()),
         "Query result for indexing statuses"
     );
     let response_body: Response<indexing_statuses::ResponseData> = queried_result.json().await?;
@@ -113,6 +187,205 @@ pub async fn get_indexing_statuses(
         .ok_or(QueryError::IndexingError)
 }
 
+/// Merge indexing statuses from several graph nodes, keeping one status per deployment.
+/// When graph nodes disagree, the healthiest status is kept, then a synced one, then
+/// the one with the furthest latest block
+pub fn merge_indexing_statuses(
+    statuses: impl IntoIterator<Item = Vec<IndexingStatusesIndexingStatuses>>,
+) -> Vec<IndexingStatusesIndexingStatuses> {
+    let mut merged: Vec<IndexingStatusesIndexingStatuses> = vec![];
+    for status in statuses.into_iter().flatten() {
+        match merged.iter_mut().find(|s| s.subgraph == status.subgraph) {
+            Some(existing) if status_rank(&status) > status_rank(existing) => *existing = status,
+            Some(_) => {}
+            None => merged.push(status),
+        }
+    }
+    merged
+}
+
+/// Orders statuses by health, then sync state, then indexing progress
+fn status_rank(status: &IndexingStatusesIndexingStatuses) -> (u8, bool, u64) {
+    let health = match status.health {
+        indexing_statuses::Health::Healthy => 2,
+        indexing_statuses::Health::Unhealthy => 1,
+        _ => 0,
+    };
+    let latest_block = status
+        .chains
+        .iter()
+        .filter_map(|chain| chain.latest_block.as_ref())
+        .filter_map(|block| block.number.parse::<u64>().ok())
+        .max()
+        .unwrap_or_default();
+    (health, status.synced, latest_block)
+}
+
+/// Post a query to the graph node status endpoint and return its data,
+/// mapping GraphQL errors and missing data to `QueryError`
+async fn graph_node_query<Q: GraphQLQuery>(
+    client: &QueryClient,
+    graph_node_endpoint: &Endpoint,
+    variables: Q::Variables,
+    context: &str,
+) -> Result<Q::ResponseData, QueryError>
+where
+    Q::ResponseData: Debug,
+{
+    let request_body = Q::build_query(variables);
+    let response = client.post_json(graph_node_endpoint, &request_body).await?;
+    let response_body: Response<Q::ResponseData> = response.json().await?;
+    trace!(
+        endpoint = %graph_node_endpoint,
+        result = tracing::field::debug(&response_body),
+        "Query result for {context}"
+    );
+    if let Some(e) = response_body
+        .errors
+        .as_deref()
+        .and_then(|errors| errors.first())
+    {
+        if e.message == "indexing_error" {
+            return Err(QueryError::IndexingError);
+        }
+        return Err(QueryError::Other(anyhow::anyhow!(
+            "Graph node could not resolve {context}: {}",
+            e.message
+        )));
+    }
+    response_body
+        .data
+        .ok_or(QueryError::ParseResponseError(format!(
+            "No data from graph node for {context}"
+        )))
+}
+
+/// Query graph node for the proof of indexing of a deployment at a block, optionally
+/// for a specific indexer address
+pub async fn query_proof_of_indexing(
+    client: &QueryClient,
+    graph_node_endpoint: &Endpoint,
+    deployment: &str,
+    block: &BlockPointer,
+    indexer: Option<&str>,
+) -> Result<String, QueryError> {
+    let variables = proof_of_indexing::Variables {
+        subgraph: deployment.to_string(),
+        block_number: block.number as i64,
+        block_hash: block.hash.clone(),
+        indexer: indexer.map(String::from),
+    };
+    graph_node_query::<ProofOfIndexing>(client, graph_node_endpoint, variables, "proofOfIndexing")
+        .await?
+        .proof_of_indexing
+        .ok_or(QueryError::ParseResponseError(format!(
+            "No proof of indexing for {deployment} at block {}",
+            block.number
+        )))
+}
+
+/// Query graph node for public proofs of indexing, in batches of `PUBLIC_POI_BATCH_SIZE`
+pub async fn query_public_pois(
+    client: &QueryClient,
+    graph_node_endpoint: &Endpoint,
+    requests: &[PublicPoiRequest],
+) -> Result<Vec<PublicPoi>, QueryError> {
+    let mut pois = Vec::with_capacity(requests.len());
+    for batch in requests.chunks(PUBLIC_POI_BATCH_SIZE) {
+        let variables = public_proofs_of_indexing::Variables {
+            requests: batch
+                .iter()
+                .map(
+                    |request| public_proofs_of_indexing::PublicProofOfIndexingRequest {
+                        deployment: request.deployment.clone(),
+                        block_number: request.block_number.to_string(),
+                    },
+                )
+                .collect(),
+        };
+        let data = graph_node_query::<PublicProofsOfIndexing>(
+            client,
+            graph_node_endpoint,
+            variables,
+            "publicProofsOfIndexing",
+        )
+        .await?;
+        for result in data.public_proofs_of_indexing {
+            pois.push(PublicPoi {
+                block_number: result.block.number.parse().map_err(|e| {
+                    QueryError::ParseResponseError(format!(
+                        "Invalid block number in public proof of indexing: {e}"
+                    ))
+                })?,
+                block_hash: result.block.hash,
+                deployment: result.deployment,
+                proof_of_indexing: result.proof_of_indexing,
+            });
+        }
+    }
+    Ok(pois)
+}
+
+/// Query graph node for the entities a deployment wrote in a block
+pub async fn query_entity_changes_in_block(
+    client: &QueryClient,
+    graph_node_endpoint: &Endpoint,
+    deployment: &str,
+    block_number: u64,
+) -> Result<EntityChanges, QueryError> {
+    let variables = entity_changes_in_block::Variables {
+        subgraph_id: deployment.to_string(),
+        block_number: block_number as i64,
+    };
+    let changes = graph_node_query::<EntityChangesInBlock>(
+        client,
+        graph_node_endpoint,
+        variables,
+        "entityChangesInBlock",
+    )
+    .await?
+    .entity_changes_in_block;
+    Ok(EntityChanges {
+        updates: changes
+            .updates
+            .into_iter()
+            .map(|u| (u.type_, u.entities))
+            .collect(),
+        deletions: changes
+            .deletions
+            .into_iter()
+            .map(|d| (d.type_, d.entities))
+            .collect(),
+    })
+}
+
+/// Query graph node for the features declared by a deployment
+pub async fn query_subgraph_features(
+    client: &QueryClient,
+    graph_node_endpoint: &Endpoint,
+    deployment: &str,
+) -> Result<SubgraphFeatures, QueryError> {
+    let variables = subgraph_features_query::Variables {
+        subgraph_id: deployment.to_string(),
+    };
+    let features = graph_node_query::<SubgraphFeaturesQuery>(
+        client,
+        graph_node_endpoint,
+        variables,
+        "subgraphFeatures",
+    )
+    .await?
+    .subgraph_features;
+    Ok(SubgraphFeatures {
+        api_version: features.api_version,
+        spec_version: features.spec_version,
+        features: features.features,
+        data_sources: features.data_sources,
+        handlers: features.handlers,
+        network: features.network,
+    })
+}
+
 /// This function update the chainhead block pointer for each Network according to the indexingStatuses of subgraphs
 pub fn update_network_chainheads(
     statuses: Vec<IndexingStatusesIndexingStatuses>,
@@ -185,3 +458,196 @@ pub fn subgraph_network_blocks(
     );
     subgraph_network_blocks
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{routing::post, Json, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::callbook::CallBook;
+    use crate::graphql::http_client::tests::spawn_stub;
+    use crate::graphql::http_client::QueryClientConfig;
+
+    /// Stub graph node answering each query by operation name, recording the batches
+    /// of public POI requests it receives
+    async fn stub_graph_node(poi_batches: Arc<Mutex<Vec<usize>>>) -> Endpoint {
+        let app = Router::new().route(
+            "/graphql",
+            post(move |Json(body): Json<Value>| async move {
+                let variables = &body["variables"];
+                let data = match body["operationName"].as_str().unwrap() {
+                    "ProofOfIndexing" if variables["subgraph"] == "QmMissing" => {
+                        return Json(json!({
+                            "data": null,
+                            "errors": [{"message": "indexing_error"}]
+                        }));
+                    }
+                    "ProofOfIndexing" => json!({"proofOfIndexing": "0xpoi"}),
+                    "PublicProofsOfIndexing" => {
+                        let requests = variables["requests"].as_array().unwrap();
+                        poi_batches.lock().unwrap().push(requests.len());
+                        let results: Vec<Value> = requests
+                            .iter()
+                            .map(|r| json!({
+                                "deployment": r["deployment"],
+                                "block": {"hash": null, "number": r["blockNumber"]},
+                                "proofOfIndexing": format!("0xpoi{}", r["blockNumber"].as_str().unwrap())
+                            }))
+                            .collect();
+                        json!({"publicProofsOfIndexing": results})
+                    }
+                    "EntityChangesInBlock" => json!({"entityChangesInBlock": {
+                        "updates": [{"type": "Transfer", "entities": [{"id": "0x1", "value": "10"}]}],
+                        "deletions": [{"type": "Approval", "entities": ["0x2"]}]
+                    }}),
+                    "SubgraphFeaturesQuery" => json!({"subgraphFeatures": {
+                        "apiVersion": "0.0.7",
+                        "specVersion": "0.0.5",
+                        "features": ["grafting"],
+                        "dataSources": ["ethereum/contract"],
+                        "handlers": ["event"],
+                        "network": "mainnet"
+                    }}),
+                    _ => Value::Null,
+                };
+                Json(json!({ "data": data }))
+            }),
+        );
+        let url = spawn_stub(app).await;
+        Endpoint::new(format!("{url}/graphql"))
+    }
+
+    #[tokio::test]
+    async fn test_proof_of_indexing() {
+        let endpoint = stub_graph_node(Arc::default()).await;
+        let client = QueryClient::default();
+        let block = BlockPointer {
+            hash: "0xblock".to_string(),
+            number: 17_000_000,
+        };
+
+        let poi = query_proof_of_indexing(&client, &endpoint, "Qm1", &block, Some("0xindexer"))
+            .await
+            .unwrap();
+        assert_eq!(poi, "0xpoi");
+        assert!(matches!(
+            query_proof_of_indexing(&client, &endpoint, "QmMissing", &block, None).await,
+            Err(QueryError::IndexingError)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_public_pois_batched() {
+        let batches = Arc::new(Mutex::new(vec![]));
+        let endpoint = stub_graph_node(batches.clone()).await;
+        let requests: Vec<PublicPoiRequest> = (0..25)
+            .map(|i| PublicPoiRequest {
+                deployment: format!("Qm{i}"),
+                block_number: 100 + i,
+            })
+            .collect();
+
+        let pois = query_public_pois(&QueryClient::default(), &endpoint, &requests)
+            .await
+            .unwrap();
+        assert_eq!(pois.len(), 25);
+        assert_eq!(
+            pois[24],
+            PublicPoi {
+                deployment: "Qm24".to_string(),
+                block_number: 124,
+                block_hash: None,
+                proof_of_indexing: "0xpoi124".to_string(),
+            }
+        );
+        assert_eq!(*batches.lock().unwrap(), vec![10, 10, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_entity_changes_and_features() {
+        let endpoint = stub_graph_node(Arc::default()).await;
+        let client = QueryClient::default();
+
+        let changes = query_entity_changes_in_block(&client, &endpoint, "Qm1", 100)
+            .await
+            .unwrap();
+        assert_eq!(changes.updates["Transfer"][0]["value"], "10");
+        assert_eq!(changes.deletions["Approval"], vec!["0x2".to_string()]);
+
+        let features = query_subgraph_features(&client, &endpoint, "Qm1")
+            .await
+            .unwrap();
+        assert_eq!(features.features, vec!["grafting".to_string()]);
+        assert_eq!(features.network.as_deref(), Some("mainnet"));
+    }
+
+    #[test]
+    fn test_merge_indexing_statuses() {
+        let status =
+            |health: &str, synced: bool, latest: u64| -> IndexingStatusesIndexingStatuses {
+                serde_json::from_value(json!({
+                    "subgraph": "Qm1",
+                    "synced": synced,
+                    "health": health,
+                    "node": null,
+                    "fatalError": null,
+                    "chains": [{
+                        "network": "mainnet",
+                        "latestBlock": {"number": latest.to_string(), "hash": "0x1"},
+                        "chainHeadBlock": null
+                    }]
+                }))
+                .unwrap()
+            };
+        let mut other = status("healthy", false, 1);
+        other.subgraph = "Qm2".to_string();
+
+        let merged = merge_indexing_statuses([
+            vec![status("failed", true, 300), other],
+            vec![status("healthy", false, 100)],
+            vec![status("healthy", true, 200), status("healthy", true, 250)],
+        ]);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].subgraph, "Qm1");
+        assert!(merged[0].synced);
+        assert_eq!(
+            merged[0].chains[0].latest_block.as_ref().unwrap().number,
+            "250"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_callbook_graph_node_failover() {
+        let down = Endpoint::new("http://127.0.0.1:1/graphql");
+        let up = stub_graph_node(Arc::default()).await;
+        let callbook = CallBook::new(String::new(), String::new(), Some(down.url.clone()))
+            .with_additional_graph_node(up.clone())
+            .with_query_client_config(QueryClientConfig {
+                max_retries: 0,
+                ..Default::default()
+            });
+        let block = BlockPointer::new(17_000_000, "0xblock".to_string());
+
+        let poi = callbook
+            .proof_of_indexing("Qm1", &block, None)
+            .await
+            .unwrap();
+        assert_eq!(poi, "0xpoi");
+        let health = callbook.graph_node_health();
+        assert_eq!(health[&down.url].consecutive_failures, 1);
+        assert!(health[&up.url].is_healthy());
+
+        // The healthy endpoint is tried first from now on
+        callbook
+            .proof_of_indexing("Qm1", &block, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            callbook.graph_node_health()[&down.url].consecutive_failures,
+            1
+        );
+    }
+}
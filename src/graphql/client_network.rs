@@ -1,10 +1,18 @@
+use std::collections::HashMap;
+
 use graphql_client::{GraphQLQuery, Response};
 use num_traits::Zero;
+use serde_derive::{Deserialize, Serialize};
 use tracing::{error, trace};
 
-use crate::graphql::QueryError;
+use crate::graphql::{endpoint::Endpoint, http_client::QueryClient, QueryError};
+use crate::Account;
 
-use super::grt_gwei_string_to_f32;
+use super::{
+    grt_wei_string_to_amount,
+    pagination::{PageCursor, DEFAULT_PAGE_SIZE},
+    GrtAmount,
+};
 
 /// Derived GraphQL Query to Network Subgraph
 #[derive(GraphQLQuery)]
@@ -15,23 +23,94 @@ use super::grt_gwei_string_to_f32;
 )]
 pub struct IndexerStatus;
 
+/// Derived page of indexer stakes, ordered by indexer address
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/schema_network.graphql",
+    query_path = "src/graphql/query_indexer_stakes.graphql",
+    response_derives = "Debug, Serialize, Deserialize"
+)]
+pub struct IndexerStakesPage;
+
 /// Query network subgraph for indexer status
-/// Contains indexer address, stake, allocations
+/// Contains indexer address, stake, active allocations
 /// and graph network minimum indexer stake requirement
 pub async fn query_network_subgraph(
-    url: &str,
+    client: &QueryClient,
+    url: &Endpoint,
     indexer_address: &str,
 ) -> Result<Network, QueryError> {
-    // Can refactor for all types of queries
-    let variables: indexer_status::Variables = indexer_status::Variables {
-        address: indexer_address.to_string(),
-    };
+    let mut cursor = PageCursor::new(DEFAULT_PAGE_SIZE);
+    let mut indexer: Option<Indexer> = None;
+    let mut graph_network: Option<GraphNetwork> = None;
+    while let Some(last_id) = cursor.next_page() {
+        let variables = indexer_status::Variables {
+            address: indexer_address.to_string(),
+            first: cursor.first(),
+            last_id,
+        };
+        let data = indexer_status_page(client, url, indexer_address, variables).await?;
+        if graph_network.is_none() {
+            graph_network = Some(GraphNetwork {
+                minimum_indexer_stake: grt_wei_string_to_amount(
+                    &data.graph_network.minimum_indexer_stake,
+                )?,
+            });
+        }
+        let Some(page_indexer) = data.indexer else {
+            break;
+        };
+        if indexer.is_none() {
+            match grt_wei_string_to_amount(&page_indexer.staked_tokens) {
+                Ok(staked_tokens) => {
+                    indexer = Some(Indexer {
+                        staked_tokens,
+                        allocations: vec![],
+                    })
+                }
+                Err(e) => {
+                    error!(
+                        error = tracing::field::debug(&e),
+                        "Indexer not available from the network subgraph"
+                    );
+                    break;
+                }
+            }
+        }
+
+        let page = page_indexer.allocations.unwrap_or_default();
+        cursor.advance(&page, |alloc| &alloc.id);
+        if let Some(indexer) = indexer.as_mut() {
+            for alloc in page {
+                indexer.allocations.push(Allocation {
+                    allocated_tokens: grt_wei_string_to_amount(&alloc.allocated_tokens)?,
+                    created_at_epoch: alloc.created_at_epoch as u64,
+                    id: alloc.id,
+                    subgraph_deployment: SubgraphDeployment {
+                        ipfs_hash: alloc.subgraph_deployment.ipfs_hash,
+                    },
+                });
+            }
+        }
+    }
+
+    Ok(Network {
+        indexer,
+        graph_network: graph_network.ok_or(QueryError::ParseResponseError(format!(
+            "Missing graph network data from network subgraph for {indexer_address}"
+        )))?,
+    })
+}
+
+/// Query a page of the indexer's active allocations, along with its stake and the network minimum
+async fn indexer_status_page(
+    client: &QueryClient,
+    url: &Endpoint,
+    indexer_address: &str,
+    variables: indexer_status::Variables,
+) -> Result<indexer_status::ResponseData, QueryError> {
     let request_body = IndexerStatus::build_query(variables);
-    let client = reqwest::Client::builder()
-        .user_agent("network-subgraph")
-        .build()?;
-    let request = client.post(url).json(&request_body);
-    let response = request.send().await?.error_for_status()?;
+    let response = client.post_json(url, &request_body).await?;
     let response_body: Response<indexer_status::ResponseData> = response.json().await?;
     trace!(
         indexer_address,
@@ -47,51 +126,64 @@ pub async fn query_network_subgraph(
             return Err(QueryError::Other(anyhow::anyhow!("{}", e.message)));
         }
     }
-    let data = if let Some(data) = response_body.data {
-        data
-    } else {
-        return Err(QueryError::ParseResponseError(format!(
+    response_body
+        .data
+        .ok_or(QueryError::ParseResponseError(format!(
             "Missing response data from network subgraph for {indexer_address}"
-        )));
-    };
-
-    let indexer = data.indexer.and_then(|x| {
-        match Some(grt_gwei_string_to_f32(&x.staked_tokens)).transpose() {
-            Ok(token) => {
-                let allocations: Vec<Allocation> = x.allocations.map(|allocs| {
-                    allocs
-                        .iter()
-                        .map(|alloc| Allocation {
-                            subgraph_deployment: SubgraphDeployment {
-                                ipfs_hash: alloc.subgraph_deployment.ipfs_hash.clone(),
-                            },
-                        })
-                        .collect::<Vec<Allocation>>()
-                })?;
-                Some(Indexer {
-                    staked_tokens: token?,
-                    allocations,
-                })
-            }
-            Err(e) => {
-                error!(
-                    error = tracing::field::debug(&e),
-                    "Indexer not available from the network subgraph"
-                );
+        )))
+}
 
-                None
+/// Query the staked tokens of every indexer, paging through the indexers by address,
+/// along with the graph network minimum indexer stake requirement.
+/// Stakes are keyed by lowercased indexer address
+pub async fn query_indexer_stakes(
+    client: &QueryClient,
+    url: &Endpoint,
+    page_size: usize,
+) -> Result<(HashMap<String, GrtAmount>, GraphNetwork), QueryError> {
+    let mut stakes = HashMap::new();
+    let mut graph_network = GraphNetwork::default();
+    let mut cursor = PageCursor::new(page_size);
+    while let Some(last_id) = cursor.next_page() {
+        let request_body = IndexerStakesPage::build_query(indexer_stakes_page::Variables {
+            first: cursor.first(),
+            last_id: last_id.clone(),
+        });
+        let response = client.post_json(url, &request_body).await?;
+        let response_body: Response<indexer_stakes_page::ResponseData> = response.json().await?;
+        if let Some(e) = response_body
+            .errors
+            .as_deref()
+            .and_then(|errors| errors.first())
+        {
+            if e.message == "indexing_error" {
+                return Err(QueryError::IndexingError);
+            } else {
+                return Err(QueryError::Other(anyhow::anyhow!("{}", e.message)));
             }
         }
-    });
+        let data = response_body
+            .data
+            .ok_or(QueryError::ParseResponseError(String::from(
+                "Missing response data from network subgraph for indexer stakes",
+            )))?;
+        trace!(
+            last_id,
+            indexers = data.indexers.len(),
+            "Queried page of indexer stakes"
+        );
 
-    Ok(Network {
-        indexer,
-        graph_network: GraphNetwork {
-            minimum_indexer_stake: grt_gwei_string_to_f32(
-                &data.graph_network.minimum_indexer_stake,
-            )?,
-        },
-    })
+        cursor.advance(&data.indexers, |indexer| &indexer.id);
+        for indexer in data.indexers {
+            stakes.insert(
+                indexer.id.to_lowercase(),
+                grt_wei_string_to_amount(&indexer.staked_tokens)?,
+            );
+        }
+        graph_network.minimum_indexer_stake =
+            grt_wei_string_to_amount(&data.graph_network.minimum_indexer_stake)?;
+    }
+    Ok((stakes, graph_network))
 }
 
 /// Network tracks the GraphcastID's indexer and general Graph network data
@@ -103,11 +195,10 @@ pub struct Network {
 
 impl Network {
     /// Fetch indexer staked tokens
-    pub fn indexer_stake(&self) -> f32 {
-        // TODO: do division by 1e18 for grt unit
+    pub fn indexer_stake(&self) -> GrtAmount {
         self.indexer
             .as_ref()
-            .map(|i| i.staked_tokens)
+            .map(|i| i.staked_tokens.clone())
             .unwrap_or_else(Zero::zero)
     }
 
@@ -134,30 +225,184 @@ pub struct SubgraphDeployment {
     pub ipfs_hash: String,
 }
 
+/// Active allocation of an indexer
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Allocation {
+    pub id: String,
+    pub allocated_tokens: GrtAmount,
+    pub created_at_epoch: u64,
     pub subgraph_deployment: SubgraphDeployment,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Indexer {
-    staked_tokens: f32,
+    staked_tokens: GrtAmount,
     allocations: Vec<Allocation>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl Indexer {
+    pub fn new(staked_tokens: GrtAmount, allocations: Vec<Allocation>) -> Self {
+        Indexer {
+            staked_tokens,
+            allocations,
+        }
+    }
+
+    pub fn staked_tokens(&self) -> &GrtAmount {
+        &self.staked_tokens
+    }
+
+    /// Active allocations of the indexer
+    pub fn allocations(&self) -> &[Allocation] {
+        &self.allocations
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct GraphNetwork {
-    pub minimum_indexer_stake: f32,
+    pub minimum_indexer_stake: GrtAmount,
+}
+
+/// Layer of The Graph protocol deployment a network subgraph indexes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkLayer {
+    /// Ethereum mainnet or its testnets
+    L1,
+    /// Arbitrum One or its testnets, where transferred stake lives
+    L2,
+}
+
+/// Network subgraph of one protocol deployment, such as Ethereum mainnet or Arbitrum One
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkSubgraph {
+    /// Name reported when this network matches, such as `mainnet` or `arbitrum-one`
+    pub name: String,
+    pub layer: NetworkLayer,
+    pub endpoint: Endpoint,
+}
+
+impl NetworkSubgraph {
+    pub fn new(name: impl Into<String>, layer: NetworkLayer, endpoint: Endpoint) -> Self {
+        NetworkSubgraph {
+            name: name.into(),
+            layer,
+            endpoint,
+        }
+    }
+}
+
+/// How identities are resolved across several network subgraphs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NetworkResolution {
+    /// An account matching on any network is accepted, and stake is summed across
+    /// networks against the lowest minimum stake
+    #[default]
+    Any,
+    /// The account must match, and the stake requirement be met, on every network
+    All,
+    /// L2 networks answer first; for stake, the first network knowing the indexer is used
+    PreferL2,
+}
+
+impl NetworkResolution {
+    /// Networks in the order they are consulted, L2 first when preferred
+    pub fn order<'a>(&self, networks: &'a [NetworkSubgraph]) -> Vec<&'a NetworkSubgraph> {
+        let mut ordered: Vec<&NetworkSubgraph> = networks.iter().collect();
+        if *self == NetworkResolution::PreferL2 {
+            ordered.sort_by_key(|network| network.layer != NetworkLayer::L2);
+        }
+        ordered
+    }
+}
+
+/// Graph account matched across network subgraphs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountResolution {
+    pub account: Account,
+    /// Networks on which the agent matched the graph account
+    pub networks: Vec<String>,
+}
+
+/// Indexer stake resolved across network subgraphs
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StakeResolution {
+    pub stake: GrtAmount,
+    pub minimum_stake: GrtAmount,
+    pub satisfied: bool,
+    /// Networks the stake was taken from
+    pub networks: Vec<String>,
+}
+
+/// Resolve an indexer's stake from each network's indexer status, ordered as
+/// `NetworkResolution::order`
+pub fn resolve_stake(
+    resolution: NetworkResolution,
+    networks: &[(&NetworkSubgraph, Network)],
+) -> StakeResolution {
+    let known = || networks.iter().filter(|(_, n)| n.indexer.is_some());
+    let names = |networks: Vec<&(&NetworkSubgraph, Network)>| {
+        networks.iter().map(|(ns, _)| ns.name.clone()).collect()
+    };
+    match resolution {
+        NetworkResolution::Any => {
+            let stake: GrtAmount = networks.iter().map(|(_, n)| n.indexer_stake()).sum();
+            let minimum_stake = networks
+                .iter()
+                .map(|(_, n)| n.graph_network.minimum_indexer_stake.clone())
+                .min()
+                .unwrap_or_default();
+            StakeResolution {
+                satisfied: !networks.is_empty() && stake >= minimum_stake,
+                stake,
+                minimum_stake,
+                networks: names(known().collect()),
+            }
+        }
+        NetworkResolution::All => StakeResolution {
+            stake: networks.iter().map(|(_, n)| n.indexer_stake()).sum(),
+            minimum_stake: networks
+                .iter()
+                .map(|(_, n)| n.graph_network.minimum_indexer_stake.clone())
+                .max()
+                .unwrap_or_default(),
+            satisfied: !networks.is_empty()
+                && networks.iter().all(|(_, n)| n.stake_satisfy_requirement()),
+            networks: names(
+                networks
+                    .iter()
+                    .filter(|(_, n)| n.stake_satisfy_requirement())
+                    .collect(),
+            ),
+        },
+        NetworkResolution::PreferL2 => match known().next().or(networks.first()) {
+            Some((ns, network)) => StakeResolution {
+                stake: network.indexer_stake(),
+                minimum_stake: network.graph_network.minimum_indexer_stake.clone(),
+                satisfied: network.stake_satisfy_requirement(),
+                networks: vec![ns.name.clone()],
+            },
+            None => StakeResolution {
+                stake: GrtAmount::zero(),
+                minimum_stake: GrtAmount::zero(),
+                satisfied: false,
+                networks: vec![],
+            },
+        },
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use num_traits::One;
-
     use super::*;
+    use crate::graphql::http_client::tests::spawn_stub;
 
     fn dummy_allocations() -> Vec<Allocation> {
         [Allocation {
+            id: "0xallocation".to_string(),
+            allocated_tokens: GrtAmount::from_grt(1),
+            created_at_epoch: 1,
             subgraph_deployment: SubgraphDeployment {
                 ipfs_hash: "Qmdsp5yyFzMVUdSv5N9KndTisjXHrGDEXNaBxjyCTvDfPs".to_string(),
             },
@@ -169,7 +414,7 @@ mod tests {
     async fn stake_minimum_requirement_pass() {
         let network = Network {
             indexer: Some(Indexer {
-                staked_tokens: 1_f32,
+                staked_tokens: GrtAmount::from_grt(1),
                 allocations: dummy_allocations(),
             }),
             graph_network: GraphNetwork {
@@ -177,7 +422,7 @@ mod tests {
             },
         };
         assert_eq!(network.indexer_allocations().len(), 1);
-        assert_eq!(network.indexer_stake(), 1.0);
+        assert_eq!(network.indexer_stake(), GrtAmount::from_grt(1));
         assert!(network.stake_satisfy_requirement());
     }
 
@@ -189,7 +434,7 @@ mod tests {
                 allocations: dummy_allocations(),
             }),
             graph_network: GraphNetwork {
-                minimum_indexer_stake: One::one(),
+                minimum_indexer_stake: GrtAmount::from_grt(1),
             },
         };
         assert!(!network.stake_satisfy_requirement());
@@ -200,7 +445,7 @@ mod tests {
         let network = Network {
             indexer: None,
             graph_network: GraphNetwork {
-                minimum_indexer_stake: One::one(),
+                minimum_indexer_stake: GrtAmount::from_grt(1),
             },
         };
 
@@ -209,4 +454,145 @@ mod tests {
         assert!(network.indexer.is_none());
         assert!(!network.stake_satisfy_requirement());
     }
+
+    #[tokio::test]
+    async fn test_allocations_paginated() {
+        use axum::{routing::post, Json, Router};
+        use serde_json::{json, Value};
+
+        let allocations: Vec<Value> = (0..DEFAULT_PAGE_SIZE + 5)
+            .map(|i| {
+                json!({
+                    "id": format!("0x{i:06}"),
+                    "allocatedTokens": "1000000000000000000",
+                    "createdAtEpoch": 100,
+                    "subgraphDeployment": {"ipfsHash": format!("Qm{i}")}
+                })
+            })
+            .collect();
+        let app = Router::new().route(
+            "/",
+            post(move |Json(body): Json<Value>| async move {
+                let first = body["variables"]["first"].as_u64().unwrap() as usize;
+                let last_id = body["variables"]["lastId"].as_str().unwrap();
+                let page: Vec<&Value> = allocations
+                    .iter()
+                    .filter(|a| a["id"].as_str().unwrap() > last_id)
+                    .take(first)
+                    .collect();
+                Json(json!({"data": {
+                    "indexer": {"stakedTokens": "100000000000000000000000", "allocations": page},
+                    "graphNetwork": {"minimumIndexerStake": "100000000000000000000000"}
+                }}))
+            }),
+        );
+        let url = spawn_stub(app).await;
+
+        let network = query_network_subgraph(
+            &QueryClient::default(),
+            &Endpoint::new(format!("{url}/")),
+            "0xindexer",
+        )
+        .await
+        .unwrap();
+        assert_eq!(network.indexer_allocations().len(), DEFAULT_PAGE_SIZE + 5);
+        assert!(network.stake_satisfy_requirement());
+        let last = network.indexer.unwrap().allocations.pop().unwrap();
+        assert_eq!(last.allocated_tokens, GrtAmount::from_grt(1));
+        assert_eq!(last.created_at_epoch, 100);
+    }
+
+    #[test]
+    fn test_resolve_stake_across_layers() {
+        let l1 = NetworkSubgraph::new("mainnet", NetworkLayer::L1, Endpoint::new("l1"));
+        let l2 = NetworkSubgraph::new("arbitrum-one", NetworkLayer::L2, Endpoint::new("l2"));
+        let network = |stake: Option<u64>| Network {
+            indexer: stake.map(|s| Indexer::new(GrtAmount::from_grt(s), vec![])),
+            graph_network: GraphNetwork {
+                minimum_indexer_stake: GrtAmount::from_grt(100_000),
+            },
+        };
+        let configured = [l1.clone(), l2.clone()];
+        let ordered = NetworkResolution::PreferL2.order(&configured);
+        assert_eq!(ordered, vec![&l2, &l1]);
+
+        // Half of the stake transferred to L2
+        let split = [(&l1, network(Some(60_000))), (&l2, network(Some(40_000)))];
+        let any = resolve_stake(NetworkResolution::Any, &split);
+        assert!(any.satisfied);
+        assert_eq!(any.stake, GrtAmount::from_grt(100_000));
+        assert_eq!(any.networks, vec!["mainnet", "arbitrum-one"]);
+        assert!(!resolve_stake(NetworkResolution::All, &split).satisfied);
+
+        let transferred = [(&l2, network(Some(150_000))), (&l1, network(None))];
+        let prefer_l2 = resolve_stake(NetworkResolution::PreferL2, &transferred);
+        assert!(prefer_l2.satisfied);
+        assert_eq!(prefer_l2.networks, vec!["arbitrum-one"]);
+        let all = resolve_stake(NetworkResolution::All, &transferred);
+        assert!(!all.satisfied);
+        assert_eq!(all.networks, vec!["arbitrum-one"]);
+    }
+
+    /// Stub network subgraph where the operator is registered for the account or not
+    async fn stub_network_subgraph(registered: bool) -> Endpoint {
+        use axum::{routing::post, Json, Router};
+        use serde_json::json;
+
+        let graph_accounts = if registered {
+            json!([{"id": "0xaccount", "operators": [{"id": "0xoperator"}], "indexer": null}])
+        } else {
+            json!([])
+        };
+        let app = Router::new().route(
+            "/",
+            post(move || async move { Json(json!({"data": {"graphAccounts": graph_accounts}})) }),
+        );
+        let url = spawn_stub(app).await;
+        Endpoint::new(format!("{url}/"))
+    }
+
+    #[tokio::test]
+    async fn test_resolve_graph_account_across_layers() {
+        use crate::callbook::CallBook;
+
+        let l1 = NetworkSubgraph::new(
+            "mainnet",
+            NetworkLayer::L1,
+            stub_network_subgraph(false).await,
+        );
+        let l2 = NetworkSubgraph::new(
+            "arbitrum-one",
+            NetworkLayer::L2,
+            stub_network_subgraph(true).await,
+        );
+        let callbook = |resolution| {
+            CallBook::new(String::new(), String::new(), None)
+                .with_network_subgraph(l1.clone())
+                .with_network_subgraph(l2.clone())
+                .with_network_resolution(resolution)
+        };
+
+        let any = callbook(NetworkResolution::Any)
+            .resolve_graph_account("0xoperator", "0xaccount")
+            .await
+            .unwrap();
+        assert_eq!(
+            any.account,
+            Account::new("0xoperator".to_string(), "0xaccount".to_string())
+        );
+        assert_eq!(any.networks, vec!["arbitrum-one"]);
+
+        assert!(callbook(NetworkResolution::All)
+            .resolve_graph_account("0xoperator", "0xaccount")
+            .await
+            .is_err());
+
+        let account = Account::new("0xoperator".to_string(), "0xaccount".to_string());
+        let prefer_l2 = account
+            .account_from_network(&callbook(NetworkResolution::PreferL2))
+            .await
+            .unwrap();
+        assert_eq!(prefer_l2.account, account);
+        assert_eq!(prefer_l2.networks, vec!["arbitrum-one"]);
+    }
 }
@@ -0,0 +1,252 @@
+//! Prometheus instant and range queries, so radios can attach local indexer
+//! metrics such as query latency, indexing lag and error rates to their payloads.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use prometheus_http_query::response::{Data, PromqlResult, Sample};
+use prometheus_http_query::Client;
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use super::{endpoint::Endpoint, http_client::QueryClient, QueryError};
+
+/// Value of a series at a point in time
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PrometheusSample {
+    /// Unix timestamp in seconds
+    pub timestamp: f64,
+    pub value: f64,
+}
+
+/// Sample of a series from an instant query
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InstantSample {
+    /// Labels identifying the series, empty for scalar results
+    pub labels: HashMap<String, String>,
+    pub sample: PrometheusSample,
+}
+
+/// Samples of a series from a range query
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RangeSeries {
+    pub labels: HashMap<String, String>,
+    pub samples: Vec<PrometheusSample>,
+}
+
+impl From<&Sample> for PrometheusSample {
+    fn from(sample: &Sample) -> Self {
+        PrometheusSample {
+            timestamp: sample.timestamp(),
+            value: sample.value(),
+        }
+    }
+}
+
+fn prometheus_client(client: &QueryClient, endpoint: &Endpoint) -> Result<Client, QueryError> {
+    Ok(Client::from(
+        client.inner().clone(),
+        &endpoint.request_url(),
+    )?)
+}
+
+/// Drop the requested URL from errors when it carries the endpoint's API key
+fn redact_error(endpoint: &Endpoint, error: prometheus_http_query::Error) -> QueryError {
+    match error {
+        prometheus_http_query::Error::Client(e) if endpoint.url_has_secret() => {
+            QueryError::PrometheusError(prometheus_http_query::Error::Client(e.without_url()))
+        }
+        e => QueryError::PrometheusError(e),
+    }
+}
+
+/// Evaluate a PromQL expression at a single point in time, now if `at` is not given
+pub async fn query_prometheus_instant(
+    client: &QueryClient,
+    endpoint: &Endpoint,
+    query: &str,
+    at: Option<i64>,
+) -> Result<Vec<InstantSample>, QueryError> {
+    let mut builder = prometheus_client(client, endpoint)?.query(query);
+    if let Some(at) = at {
+        builder = builder.at(at);
+    }
+    for (name, value) in endpoint.headers() {
+        if let Some(name) = name {
+            builder = builder.header(name, value);
+        }
+    }
+    let result = builder.get().await.map_err(|e| redact_error(endpoint, e))?;
+    trace!(
+        query,
+        result = tracing::field::debug(&result),
+        "Prometheus instant query result"
+    );
+    instant_samples(&result)
+}
+
+/// Evaluate a PromQL expression over `[start, end]` (Unix seconds) every `step`
+pub async fn query_prometheus_range(
+    client: &QueryClient,
+    endpoint: &Endpoint,
+    query: &str,
+    start: i64,
+    end: i64,
+    step: Duration,
+) -> Result<Vec<RangeSeries>, QueryError> {
+    let mut builder =
+        prometheus_client(client, endpoint)?.query_range(query, start, end, step.as_secs_f64());
+    for (name, value) in endpoint.headers() {
+        if let Some(name) = name {
+            builder = builder.header(name, value);
+        }
+    }
+    let result = builder.get().await.map_err(|e| redact_error(endpoint, e))?;
+    trace!(
+        query,
+        result = tracing::field::debug(&result),
+        "Prometheus range query result"
+    );
+    range_series(&result)
+}
+
+fn instant_samples(result: &PromqlResult) -> Result<Vec<InstantSample>, QueryError> {
+    match result.data() {
+        Data::Vector(vector) => Ok(vector
+            .iter()
+            .map(|v| InstantSample {
+                labels: v.metric().clone(),
+                sample: v.sample().into(),
+            })
+            .collect()),
+        Data::Scalar(sample) => Ok(vec![InstantSample {
+            labels: HashMap::new(),
+            sample: sample.into(),
+        }]),
+        Data::Matrix(_) => Err(QueryError::ParseResponseError(String::from(
+            "Prometheus returned a range vector for an instant query",
+        ))),
+    }
+}
+
+fn range_series(result: &PromqlResult) -> Result<Vec<RangeSeries>, QueryError> {
+    result
+        .data()
+        .as_matrix()
+        .map(|matrix| {
+            matrix
+                .iter()
+                .map(|series| RangeSeries {
+                    labels: series.metric().clone(),
+                    samples: series
+                        .samples()
+                        .iter()
+                        .map(PrometheusSample::from)
+                        .collect(),
+                })
+                .collect()
+        })
+        .ok_or(QueryError::ParseResponseError(String::from(
+            "Prometheus did not return a range vector for a range query",
+        )))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Query, http::HeaderMap, routing::get, Json, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::graphql::endpoint::{EndpointAuth, Secret};
+    use crate::graphql::http_client::tests::spawn_stub;
+
+    async fn stub_prometheus() -> Endpoint {
+        let app = Router::new()
+            .route(
+                "/api/v1/query",
+                get(
+                    |headers: HeaderMap, Query(params): Query<HashMap<String, String>>| async move {
+                        if headers.get("authorization").map(|v| v.as_bytes()) != Some(b"Bearer token")
+                        {
+                            return Json(json!({
+                                "status": "error", "errorType": "unauthorized", "error": "missing token"
+                            }));
+                        }
+                        let result = if params["query"] == "scalar(1)" {
+                            json!({"resultType": "scalar", "result": [1700000000.5, "1"]})
+                        } else {
+                            json!({"resultType": "vector", "result": [{
+                                "metric": {"__name__": "deployment_head", "deployment": "Qm1"},
+                                "value": [1700000000.5, "17000000"]
+                            }]})
+                        };
+                        Json(json!({"status": "success", "data": result}))
+                    },
+                ),
+            )
+            .route(
+                "/api/v1/query_range",
+                get(|Query(params): Query<HashMap<String, String>>| async move {
+                    let start: i64 = params["start"].parse().unwrap();
+                    let values: Vec<Value> =
+                        (0..3).map(|i| json!([start + i * 60, format!("{i}")])).collect();
+                    Json(json!({"status": "success", "data": {
+                        "resultType": "matrix",
+                        "result": [{"metric": {"deployment": "Qm1"}, "values": values}]
+                    }}))
+                }),
+            );
+        let url = spawn_stub(app).await;
+        Endpoint::new(url).with_auth(EndpointAuth::bearer_token(Secret::new("token")))
+    }
+
+    #[tokio::test]
+    async fn test_instant_query() {
+        let endpoint = stub_prometheus().await;
+        let client = QueryClient::default();
+
+        let samples = query_prometheus_instant(&client, &endpoint, "deployment_head", None)
+            .await
+            .unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].labels["deployment"], "Qm1");
+        assert_eq!(
+            samples[0].sample,
+            PrometheusSample {
+                timestamp: 1700000000.5,
+                value: 17000000.0
+            }
+        );
+
+        let scalar = query_prometheus_instant(&client, &endpoint, "scalar(1)", None)
+            .await
+            .unwrap();
+        assert!(scalar[0].labels.is_empty());
+        assert_eq!(scalar[0].sample.value, 1.0);
+
+        let unauthorized = Endpoint::new(endpoint.url.clone());
+        assert!(matches!(
+            query_prometheus_instant(&client, &unauthorized, "deployment_head", None).await,
+            Err(QueryError::PrometheusError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_range_query() {
+        let endpoint = stub_prometheus().await;
+        let series = query_prometheus_range(
+            &QueryClient::default(),
+            &endpoint,
+            "deployment_head",
+            1700000000,
+            1700000120,
+            Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].samples.len(), 3);
+        assert_eq!(series[0].samples[2].timestamp, 1700000120.0);
+        assert_eq!(series[0].samples[2].value, 2.0);
+    }
+}
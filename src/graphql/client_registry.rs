@@ -1,8 +1,14 @@
+use std::collections::HashMap;
+
 use graphql_client::{GraphQLQuery, Response};
 use serde_derive::{Deserialize, Serialize};
 use tracing::{trace, warn};
 
-use super::QueryError;
+use super::{endpoint::Endpoint, http_client::QueryClient, pagination::PageCursor, QueryError};
+
+type BigInt = String;
+
+const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
 
 /// Derived Indexer
 #[derive(GraphQLQuery, Serialize, Deserialize, Debug)]
@@ -13,38 +19,47 @@ use super::QueryError;
 )]
 pub struct SetGraphcastIds;
 
+/// Derived page of Graphcast ID registrations, ordered by entity id
+#[derive(GraphQLQuery, Serialize, Deserialize, Debug)]
+#[graphql(
+    schema_path = "src/graphql/schema_registry.graphql",
+    query_path = "src/graphql/query_registry_snapshot.graphql",
+    response_derives = "Debug, Serialize, Deserialize"
+)]
+pub struct GraphcastIdsPage;
+
 /// Query registry subgraph endpoint for resolving Graphcast ID and indexer address
 pub async fn perform_graphcast_id_indexer_query(
-    registry_subgraph_endpoint: &str,
+    client: &QueryClient,
+    registry_subgraph_endpoint: &Endpoint,
     variables: set_graphcast_ids::Variables,
-) -> Result<reqwest::Response, reqwest::Error> {
+) -> Result<reqwest::Response, QueryError> {
     let request_body = SetGraphcastIds::build_query(variables);
-    let client = reqwest::Client::new();
     client
-        .post(registry_subgraph_endpoint)
-        .json(&request_body)
-        .send()
-        .await?
-        .error_for_status()
+        .post_json(registry_subgraph_endpoint, &request_body)
+        .await
 }
 
 /// Construct GraphQL variables and parse result for indexer address
 pub async fn query_registry(
-    registry_subgraph_endpoint: &str,
+    client: &QueryClient,
+    registry_subgraph_endpoint: &Endpoint,
     wallet_address: &str,
 ) -> Result<String, QueryError> {
     let variables: set_graphcast_ids::Variables = set_graphcast_ids::Variables {
         address: wallet_address.to_string(),
     };
     let queried_result =
-        perform_graphcast_id_indexer_query(registry_subgraph_endpoint, variables).await?;
+        perform_graphcast_id_indexer_query(client, registry_subgraph_endpoint, variables).await?;
     trace!(
-        result = tracing::field::debug(&queried_result),
+        endpoint = %registry_subgraph_endpoint,
+        status = tracing::field::debug(queried_result.status()),
         "Query result for registry indexer"
     );
     if !&queried_result.status().is_success() {
         warn!(
-            result = tracing::field::debug(&queried_result),
+            endpoint = %registry_subgraph_endpoint,
+            status = tracing::field::debug(queried_result.status()),
             "Unsuccessful query"
         );
     }
@@ -62,3 +77,71 @@ pub async fn query_registry(
         )))
     }
 }
+
+/// Query every Graphcast ID registration, paging through the entities by id, and keep
+/// the latest registration of each indexer so that replaced Graphcast IDs are dropped.
+/// Returns a map from lowercased Graphcast ID to lowercased indexer address
+pub async fn query_all_graphcast_ids(
+    client: &QueryClient,
+    registry_subgraph_endpoint: &Endpoint,
+    page_size: usize,
+) -> Result<HashMap<String, String>, QueryError> {
+    // Lowercased indexer -> (block number, entity id, lowercased Graphcast ID)
+    let mut latest: HashMap<String, (u64, String, String)> = HashMap::new();
+    let mut cursor = PageCursor::new(page_size);
+    while let Some(last_id) = cursor.next_page() {
+        let request_body = GraphcastIdsPage::build_query(graphcast_ids_page::Variables {
+            first: cursor.first(),
+            last_id: last_id.clone(),
+        });
+        let response = client
+            .post_json(registry_subgraph_endpoint, &request_body)
+            .await?;
+        let response_body: Response<graphcast_ids_page::ResponseData> = response.json().await?;
+        if let Some(e) = response_body
+            .errors
+            .as_deref()
+            .and_then(|errors| errors.first())
+        {
+            return Err(QueryError::Other(anyhow::anyhow!("{}", e.message)));
+        }
+        let page = response_body
+            .data
+            .ok_or(QueryError::ParseResponseError(String::from(
+                "No response data from registry for Graphcast ID registrations",
+            )))?
+            .graphcast_ids;
+        trace!(
+            last_id,
+            entries = page.len(),
+            "Queried page of Graphcast ID registrations"
+        );
+
+        cursor.advance(&page, |registration| &registration.id);
+        for registration in page {
+            let block_number = registration.block_number.parse::<u64>().map_err(|_| {
+                QueryError::ParseResponseError(format!(
+                    "Invalid block number of registration {}: {}",
+                    registration.id, registration.block_number
+                ))
+            })?;
+            let candidate = (
+                block_number,
+                registration.id,
+                registration.graphcast_id.to_lowercase(),
+            );
+            let indexer = registration.indexer.to_lowercase();
+            match latest.get(&indexer) {
+                Some(current) if (current.0, &current.1) >= (candidate.0, &candidate.1) => {}
+                _ => {
+                    latest.insert(indexer, candidate);
+                }
+            }
+        }
+    }
+    Ok(latest
+        .into_iter()
+        .filter(|(_, (_, _, graphcast_id))| graphcast_id != ZERO_ADDRESS)
+        .map(|(indexer, (_, _, graphcast_id))| (graphcast_id, indexer))
+        .collect())
+}
@@ -0,0 +1,469 @@
+//! Graphcast registry contract read over an Ethereum JSON-RPC provider, resolving
+//! Graphcast IDs from chain state instead of the registry subgraph.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ethers::contract::{abigen, ContractError};
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::Address;
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::Mutex as AsyncMutex;
+use tracing::trace;
+
+use super::{
+    client_rpc::{provider, rpc_error},
+    endpoint::Endpoint,
+    http_client::QueryClient,
+    QueryError,
+};
+
+/// Blocks covered by each `eth_getLogs` request unless configured otherwise, within
+/// the range limit of common providers
+pub const DEFAULT_LOG_CHUNK_SIZE: u64 = 10_000;
+
+abigen!(
+    GraphcastRegistryContract,
+    r#"[
+        function indexerToGraphcastID(address indexer) external view returns (address)
+        function setGraphcastID(address graphcastID) external
+        event SetGraphcastID(address indexed indexer, address indexed graphcastID)
+    ]"#
+);
+
+/// Registry contract deployment to read Graphcast ID registrations from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistryContractConfig {
+    /// JSON-RPC provider of the chain the registry is deployed on
+    pub rpc: Endpoint,
+    /// Address of the registry contract
+    pub address: String,
+    /// Block the contract was deployed at, where the scan for registrations starts
+    pub deployment_block: u64,
+    /// Blocks covered by each `eth_getLogs` request
+    #[serde(default = "default_log_chunk_size")]
+    pub log_chunk_size: u64,
+}
+
+fn default_log_chunk_size() -> u64 {
+    DEFAULT_LOG_CHUNK_SIZE
+}
+
+impl RegistryContractConfig {
+    pub fn new(rpc: Endpoint, address: impl Into<String>, deployment_block: u64) -> Self {
+        RegistryContractConfig {
+            rpc,
+            address: address.into(),
+            deployment_block,
+            log_chunk_size: DEFAULT_LOG_CHUNK_SIZE,
+        }
+    }
+
+    /// Scan logs in requests of at most `log_chunk_size` blocks, for providers with a
+    /// lower range limit
+    pub fn with_log_chunk_size(mut self, log_chunk_size: u64) -> Self {
+        self.log_chunk_size = log_chunk_size.max(1);
+        self
+    }
+
+    pub(crate) fn contract(
+        &self,
+        client: &QueryClient,
+    ) -> Result<GraphcastRegistryContract<Provider<Http>>, QueryError> {
+        let address = parse_address(&self.address)?;
+        Ok(GraphcastRegistryContract::new(
+            address,
+            Arc::new(provider(client, &self.rpc)?),
+        ))
+    }
+}
+
+pub(crate) fn parse_address(address: &str) -> Result<Address, QueryError> {
+    address
+        .parse()
+        .map_err(|_| QueryError::ParseResponseError(format!("Invalid address: {address}")))
+}
+
+pub(crate) fn contract_error<M: Middleware>(
+    config: &RegistryContractConfig,
+    error: ContractError<M>,
+) -> QueryError {
+    match error {
+        ContractError::ProviderError { e } => rpc_error(&config.rpc, e),
+        e => QueryError::Rpc(e.to_string()),
+    }
+}
+
+/// Registrations indexed from the `SetGraphcastID` events scanned so far
+#[derive(Debug, Default)]
+struct RegistrationLogs {
+    /// Last block scanned, `None` before the first scan
+    scanned_to: Option<u64>,
+    /// Indexer -> latest Graphcast ID
+    graphcast_ids: HashMap<Address, Address>,
+    /// Graphcast ID -> indexer currently registered with it
+    indexers: HashMap<Address, Address>,
+}
+
+impl RegistrationLogs {
+    fn register(&mut self, indexer: Address, graphcast_id: Address) {
+        if let Some(replaced) = self.graphcast_ids.insert(indexer, graphcast_id) {
+            if self.indexers.get(&replaced) == Some(&indexer) {
+                self.indexers.remove(&replaced);
+            }
+        }
+        if !graphcast_id.is_zero() {
+            self.indexers.insert(graphcast_id, indexer);
+        }
+    }
+}
+
+/// Reads Graphcast ID registrations from the registry contract's events. Logs are
+/// scanned from the deployment block in requests of `log_chunk_size` blocks, and later
+/// lookups only scan the blocks produced since. Cheap to clone, clones share the index.
+/// Serialized as its `RegistryContractConfig`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "RegistryContractConfig", into = "RegistryContractConfig")]
+pub struct RegistryContractReader {
+    config: RegistryContractConfig,
+    logs: Arc<AsyncMutex<RegistrationLogs>>,
+}
+
+impl RegistryContractReader {
+    pub fn new(config: RegistryContractConfig) -> Self {
+        RegistryContractReader {
+            config,
+            logs: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> &RegistryContractConfig {
+        &self.config
+    }
+
+    /// Scan the blocks produced since the last scan, up to the provider's latest block
+    async fn scan(
+        &self,
+        client: &QueryClient,
+    ) -> Result<tokio::sync::MutexGuard<'_, RegistrationLogs>, QueryError> {
+        let config = &self.config;
+        let contract = config.contract(client)?;
+        let mut logs = self.logs.lock().await;
+        let latest_block = contract
+            .client()
+            .get_block_number()
+            .await
+            .map_err(|e| rpc_error(&config.rpc, e))?
+            .as_u64();
+        let mut from_block = logs
+            .scanned_to
+            .map_or(config.deployment_block, |scanned_to| scanned_to + 1);
+        while from_block <= latest_block {
+            let to_block =
+                latest_block.min(from_block.saturating_add(config.log_chunk_size.max(1) - 1));
+            let events = contract
+                .set_graphcast_id_filter()
+                .from_block(from_block)
+                .to_block(to_block)
+                .query()
+                .await
+                .map_err(|e| contract_error(config, e))?;
+            trace!(
+                endpoint = %config.rpc,
+                from_block,
+                to_block,
+                events = events.len(),
+                "Scanned registry contract registrations"
+            );
+            for event in events {
+                logs.register(event.indexer, event.graphcast_id);
+            }
+            // Record progress per chunk so a failed request resumes where it stopped
+            logs.scanned_to = Some(to_block);
+            from_block = to_block + 1;
+        }
+        Ok(logs)
+    }
+
+    /// Resolve the indexer a Graphcast ID is registered for, from the latest
+    /// `SetGraphcastID` event of each indexer, confirmed with the contract's current
+    /// `indexerToGraphcastID`
+    pub async fn registered_indexer(
+        &self,
+        client: &QueryClient,
+        wallet_address: &str,
+    ) -> Result<String, QueryError> {
+        let graphcast_id = parse_address(wallet_address)?;
+        let indexer = self
+            .scan(client)
+            .await?
+            .indexers
+            .get(&graphcast_id)
+            .copied()
+            .ok_or(QueryError::ParseResponseError(format!(
+                "No indexer data queried from registry for GraphcastID: {wallet_address}"
+            )))?;
+        let current_id = self
+            .config
+            .contract(client)?
+            .indexer_to_graphcast_id(indexer)
+            .call()
+            .await
+            .map_err(|e| contract_error(&self.config, e))?;
+        if current_id != graphcast_id {
+            return Err(QueryError::ParseResponseError(format!(
+                "GraphcastID {wallet_address} is no longer registered for indexer {indexer:#x}"
+            )));
+        }
+        Ok(format!("{indexer:#x}"))
+    }
+
+    /// Every current registration, as a map from lowercased Graphcast ID to lowercased
+    /// indexer address
+    pub async fn graphcast_ids(
+        &self,
+        client: &QueryClient,
+    ) -> Result<HashMap<String, String>, QueryError> {
+        Ok(self
+            .scan(client)
+            .await?
+            .indexers
+            .iter()
+            .map(|(graphcast_id, indexer)| (format!("{graphcast_id:#x}"), format!("{indexer:#x}")))
+            .collect())
+    }
+}
+
+impl PartialEq for RegistryContractReader {
+    fn eq(&self, other: &Self) -> bool {
+        self.config == other.config
+    }
+}
+
+impl From<RegistryContractConfig> for RegistryContractReader {
+    fn from(config: RegistryContractConfig) -> Self {
+        RegistryContractReader::new(config)
+    }
+}
+
+impl From<RegistryContractReader> for RegistryContractConfig {
+    fn from(reader: RegistryContractReader) -> Self {
+        reader.config
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{routing::post, Json, Router};
+    use ethers::abi::AbiEncode;
+    use ethers::types::H256;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::graphql::http_client::tests::spawn_stub;
+
+    const REGISTRY: &str = "0x26ebbad1f2e4e9e9e2ab5e8e8d8a5d7b0c3e0e01";
+    const INDEXER: &str = "0x0000000000000000000000000000000000000abc";
+    const GRAPHCAST_ID: &str = "0x0000000000000000000000000000000000000def";
+    const REPLACED_ID: &str = "0x0000000000000000000000000000000000000123";
+
+    fn topic(address: &str) -> String {
+        format!("{:#x}", H256::from(address.parse::<Address>().unwrap()))
+    }
+
+    /// Stub JSON-RPC provider for a registry where the indexer registered
+    /// `REPLACED_ID` at block 100, then `GRAPHCAST_ID` at block 12000, with its chainhead
+    /// at block 25000. Counts the `eth_getLogs` requests
+    async fn stub_registry() -> (Endpoint, Arc<AtomicUsize>) {
+        let log_requests = Arc::new(AtomicUsize::new(0));
+        let counter = log_requests.clone();
+        let app = Router::new().route(
+            "/",
+            post(move |Json(body): Json<Value>| async move {
+                let block = |value: &Value| {
+                    value
+                        .as_str()
+                        .and_then(|number| u64::from_str_radix(&number[2..], 16).ok())
+                };
+                let result = match body["method"].as_str().unwrap() {
+                    "eth_blockNumber" => json!("0x61a8"),
+                    "eth_getLogs" => {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        let filter = &body["params"][0];
+                        let wanted = filter["topics"][2].as_str().map(str::to_string);
+                        let from_block = block(&filter["fromBlock"]).unwrap();
+                        let to_block = block(&filter["toBlock"]).unwrap_or(u64::MAX);
+                        let signature = filter["topics"][0].clone();
+                        let logs: Vec<Value> = [(REPLACED_ID, 100), (GRAPHCAST_ID, 12_000)]
+                            .iter()
+                            .filter(|(id, number)| {
+                                wanted.iter().all(|wanted| &topic(id) == wanted)
+                                    && (from_block..=to_block).contains(number)
+                            })
+                            .map(|(id, number)| {
+                                json!({
+                                    "address": REGISTRY,
+                                    "topics": [signature, topic(INDEXER), topic(id)],
+                                    "data": "0x",
+                                    "blockNumber": format!("{number:#x}"),
+                                    "logIndex": "0x0",
+                                    "removed": false
+                                })
+                            })
+                            .collect();
+                        json!(logs)
+                    }
+                    "eth_call" => {
+                        let current: Address = GRAPHCAST_ID.parse().unwrap();
+                        json!(current.encode_hex())
+                    }
+                    _ => Value::Null,
+                };
+                Json(json!({"jsonrpc": "2.0", "id": body["id"], "result": result}))
+            }),
+        );
+        let url = spawn_stub(app).await;
+        (Endpoint::new(format!("{url}/")), log_requests)
+    }
+
+    #[tokio::test]
+    async fn test_registry_contract() {
+        let (rpc, log_requests) = stub_registry().await;
+        let reader = RegistryContractReader::new(RegistryContractConfig::new(rpc, REGISTRY, 0));
+        let client = QueryClient::default();
+
+        let indexer = reader
+            .registered_indexer(&client, GRAPHCAST_ID)
+            .await
+            .unwrap();
+        assert_eq!(indexer, INDEXER);
+        // Blocks 0 to 25000 are scanned in chunks of 10000 blocks
+        assert_eq!(log_requests.load(Ordering::SeqCst), 3);
+        assert!(matches!(
+            reader.registered_indexer(&client, REPLACED_ID).await,
+            Err(QueryError::ParseResponseError(_))
+        ));
+        assert!(reader
+            .registered_indexer(&client, "0x0000000000000000000000000000000000000999")
+            .await
+            .is_err());
+        // Later lookups only scan blocks produced since, none at the same chainhead
+        assert_eq!(log_requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_registry_contract_graphcast_ids() {
+        let (rpc, log_requests) = stub_registry().await;
+        let reader = RegistryContractReader::new(
+            RegistryContractConfig::new(rpc, REGISTRY, 50).with_log_chunk_size(5_000),
+        );
+        let registrations = reader.graphcast_ids(&QueryClient::default()).await.unwrap();
+        assert_eq!(
+            registrations,
+            HashMap::from([(GRAPHCAST_ID.to_string(), INDEXER.to_string())])
+        );
+        assert_eq!(log_requests.load(Ordering::SeqCst), 5);
+    }
+
+    /// Deploy a minimal registry contract to a development chain, storing each sender's
+    /// Graphcast ID and emitting `SetGraphcastID` like the graphcast-registry contract.
+    /// Returns the contract address
+    pub(crate) async fn deploy_test_registry<M: Middleware>(client: &M) -> Address {
+        use ethers::types::TransactionRequest;
+        use ethers::utils::keccak256;
+
+        let selector = |signature: &str| keccak256(signature)[..4].to_vec();
+        let runtime = [
+            // Dispatch on the function selector, revert on others
+            vec![0x60, 0x00, 0x35, 0x60, 0xe0, 0x1c, 0x80, 0x63],
+            selector("indexerToGraphcastID(address)"),
+            vec![0x14, 0x60, 0x1e, 0x57, 0x80, 0x63],
+            selector("setGraphcastID(address)"),
+            vec![0x14, 0x60, 0x2b, 0x57, 0x60, 0x00, 0x80, 0xfd],
+            // indexerToGraphcastID: return the ID stored at the indexer's address
+            vec![
+                0x5b, 0x60, 0x04, 0x35, 0x54, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3,
+            ],
+            // setGraphcastID: store the ID at the sender's address and log the event
+            vec![0x5b, 0x60, 0x04, 0x35, 0x80, 0x33, 0x55, 0x33, 0x7f],
+            keccak256("SetGraphcastID(address,address)").to_vec(),
+            vec![0x60, 0x00, 0x80, 0xa3, 0x00],
+        ]
+        .concat();
+        // Copy the runtime code to memory and return it
+        let init = vec![
+            0x60,
+            runtime.len() as u8,
+            0x80,
+            0x60,
+            0x0b,
+            0x60,
+            0x00,
+            0x39,
+            0x60,
+            0x00,
+            0xf3,
+        ];
+        let receipt = client
+            .send_transaction(
+                TransactionRequest::new().data([init, runtime].concat()),
+                None,
+            )
+            .await
+            .unwrap()
+            .await
+            .unwrap()
+            .unwrap();
+        receipt.contract_address.unwrap()
+    }
+
+    /// Deploy a registry, then register and resolve a Graphcast ID on a local anvil
+    /// chain. Set `ANVIL_RPC_URL` to run it against another node than `127.0.0.1:8545`
+    #[tokio::test]
+    #[ignore = "requires an anvil node"]
+    async fn test_registry_contract_anvil() {
+        use ethers::middleware::SignerMiddleware;
+        use ethers::signers::{LocalWallet, Signer};
+
+        let rpc = std::env::var("ANVIL_RPC_URL").unwrap_or("http://127.0.0.1:8545".to_string());
+        let client = QueryClient::default();
+
+        // First anvil development account registers the second one as its Graphcast ID
+        let indexer: LocalWallet =
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+                .parse()
+                .unwrap();
+        let graphcast_id: Address = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8"
+            .parse()
+            .unwrap();
+        let provider = Provider::<Http>::try_from(rpc.clone()).unwrap();
+        let chain_id = provider.get_chainid().await.unwrap();
+        let signer = Arc::new(SignerMiddleware::new(
+            provider,
+            indexer.with_chain_id(chain_id.as_u64()),
+        ));
+        let address = deploy_test_registry(signer.as_ref()).await;
+        let deployment_block = signer.get_block_number().await.unwrap().as_u64();
+        let contract = GraphcastRegistryContract::new(address, signer);
+        contract
+            .set_graphcast_id(graphcast_id)
+            .send()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        let reader = RegistryContractReader::new(RegistryContractConfig::new(
+            Endpoint::new(rpc),
+            format!("{address:#x}"),
+            deployment_block,
+        ));
+        let resolved = reader
+            .registered_indexer(&client, &format!("{graphcast_id:#x}"))
+            .await
+            .unwrap();
+        assert_eq!(resolved, format!("{:#x}", contract.client().address()));
+    }
+}
@@ -0,0 +1,374 @@
+//! Ethereum JSON-RPC providers as a source of block hashes and chainheads, used in
+//! place of or alongside graph node, or to cross-check what graph node reports.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex as SyncMutex};
+
+use ethers::providers::{Http, Middleware, Provider, ProviderError};
+use ethers::types::BlockNumber;
+use serde_derive::{Deserialize, Serialize};
+use tracing::trace;
+
+use super::{endpoint::Endpoint, http_client::QueryClient, QueryError};
+use crate::BlockPointer;
+
+/// Where block hashes and chainheads are read from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlockSource {
+    /// Graph node only
+    GraphNode,
+    /// Graph node, falling back to the network's RPC provider on failure
+    #[default]
+    RpcFallback,
+    /// The network's RPC provider, falling back to graph node on failure
+    RpcPrimary,
+    /// Both, failing with `QueryError::BlockHashMismatch` when they disagree
+    CrossCheck,
+}
+
+/// JSON-RPC providers by network name, and how they combine with graph node.
+/// Networks without a provider are always read from graph node
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct RpcConfig {
+    #[serde(default)]
+    pub providers: BTreeMap<String, Endpoint>,
+    #[serde(default)]
+    pub block_source: BlockSource,
+    /// HTTP clients carrying the providers' auth headers, with the endpoint they were
+    /// built for, by network
+    #[serde(skip)]
+    clients: Arc<SyncMutex<HashMap<String, (Endpoint, reqwest::Client)>>>,
+}
+
+impl RpcConfig {
+    pub fn with_provider(mut self, network: impl Into<String>, endpoint: Endpoint) -> Self {
+        self.providers.insert(network.into(), endpoint);
+        self
+    }
+
+    pub fn with_block_source(mut self, block_source: BlockSource) -> Self {
+        self.block_source = block_source;
+        self
+    }
+
+    pub fn provider(&self, network: &str) -> Option<&Endpoint> {
+        self.providers.get(network)
+    }
+
+    /// JSON-RPC client of a network's provider. Clients carrying auth headers are built
+    /// once per provider and reused, keeping their connection pool
+    pub(crate) fn json_rpc(
+        &self,
+        client: &QueryClient,
+        network: &str,
+    ) -> Result<Provider<Http>, QueryError> {
+        let endpoint = self
+            .providers
+            .get(network)
+            .ok_or_else(|| QueryError::Rpc(format!("No JSON-RPC provider for {network}")))?;
+        if endpoint.headers().is_empty() {
+            return provider(client, endpoint);
+        }
+        let mut clients = self.clients.lock().unwrap();
+        let http_client = match clients.get(network) {
+            Some((built_for, http_client)) if built_for == endpoint => http_client.clone(),
+            _ => {
+                let http_client = authenticated_client(client, endpoint)?;
+                clients.insert(network.to_string(), (endpoint.clone(), http_client.clone()));
+                http_client
+            }
+        };
+        provider_with_client(endpoint, http_client)
+    }
+}
+
+impl fmt::Debug for RpcConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcConfig")
+            .field("providers", &self.providers)
+            .field("block_source", &self.block_source)
+            .finish()
+    }
+}
+
+impl PartialEq for RpcConfig {
+    fn eq(&self, other: &Self) -> bool {
+        self.providers == other.providers && self.block_source == other.block_source
+    }
+}
+
+impl Eq for RpcConfig {}
+
+pub(crate) fn provider(
+    client: &QueryClient,
+    endpoint: &Endpoint,
+) -> Result<Provider<Http>, QueryError> {
+    let http_client = if endpoint.headers().is_empty() {
+        client.inner().clone()
+    } else {
+        authenticated_client(client, endpoint)?
+    };
+    provider_with_client(endpoint, http_client)
+}
+
+/// HTTP client with the query client's settings sending the endpoint's auth headers
+fn authenticated_client(
+    client: &QueryClient,
+    endpoint: &Endpoint,
+) -> Result<reqwest::Client, QueryError> {
+    client
+        .config()
+        .client_builder()
+        .default_headers(endpoint.headers())
+        .build()
+        .map_err(|e| QueryError::Rpc(e.to_string()))
+}
+
+fn provider_with_client(
+    endpoint: &Endpoint,
+    http_client: reqwest::Client,
+) -> Result<Provider<Http>, QueryError> {
+    let url = endpoint
+        .request_url()
+        .parse::<reqwest::Url>()
+        .map_err(|_| QueryError::Rpc(format!("Invalid JSON-RPC provider URL {endpoint}")))?;
+    Ok(Provider::new(Http::new_with_client(url, http_client)))
+}
+
+/// Provider errors quote the requested URL, which may carry the API key
+pub(crate) fn rpc_error(endpoint: &Endpoint, error: ProviderError) -> QueryError {
+    let message = error
+        .to_string()
+        .replace(&endpoint.request_url(), &endpoint.url);
+    match &endpoint.auth.api_key {
+        Some(api_key) => QueryError::Rpc(message.replace(api_key.expose(), "[REDACTED]")),
+        None => QueryError::Rpc(message),
+    }
+}
+
+/// Hash of the block at `block_number`, as hex without the `0x` prefix like graph node's
+/// `blockHashFromNumber`
+pub async fn query_rpc_block_hash(
+    client: &QueryClient,
+    endpoint: &Endpoint,
+    block_number: u64,
+) -> Result<String, QueryError> {
+    rpc_block_hash(&provider(client, endpoint)?, endpoint, block_number).await
+}
+
+pub(crate) async fn rpc_block_hash(
+    provider: &Provider<Http>,
+    endpoint: &Endpoint,
+    block_number: u64,
+) -> Result<String, QueryError> {
+    let block = provider
+        .get_block(block_number)
+        .await
+        .map_err(|e| rpc_error(endpoint, e))?;
+    trace!(
+        endpoint = %endpoint,
+        block_number,
+        hash = tracing::field::debug(block.as_ref().and_then(|b| b.hash)),
+        "JSON-RPC block hash"
+    );
+    block
+        .and_then(|b| b.hash)
+        .map(|hash| format!("{hash:x}"))
+        .ok_or(QueryError::ParseResponseError(format!(
+            "No block {block_number} from JSON-RPC provider"
+        )))
+}
+
+/// Latest block of the provider's chain, with a `0x` prefixed hash like graph node's
+/// `chainHeadBlock`
+pub async fn query_rpc_chainhead(
+    client: &QueryClient,
+    endpoint: &Endpoint,
+) -> Result<BlockPointer, QueryError> {
+    rpc_chainhead(&provider(client, endpoint)?, endpoint).await
+}
+
+pub(crate) async fn rpc_chainhead(
+    provider: &Provider<Http>,
+    endpoint: &Endpoint,
+) -> Result<BlockPointer, QueryError> {
+    let block = provider
+        .get_block(BlockNumber::Latest)
+        .await
+        .map_err(|e| rpc_error(endpoint, e))?;
+    match block.and_then(|b| Some((b.number?, b.hash?))) {
+        Some((number, hash)) => Ok(BlockPointer::new(number.as_u64(), format!("{hash:#x}"))),
+        None => Err(QueryError::ParseResponseError(String::from(
+            "No latest block from JSON-RPC provider",
+        ))),
+    }
+}
+
+/// Compare block hashes regardless of `0x` prefix and case
+pub fn same_block_hash(a: &str, b: &str) -> bool {
+    let normalize = |hash: &str| hash.trim_start_matches("0x").to_lowercase();
+    normalize(a) == normalize(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::post, Json, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::callbook::CallBook;
+    use crate::graphql::endpoint::{EndpointAuth, Secret};
+    use crate::graphql::http_client::tests::spawn_stub;
+    use crate::graphql::http_client::QueryClientConfig;
+
+    const CHAINHEAD: u64 = 1_000;
+
+    /// Block hash served by the stub provider for a block number
+    fn stub_hash(number: u64) -> String {
+        format!("{number:064x}")
+    }
+
+    fn block(number: u64) -> Value {
+        json!({
+            "number": format!("{number:#x}"),
+            "hash": format!("0x{}", stub_hash(number)),
+            "parentHash": format!("0x{}", stub_hash(number - 1)),
+            "sha3Uncles": format!("0x{}", "0".repeat(64)),
+            "miner": format!("0x{}", "0".repeat(40)),
+            "stateRoot": format!("0x{}", "0".repeat(64)),
+            "transactionsRoot": format!("0x{}", "0".repeat(64)),
+            "receiptsRoot": format!("0x{}", "0".repeat(64)),
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "difficulty": "0x0",
+            "gasLimit": "0x1c9c380",
+            "gasUsed": "0x0",
+            "timestamp": "0x64000000",
+            "extraData": "0x",
+            "transactions": [],
+            "uncles": []
+        })
+    }
+
+    /// Stub JSON-RPC provider serving blocks up to `CHAINHEAD`
+    async fn stub_rpc() -> Endpoint {
+        let app = Router::new().route(
+            "/",
+            post(|Json(body): Json<Value>| async move {
+                let number = match body["params"][0].as_str().unwrap() {
+                    "latest" => CHAINHEAD,
+                    hex => u64::from_str_radix(hex.trim_start_matches("0x"), 16).unwrap(),
+                };
+                let result = if number <= CHAINHEAD {
+                    block(number)
+                } else {
+                    Value::Null
+                };
+                Json(json!({"jsonrpc": "2.0", "id": body["id"], "result": result}))
+            }),
+        );
+        let url = spawn_stub(app).await;
+        Endpoint::new(format!("{url}/"))
+    }
+
+    #[tokio::test]
+    async fn test_rpc_blocks() {
+        let endpoint = stub_rpc().await;
+        let client = QueryClient::default();
+
+        let hash = query_rpc_block_hash(&client, &endpoint, 42).await.unwrap();
+        assert_eq!(hash, stub_hash(42));
+        assert!(matches!(
+            query_rpc_block_hash(&client, &endpoint, CHAINHEAD + 1).await,
+            Err(QueryError::ParseResponseError(_))
+        ));
+
+        let head = query_rpc_chainhead(&client, &endpoint).await.unwrap();
+        assert_eq!(head.number, CHAINHEAD);
+        assert!(same_block_hash(&head.hash, &stub_hash(CHAINHEAD)));
+    }
+
+    #[tokio::test]
+    async fn test_authenticated_provider_timeout() {
+        let app = Router::new().route(
+            "/",
+            post(|| async {
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                Json(Value::Null)
+            }),
+        );
+        let url = spawn_stub(app).await;
+        let endpoint = Endpoint::new(format!("{url}/"))
+            .with_auth(EndpointAuth::default().with_header("x-api-key", Secret::new("key")));
+        let client = QueryClient::new(QueryClientConfig {
+            request_timeout: std::time::Duration::from_millis(200),
+            ..Default::default()
+        });
+
+        let started = std::time::Instant::now();
+        assert!(query_rpc_chainhead(&client, &endpoint).await.is_err());
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_authenticated_provider_reused() {
+        let rpc = stub_rpc()
+            .await
+            .with_auth(EndpointAuth::default().with_header("x-api-key", Secret::new("key")));
+        let rpc_config = RpcConfig::default().with_provider("mainnet", rpc);
+        let callbook =
+            CallBook::new(String::new(), String::new(), None).with_rpc_config(rpc_config.clone());
+
+        callbook.block_hash("mainnet", 42).await.unwrap();
+        callbook.chainhead("mainnet").await.unwrap();
+        assert_eq!(rpc_config.clients.lock().unwrap().len(), 1);
+    }
+
+    /// Stub graph node answering `blockHashFromNumber` with `hash`
+    async fn stub_graph_node(hash: String) -> String {
+        let app = Router::new().route(
+            "/graphql",
+            post(move |_: Json<Value>| async move {
+                Json(json!({"data": {"blockHashFromNumber": hash}}))
+            }),
+        );
+        let url = spawn_stub(app).await;
+        format!("{url}/graphql")
+    }
+
+    #[tokio::test]
+    async fn test_callbook_block_sources() {
+        let rpc = stub_rpc().await;
+        let rpc_config = RpcConfig::default().with_provider("mainnet", rpc);
+
+        // Graph node not configured: fall back to the provider
+        let callbook =
+            CallBook::new(String::new(), String::new(), None).with_rpc_config(rpc_config.clone());
+        assert_eq!(
+            callbook.block_hash("mainnet", 42).await.unwrap(),
+            stub_hash(42)
+        );
+        assert_eq!(
+            callbook.chainhead("mainnet").await.unwrap().number,
+            CHAINHEAD
+        );
+        assert!(callbook.block_hash("goerli", 42).await.is_err());
+
+        // Graph node agrees for block 42 only
+        let graph_node = stub_graph_node(stub_hash(42)).await;
+        let callbook = CallBook::new(String::new(), String::new(), Some(graph_node))
+            .with_rpc_config(rpc_config.with_block_source(BlockSource::CrossCheck));
+        assert_eq!(
+            callbook.block_hash("mainnet", 42).await.unwrap(),
+            stub_hash(42)
+        );
+        assert!(matches!(
+            callbook.block_hash("mainnet", 43).await,
+            Err(QueryError::BlockHashMismatch {
+                block_number: 43,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,378 @@
+//! Typed deployment indexing statuses and a watcher turning successive statuses into
+//! events, so radios and bots can react when a deployment turns unhealthy or fails,
+//! falls behind its chainhead, or finishes syncing.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::client_graph_node::indexing_statuses::{
+    Health, IndexingStatusesIndexingStatuses, IndexingStatusesIndexingStatusesChains,
+};
+use crate::BlockPointer;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeploymentHealth {
+    /// Syncing normally
+    Healthy,
+    /// Syncing but with errors
+    Unhealthy,
+    /// Halted due to errors
+    Failed,
+}
+
+impl fmt::Display for DeploymentHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeploymentHealth::Healthy => write!(f, "healthy"),
+            DeploymentHealth::Unhealthy => write!(f, "unhealthy"),
+            DeploymentHealth::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+impl From<&Health> for DeploymentHealth {
+    fn from(health: &Health) -> Self {
+        match health {
+            Health::Healthy => DeploymentHealth::Healthy,
+            Health::Unhealthy => DeploymentHealth::Unhealthy,
+            // Unknown health values are treated as failures to surface them
+            Health::Failed | Health::Other(_) => DeploymentHealth::Failed,
+        }
+    }
+}
+
+/// Error that halted a deployment
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeploymentError {
+    pub handler: Option<String>,
+    pub message: String,
+}
+
+/// Indexing progress of a deployment on one chain
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainStatus {
+    pub network: String,
+    pub latest_block: Option<BlockPointer>,
+    pub chain_head_block: Option<BlockPointer>,
+}
+
+impl ChainStatus {
+    /// Number of blocks between the latest indexed block and the chainhead,
+    /// if graph node reported both
+    pub fn blocks_behind(&self) -> Option<u64> {
+        match (&self.latest_block, &self.chain_head_block) {
+            (Some(latest), Some(head)) => Some(head.number.saturating_sub(latest.number)),
+            _ => None,
+        }
+    }
+}
+
+impl From<IndexingStatusesIndexingStatusesChains> for ChainStatus {
+    fn from(chain: IndexingStatusesIndexingStatusesChains) -> Self {
+        ChainStatus {
+            network: chain.network,
+            latest_block: chain
+                .latest_block
+                .map(|b| BlockPointer::new(b.number.parse().unwrap_or_default(), b.hash)),
+            chain_head_block: chain
+                .chain_head_block
+                .map(|b| BlockPointer::new(b.number.parse().unwrap_or_default(), b.hash)),
+        }
+    }
+}
+
+/// Indexing status of a deployment as reported by graph node
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeploymentStatus {
+    pub deployment: String,
+    pub synced: bool,
+    pub health: DeploymentHealth,
+    /// Graph node instance indexing the deployment
+    pub node: Option<String>,
+    pub fatal_error: Option<DeploymentError>,
+    pub chains: Vec<ChainStatus>,
+}
+
+impl DeploymentStatus {
+    /// Largest distance to the chainhead across the deployment's chains
+    pub fn blocks_behind(&self) -> Option<u64> {
+        self.chains
+            .iter()
+            .filter_map(ChainStatus::blocks_behind)
+            .max()
+    }
+
+    /// Chain furthest behind its chainhead
+    fn lagging_chain(&self) -> Option<(&str, u64)> {
+        self.chains
+            .iter()
+            .filter_map(|c| c.blocks_behind().map(|behind| (c.network.as_str(), behind)))
+            .max_by_key(|(_, behind)| *behind)
+    }
+}
+
+impl From<IndexingStatusesIndexingStatuses> for DeploymentStatus {
+    fn from(status: IndexingStatusesIndexingStatuses) -> Self {
+        DeploymentStatus {
+            health: DeploymentHealth::from(&status.health),
+            deployment: status.subgraph,
+            synced: status.synced,
+            node: status.node,
+            fatal_error: status.fatal_error.map(|e| DeploymentError {
+                handler: e.handler,
+                message: e.message,
+            }),
+            chains: status.chains.into_iter().map(ChainStatus::from).collect(),
+        }
+    }
+}
+
+/// Change in a deployment's status worth reacting to
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeploymentStatusEvent {
+    Unhealthy {
+        deployment: String,
+    },
+    Failed {
+        deployment: String,
+        error: Option<DeploymentError>,
+    },
+    FallingBehind {
+        deployment: String,
+        network: String,
+        blocks_behind: u64,
+    },
+    Synced {
+        deployment: String,
+    },
+}
+
+impl DeploymentStatusEvent {
+    pub fn deployment(&self) -> &str {
+        match self {
+            DeploymentStatusEvent::Unhealthy { deployment }
+            | DeploymentStatusEvent::Failed { deployment, .. }
+            | DeploymentStatusEvent::FallingBehind { deployment, .. }
+            | DeploymentStatusEvent::Synced { deployment } => deployment,
+        }
+    }
+}
+
+/// Formats as a one line message, such as the content of a bot alert
+impl fmt::Display for DeploymentStatusEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeploymentStatusEvent::Unhealthy { deployment } => {
+                write!(f, "Deployment {deployment} is syncing with errors")
+            }
+            DeploymentStatusEvent::Failed {
+                deployment,
+                error: Some(error),
+            } => write!(f, "Deployment {deployment} failed: {}", error.message),
+            DeploymentStatusEvent::Failed { deployment, .. } => {
+                write!(f, "Deployment {deployment} failed")
+            }
+            DeploymentStatusEvent::FallingBehind {
+                deployment,
+                network,
+                blocks_behind,
+            } => write!(
+                f,
+                "Deployment {deployment} is {blocks_behind} blocks behind the {network} chainhead"
+            ),
+            DeploymentStatusEvent::Synced { deployment } => {
+                write!(f, "Deployment {deployment} finished syncing")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeploymentWatcherConfig {
+    /// How often to poll graph node for indexing statuses
+    pub poll_interval: Duration,
+    /// Blocks behind the chainhead past which a deployment is reported as falling behind
+    pub max_blocks_behind: u64,
+}
+
+impl Default for DeploymentWatcherConfig {
+    fn default() -> Self {
+        DeploymentWatcherConfig {
+            poll_interval: Duration::from_secs(30),
+            max_blocks_behind: 100,
+        }
+    }
+}
+
+/// Compares each poll of deployment statuses with the previous one and reports the
+/// transitions. Events fire once per transition, not on every poll
+#[derive(Clone, Debug, Default)]
+pub struct DeploymentWatcher {
+    max_blocks_behind: u64,
+    previous: HashMap<String, DeploymentStatus>,
+}
+
+impl DeploymentWatcher {
+    pub fn new(max_blocks_behind: u64) -> Self {
+        DeploymentWatcher {
+            max_blocks_behind,
+            previous: HashMap::new(),
+        }
+    }
+
+    /// Record the latest statuses and return the events since the last observation.
+    /// Deployments seen for the first time report unhealthy, failed or falling behind
+    /// states, but not being synced
+    pub fn observe(&mut self, statuses: Vec<DeploymentStatus>) -> Vec<DeploymentStatusEvent> {
+        let mut events = vec![];
+        let mut current = HashMap::with_capacity(statuses.len());
+        for status in statuses {
+            let previous = self.previous.get(&status.deployment);
+            if previous.map(|p| &p.health) != Some(&status.health) {
+                match status.health {
+                    DeploymentHealth::Unhealthy => events.push(DeploymentStatusEvent::Unhealthy {
+                        deployment: status.deployment.clone(),
+                    }),
+                    DeploymentHealth::Failed => events.push(DeploymentStatusEvent::Failed {
+                        deployment: status.deployment.clone(),
+                        error: status.fatal_error.clone(),
+                    }),
+                    DeploymentHealth::Healthy => {}
+                }
+            }
+            let was_behind = previous
+                .and_then(DeploymentStatus::blocks_behind)
+                .is_some_and(|behind| behind > self.max_blocks_behind);
+            match status.lagging_chain() {
+                Some((network, behind)) if behind > self.max_blocks_behind && !was_behind => events
+                    .push(DeploymentStatusEvent::FallingBehind {
+                        deployment: status.deployment.clone(),
+                        network: network.to_string(),
+                        blocks_behind: behind,
+                    }),
+                _ => {}
+            }
+            if status.synced && previous.is_some_and(|p| !p.synced) {
+                events.push(DeploymentStatusEvent::Synced {
+                    deployment: status.deployment.clone(),
+                });
+            }
+            current.insert(status.deployment.clone(), status);
+        }
+        self.previous = current;
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::callbook::CallBook;
+    use crate::data_source::FixtureDataSource;
+
+    fn status(
+        deployment: &str,
+        health: DeploymentHealth,
+        synced: bool,
+        latest: u64,
+    ) -> DeploymentStatus {
+        DeploymentStatus {
+            deployment: deployment.to_string(),
+            synced,
+            health,
+            node: Some("default".to_string()),
+            fatal_error: None,
+            chains: vec![ChainStatus {
+                network: "mainnet".to_string(),
+                latest_block: Some(BlockPointer::new(latest, "0xlatest".to_string())),
+                chain_head_block: Some(BlockPointer::new(1_000, "0xhead".to_string())),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_watcher_transitions() {
+        let mut watcher = DeploymentWatcher::new(100);
+        let events = watcher.observe(vec![
+            status("Qm1", DeploymentHealth::Healthy, false, 500),
+            status("Qm2", DeploymentHealth::Healthy, true, 1_000),
+        ]);
+        assert_eq!(
+            events,
+            vec![DeploymentStatusEvent::FallingBehind {
+                deployment: "Qm1".to_string(),
+                network: "mainnet".to_string(),
+                blocks_behind: 500,
+            }]
+        );
+
+        // Still behind: no repeated event
+        let events = watcher.observe(vec![
+            status("Qm1", DeploymentHealth::Healthy, false, 600),
+            status("Qm2", DeploymentHealth::Unhealthy, true, 1_000),
+        ]);
+        assert_eq!(
+            events,
+            vec![DeploymentStatusEvent::Unhealthy {
+                deployment: "Qm2".to_string()
+            }]
+        );
+
+        let mut failed = status("Qm2", DeploymentHealth::Failed, true, 1_000);
+        failed.fatal_error = Some(DeploymentError {
+            handler: None,
+            message: "deterministic error".to_string(),
+        });
+        let events = watcher.observe(vec![
+            status("Qm1", DeploymentHealth::Healthy, true, 1_000),
+            failed,
+        ]);
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0],
+            DeploymentStatusEvent::Synced {
+                deployment: "Qm1".to_string()
+            }
+        );
+        assert_eq!(
+            events[1].to_string(),
+            "Deployment Qm2 failed: deterministic error"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_deployments() {
+        let statuses = serde_json::from_value(serde_json::json!([{
+            "subgraph": "Qm1",
+            "synced": false,
+            "health": "failed",
+            "node": null,
+            "fatalError": {"handler": null, "message": "boom"},
+            "chains": []
+        }]))
+        .unwrap();
+        let callbook = CallBook::new(String::new(), String::new(), None).with_data_source(
+            Arc::new(FixtureDataSource::new().with_indexing_statuses(statuses)),
+        );
+
+        let mut events = callbook.watch_deployments(DeploymentWatcherConfig {
+            poll_interval: Duration::from_millis(10),
+            max_blocks_behind: 100,
+        });
+        assert_eq!(
+            events.recv().await,
+            Some(DeploymentStatusEvent::Failed {
+                deployment: "Qm1".to_string(),
+                error: Some(DeploymentError {
+                    handler: None,
+                    message: "boom".to_string()
+                }),
+            })
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Query endpoints and their authentication.
+//!
+//! The decentralized gateway expects an API key either in the URL path or as an
+//! `Authorization: Bearer` header, and self-hosted endpoints may sit behind a proxy
+//! requiring custom headers. An `Endpoint` carries the URL along with its
+//! `EndpointAuth`, whose secrets can be read from files or environment variables and
+//! are redacted from `Debug`, `Serialize` and log output.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::RequestBuilder;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::warn;
+
+/// Placeholders replaced by the API key in endpoint URLs, such as
+/// `https://gateway.thegraph.com/api/[api-key]/subgraphs/id/<id>`
+const API_KEY_PLACEHOLDERS: [&str; 2] = ["[api-key]", "{api_key}"];
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Secret value that never shows up in `Debug` or `Serialize` output
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Secret(value.into())
+    }
+
+    /// Read the secret from a file, ignoring surrounding whitespace
+    pub fn from_file(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        Ok(Secret(
+            std::fs::read_to_string(path.into())?.trim().to_string(),
+        ))
+    }
+
+    pub fn from_env(name: &str) -> Result<Self, std::env::VarError> {
+        std::env::var(name).map(Secret)
+    }
+
+    /// Plain secret value, only to be used when building requests
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+/// A secret is configured as a plain value, `{ file = "<path>" }` or `{ env = "<VAR>" }`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SecretSource {
+    Value(String),
+    File { file: PathBuf },
+    Env { env: String },
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match SecretSource::deserialize(deserializer)? {
+            SecretSource::Value(value) => Ok(Secret(value)),
+            SecretSource::File { file } => Secret::from_file(&file).map_err(|e| {
+                serde::de::Error::custom(format!("Could not read secret file {file:?}: {e}"))
+            }),
+            SecretSource::Env { env } => Secret::from_env(&env).map_err(|e| {
+                serde::de::Error::custom(format!("Could not read secret from env {env}: {e}"))
+            }),
+        }
+    }
+}
+
+/// Authentication applied to every request made to an endpoint
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointAuth {
+    /// Gateway API key, substituted for `[api-key]` in the URL or sent as a bearer
+    /// token when the URL has no placeholder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<Secret>,
+    /// Sent as `Authorization: Bearer <token>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bearer_token: Option<Secret>,
+    /// Additional headers, such as those required by an authenticating proxy
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, Secret>,
+}
+
+impl EndpointAuth {
+    pub fn api_key(api_key: Secret) -> Self {
+        EndpointAuth {
+            api_key: Some(api_key),
+            ..Default::default()
+        }
+    }
+
+    pub fn bearer_token(token: Secret) -> Self {
+        EndpointAuth {
+            bearer_token: Some(token),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: Secret) -> Self {
+        self.headers.insert(name.into(), value);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.api_key.is_none() && self.bearer_token.is_none() && self.headers.is_empty()
+    }
+}
+
+/// Query endpoint URL with its authentication
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Endpoint {
+    /// URL as configured, with the API key placeholder if any
+    pub url: String,
+    #[serde(skip_serializing_if = "EndpointAuth::is_empty")]
+    pub auth: EndpointAuth,
+}
+
+impl Endpoint {
+    pub fn new(url: impl Into<String>) -> Self {
+        Endpoint {
+            url: url.into(),
+            auth: EndpointAuth::default(),
+        }
+    }
+
+    pub fn with_auth(mut self, auth: EndpointAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    fn has_api_key_placeholder(&self) -> bool {
+        API_KEY_PLACEHOLDERS
+            .iter()
+            .any(|placeholder| self.url.contains(placeholder))
+    }
+
+    /// Whether the URL requested carries a secret, so it must not appear in errors or logs
+    pub fn url_has_secret(&self) -> bool {
+        self.auth.api_key.is_some() && self.has_api_key_placeholder()
+    }
+
+    /// URL to request, with the API key substituted for its placeholder
+    pub(crate) fn request_url(&self) -> String {
+        match &self.auth.api_key {
+            Some(api_key) => API_KEY_PLACEHOLDERS
+                .iter()
+                .fold(self.url.clone(), |url, placeholder| {
+                    url.replace(placeholder, api_key.expose())
+                }),
+            None => self.url.clone(),
+        }
+    }
+
+    /// Authentication headers sent with every request, marked sensitive.
+    /// Headers with an invalid name or value are skipped
+    pub(crate) fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let bearer_token = match (&self.auth.bearer_token, &self.auth.api_key) {
+            (Some(token), _) => Some(token),
+            (None, Some(api_key)) if !self.has_api_key_placeholder() => Some(api_key),
+            _ => None,
+        };
+        let bearer = bearer_token.map(|token| {
+            (
+                AUTHORIZATION.as_str().to_string(),
+                format!("Bearer {}", token.expose()),
+            )
+        });
+        let custom = self
+            .auth
+            .headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.expose().to_string()));
+        for (name, value) in bearer.into_iter().chain(custom) {
+            match (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(&value),
+            ) {
+                (Ok(name), Ok(mut value)) => {
+                    value.set_sensitive(true);
+                    headers.insert(name, value);
+                }
+                _ => warn!(
+                    endpoint = %self,
+                    header = name,
+                    "Skip invalid authentication header"
+                ),
+            }
+        }
+        headers
+    }
+
+    /// Start a POST request to the endpoint with its authentication applied
+    pub fn post(&self, client: &reqwest::Client) -> RequestBuilder {
+        client.post(self.request_url()).headers(self.headers())
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.url)
+    }
+}
+
+impl From<&str> for Endpoint {
+    fn from(url: &str) -> Self {
+        Endpoint::new(url)
+    }
+}
+
+impl From<String> for Endpoint {
+    fn from(url: String) -> Self {
+        Endpoint::new(url)
+    }
+}
+
+/// An endpoint is configured either as a bare URL or as `{ url, auth }`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EndpointConfig {
+    Url(String),
+    WithAuth {
+        url: String,
+        #[serde(default)]
+        auth: EndpointAuth,
+    },
+}
+
+impl<'de> Deserialize<'de> for Endpoint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match EndpointConfig::deserialize(deserializer)? {
+            EndpointConfig::Url(url) => Endpoint::new(url),
+            EndpointConfig::WithAuth { url, auth } => Endpoint { url, auth },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secrets_redacted() {
+        let endpoint = Endpoint::new("https://gateway.thegraph.com/api/[api-key]/subgraphs/id/1")
+            .with_auth(
+                EndpointAuth::api_key(Secret::new("supersecretkey"))
+                    .with_header("x-proxy-token", Secret::new("supersecretheader")),
+            );
+        let debug = format!("{endpoint:?}");
+        let serialized = serde_json::to_string(&endpoint).unwrap();
+        for output in [debug, serialized, endpoint.to_string()] {
+            assert!(!output.contains("supersecret"), "{output}");
+        }
+        assert!(endpoint.url_has_secret());
+    }
+
+    #[test]
+    fn test_apply_auth() {
+        let client = reqwest::Client::new();
+        let in_path = Endpoint::new("https://gateway.thegraph.com/api/[api-key]/subgraphs/id/1")
+            .with_auth(EndpointAuth::api_key(Secret::new("key")));
+        let request = in_path.post(&client).build().unwrap();
+        assert_eq!(
+            request.url().as_str(),
+            "https://gateway.thegraph.com/api/key/subgraphs/id/1"
+        );
+        assert!(request.headers().get("authorization").is_none());
+
+        let as_header = Endpoint::new("https://gateway.thegraph.com/api/subgraphs/id/1").with_auth(
+            EndpointAuth::api_key(Secret::new("key")).with_header("x-extra", Secret::new("v")),
+        );
+        let request = as_header.post(&client).build().unwrap();
+        assert_eq!(request.headers()["authorization"], "Bearer key");
+        assert_eq!(request.headers()["x-extra"], "v");
+    }
+
+    #[test]
+    fn test_deserialize_secret_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        std::fs::write(&path, "from-file\n").unwrap();
+        std::env::set_var("GRAPHCAST_TEST_ENDPOINT_KEY", "from-env");
+
+        let endpoint: Endpoint = serde_json::from_value(serde_json::json!({
+            "url": "http://localhost:8000",
+            "auth": {
+                "api_key": {"env": "GRAPHCAST_TEST_ENDPOINT_KEY"},
+                "bearer_token": {"file": path},
+                "headers": {"x-plain": "plain"}
+            }
+        }))
+        .unwrap();
+        assert_eq!(endpoint.auth.api_key.unwrap().expose(), "from-env");
+        assert_eq!(endpoint.auth.bearer_token.unwrap().expose(), "from-file");
+        assert_eq!(endpoint.auth.headers["x-plain"].expose(), "plain");
+
+        let bare: Endpoint = serde_json::from_str("\"http://localhost:8000\"").unwrap();
+        assert_eq!(bare, Endpoint::new("http://localhost:8000"));
+    }
+}
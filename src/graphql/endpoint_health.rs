@@ -0,0 +1,122 @@
+//! Health of query endpoints that have alternatives, such as several graph node status
+//! endpoints, so queries try the healthiest first and operators can see which are down.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as SyncMutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::{endpoint::Endpoint, QueryError};
+
+/// Outcome of the recent requests to an endpoint
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointHealth {
+    /// Failed requests since the last success
+    pub consecutive_failures: u32,
+    /// Unix timestamp of the last successful request
+    pub last_success: Option<u64>,
+    /// Unix timestamp of the last failed request
+    pub last_failure: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+impl EndpointHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.consecutive_failures == 0
+    }
+}
+
+/// Health of each endpoint by URL, shared by every clone of the tracker
+#[derive(Clone, Debug, Default)]
+pub struct EndpointHealthTracker {
+    endpoints: Arc<SyncMutex<HashMap<String, EndpointHealth>>>,
+}
+
+impl EndpointHealthTracker {
+    pub fn record_success(&self, endpoint: &Endpoint) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let health = endpoints.entry(endpoint.url.clone()).or_default();
+        health.consecutive_failures = 0;
+        health.last_success = Some(unix_now());
+    }
+
+    /// Count a failure against the endpoint. Only transport failures and timeouts are
+    /// counted; errors about the queried data say nothing about the endpoint
+    pub fn record_failure(&self, endpoint: &Endpoint, error: &QueryError) {
+        if !matches!(error, QueryError::Transport(_) | QueryError::Timeout(_)) {
+            return;
+        }
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let health = endpoints.entry(endpoint.url.clone()).or_default();
+        health.consecutive_failures += 1;
+        health.last_failure = Some(unix_now());
+        health.last_error = Some(error.to_string());
+    }
+
+    /// Health of an endpoint, healthy if it has not been queried yet
+    pub fn health(&self, endpoint: &Endpoint) -> EndpointHealth {
+        self.endpoints
+            .lock()
+            .unwrap()
+            .get(&endpoint.url)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Health of every endpoint queried so far, by URL
+    pub fn all(&self) -> HashMap<String, EndpointHealth> {
+        self.endpoints.lock().unwrap().clone()
+    }
+
+    /// Endpoints ordered from the fewest consecutive failures, keeping the
+    /// configured order among equals
+    pub fn by_health<'a>(
+        &self,
+        endpoints: impl IntoIterator<Item = &'a Endpoint>,
+    ) -> Vec<&'a Endpoint> {
+        let mut endpoints: Vec<&Endpoint> = endpoints.into_iter().collect();
+        endpoints.sort_by_key(|endpoint| self.health(endpoint).consecutive_failures);
+        endpoints
+    }
+}
+
+/// Trackers are compared by what they track, not by the recorded health
+impl PartialEq for EndpointHealthTracker {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_by_health() {
+        let tracker = EndpointHealthTracker::default();
+        let endpoints = [
+            Endpoint::new("http://index-node-0:8030/graphql"),
+            Endpoint::new("http://index-node-1:8030/graphql"),
+        ];
+        tracker.record_failure(&endpoints[0], &QueryError::Timeout("slow".to_string()));
+        // Data errors do not count against the endpoint
+        tracker.record_failure(&endpoints[1], &QueryError::IndexingError);
+
+        let ordered = tracker.by_health(&endpoints);
+        assert_eq!(ordered, vec![&endpoints[1], &endpoints[0]]);
+        assert!(!tracker.health(&endpoints[0]).is_healthy());
+        assert!(tracker.health(&endpoints[1]).is_healthy());
+
+        tracker.record_success(&endpoints[0]);
+        assert!(tracker.health(&endpoints[0]).is_healthy());
+        assert_eq!(tracker.by_health(&endpoints)[0], &endpoints[0]);
+    }
+}
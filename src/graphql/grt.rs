@@ -0,0 +1,223 @@
+//! Exact GRT token amounts.
+//!
+//! GRT has 18 decimals and subgraphs report amounts as integer strings of its smallest
+//! unit (wei). `GrtAmount` keeps that integer, so stakes compare exactly at any size,
+//! and reads and displays amounts in GRT with up to 18 decimals.
+
+use std::fmt;
+use std::iter::Sum;
+use std::ops::Add;
+use std::str::FromStr;
+
+use num_bigint::BigUint;
+use num_traits::{ToPrimitive, Zero};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Number of decimals of the GRT token
+pub const GRT_DECIMALS: usize = 18;
+
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum GrtAmountError {
+    #[error("Invalid GRT amount: {0:?}")]
+    Invalid(String),
+    #[error("GRT amount has more than 18 decimals: {0:?}")]
+    TooPrecise(String),
+}
+
+/// Non-negative amount of GRT, stored in wei
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GrtAmount(BigUint);
+
+impl GrtAmount {
+    pub fn from_wei(wei: BigUint) -> Self {
+        GrtAmount(wei)
+    }
+
+    /// Parse an integer amount of wei, as returned by the subgraphs
+    pub fn from_wei_str(wei: &str) -> Result<Self, GrtAmountError> {
+        if wei.is_empty() || !wei.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(GrtAmountError::Invalid(wei.to_string()));
+        }
+        BigUint::from_str(wei)
+            .map(GrtAmount)
+            .map_err(|_| GrtAmountError::Invalid(wei.to_string()))
+    }
+
+    /// Whole amount of GRT
+    pub fn from_grt(grt: u64) -> Self {
+        GrtAmount(BigUint::from(grt) * wei_per_grt())
+    }
+
+    pub fn wei(&self) -> &BigUint {
+        &self.0
+    }
+
+    /// Approximate amount in GRT, for display and metrics only
+    pub fn to_grt_f64(&self) -> f64 {
+        self.0.to_f64().unwrap_or(f64::INFINITY) / 1e18
+    }
+
+    pub fn checked_sub(&self, other: &GrtAmount) -> Option<GrtAmount> {
+        (self.0 >= other.0).then(|| GrtAmount(&self.0 - &other.0))
+    }
+
+    pub fn saturating_sub(&self, other: &GrtAmount) -> GrtAmount {
+        self.checked_sub(other).unwrap_or_default()
+    }
+}
+
+fn wei_per_grt() -> BigUint {
+    BigUint::from(10_u64.pow(GRT_DECIMALS as u32))
+}
+
+impl Zero for GrtAmount {
+    fn zero() -> Self {
+        GrtAmount(BigUint::zero())
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+impl Add for GrtAmount {
+    type Output = GrtAmount;
+
+    fn add(self, other: GrtAmount) -> GrtAmount {
+        GrtAmount(self.0 + other.0)
+    }
+}
+
+impl<'a> Add<&'a GrtAmount> for &'a GrtAmount {
+    type Output = GrtAmount;
+
+    fn add(self, other: &GrtAmount) -> GrtAmount {
+        GrtAmount(&self.0 + &other.0)
+    }
+}
+
+impl Sum for GrtAmount {
+    fn sum<I: Iterator<Item = GrtAmount>>(iter: I) -> Self {
+        iter.fold(GrtAmount::zero(), Add::add)
+    }
+}
+
+impl<'a> Sum<&'a GrtAmount> for GrtAmount {
+    fn sum<I: Iterator<Item = &'a GrtAmount>>(iter: I) -> Self {
+        iter.fold(GrtAmount::zero(), |acc, amount| &acc + amount)
+    }
+}
+
+/// Formats in GRT with trailing zero decimals removed, such as `100000` or `1.5`
+impl fmt::Display for GrtAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let wei = self.0.to_str_radix(10);
+        let wei = format!("{wei:0>width$}", width = GRT_DECIMALS + 1);
+        let (whole, fraction) = wei.split_at(wei.len() - GRT_DECIMALS);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            write!(f, "{whole}")
+        } else {
+            write!(f, "{whole}.{fraction}")
+        }
+    }
+}
+
+/// Parses an amount in GRT with up to 18 decimals, such as `100000` or `1.5`
+impl FromStr for GrtAmount {
+    type Err = GrtAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(GrtAmountError::Invalid(s.to_string()));
+        }
+        if !(whole.bytes().chain(fraction.bytes())).all(|b| b.is_ascii_digit()) {
+            return Err(GrtAmountError::Invalid(s.to_string()));
+        }
+        if fraction.len() > GRT_DECIMALS {
+            return Err(GrtAmountError::TooPrecise(s.to_string()));
+        }
+        let wei = format!("{whole}{fraction:0<GRT_DECIMALS$}");
+        GrtAmount::from_wei_str(&wei).map_err(|_| GrtAmountError::Invalid(s.to_string()))
+    }
+}
+
+impl Serialize for GrtAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for GrtAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        GrtAmount::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        let stake = GrtAmount::from_wei_str("30921273477321769415119223").unwrap();
+        assert_eq!(stake.to_string(), "30921273.477321769415119223");
+        assert_eq!(
+            GrtAmount::from_str("30921273.477321769415119223").unwrap(),
+            stake
+        );
+        assert_eq!(GrtAmount::from_grt(100_000).to_string(), "100000");
+        assert_eq!(
+            GrtAmount::from_wei_str("1").unwrap().to_string(),
+            "0.000000000000000001"
+        );
+        assert_eq!(GrtAmount::zero().to_string(), "0");
+        assert_eq!(GrtAmount::from_str("1.5").unwrap().to_string(), "1.5");
+        assert_eq!(GrtAmount::from_str(".5").unwrap().to_string(), "0.5");
+
+        assert!(GrtAmount::from_wei_str("abc").is_err());
+        assert!(GrtAmount::from_wei_str("-1").is_err());
+        assert!(GrtAmount::from_wei_str("").is_err());
+        assert!(GrtAmount::from_str("1.2.3").is_err());
+        assert!(GrtAmount::from_str(".").is_err());
+        assert_eq!(
+            GrtAmount::from_str("0.0000000000000000001"),
+            Err(GrtAmountError::TooPrecise(
+                "0.0000000000000000001".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_exact_comparison() {
+        // Both round to the same f32, but differ by one wei
+        let minimum = GrtAmount::from_wei_str("100000000000000000000000").unwrap();
+        let below = GrtAmount::from_wei_str("99999999999999999999999").unwrap();
+        assert!(below < minimum);
+        assert_eq!(
+            below.to_string().parse::<f32>().unwrap(),
+            minimum.to_string().parse::<f32>().unwrap()
+        );
+        assert_eq!(
+            minimum.checked_sub(&below),
+            Some(GrtAmount::from_wei_str("1").unwrap())
+        );
+        assert_eq!(below.checked_sub(&minimum), None);
+        assert_eq!(
+            [GrtAmount::from_grt(1), GrtAmount::from_grt(2)]
+                .iter()
+                .sum::<GrtAmount>(),
+            GrtAmount::from_grt(3)
+        );
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let amount = GrtAmount::from_str("1234.000000000000000001").unwrap();
+        let json = serde_json::to_string(&amount).unwrap();
+        assert_eq!(json, "\"1234.000000000000000001\"");
+        assert_eq!(serde_json::from_str::<GrtAmount>(&json).unwrap(), amount);
+    }
+}
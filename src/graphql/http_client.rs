@@ -0,0 +1,335 @@
+use std::time::{Duration, Instant};
+
+use ethers_core::rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, trace};
+
+use super::{endpoint::Endpoint, QueryError};
+use crate::metrics;
+
+/// Configuration for the HTTP client shared by subgraph and graph node queries
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryClientConfig {
+    /// Time limit for each request attempt, including reading the response
+    pub request_timeout: Duration,
+    /// Time limit for establishing a connection
+    pub connect_timeout: Duration,
+    /// Number of additional attempts made after a transport error or a 5xx response
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled for every following retry
+    pub initial_backoff: Duration,
+    /// Upper bound of the backoff between two attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for QueryClientConfig {
+    fn default() -> Self {
+        QueryClientConfig {
+            request_timeout: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(10),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// HTTP client reused across queries so connections are pooled,
+/// with per-request timeouts and jittered retries on transient failures
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "QueryClientConfig", into = "QueryClientConfig")]
+pub struct QueryClient {
+    config: QueryClientConfig,
+    client: reqwest::Client,
+}
+
+impl QueryClientConfig {
+    /// Client builder with the configured timeouts, for clients that need more settings
+    /// such as default headers
+    pub(crate) fn client_builder(&self) -> reqwest::ClientBuilder {
+        reqwest::Client::builder()
+            .user_agent(concat!("graphcast-sdk/", env!("CARGO_PKG_VERSION")))
+            .timeout(self.request_timeout)
+            .connect_timeout(self.connect_timeout)
+    }
+}
+
+impl QueryClient {
+    pub fn new(config: QueryClientConfig) -> Self {
+        let client = config.client_builder().build().unwrap_or_else(|e| {
+            debug!(
+                error = tracing::field::debug(&e),
+                "Could not build configured HTTP client, fall back to default client"
+            );
+            reqwest::Client::new()
+        });
+        QueryClient { config, client }
+    }
+
+    pub fn config(&self) -> &QueryClientConfig {
+        &self.config
+    }
+
+    /// Underlying reqwest client, for requests that do not need the retry policy
+    pub fn inner(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// Post a JSON body to the endpoint with its authentication. Transport errors and 5xx
+    /// responses are retried with exponential jittered backoff up to `max_retries` times;
+    /// any other non-success status is returned as an error right away. The latency
+    /// across attempts, including reading the response body, is recorded in
+    /// `metrics::QUERY_LATENCY` by endpoint host
+    pub async fn post_json<B: Serialize + ?Sized>(
+        &self,
+        endpoint: &Endpoint,
+        body: &B,
+    ) -> Result<reqwest::Response, QueryError> {
+        let started = Instant::now();
+        let result = match self.post_json_with_retries(endpoint, body).await {
+            Ok(response) => buffered(response)
+                .await
+                .map_err(|e| QueryError::from(redact_url(endpoint, e))),
+            Err(e) => Err(e),
+        };
+        let host = url::Url::parse(&endpoint.url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+        metrics::record_query(&host, result.is_ok(), started.elapsed());
+        result
+    }
+
+    async fn post_json_with_retries<B: Serialize + ?Sized>(
+        &self,
+        endpoint: &Endpoint,
+        body: &B,
+    ) -> Result<reqwest::Response, QueryError> {
+        let url = endpoint.url.as_str();
+        let mut attempt: u32 = 0;
+        loop {
+            let result = endpoint
+                .post(&self.client)
+                .json(body)
+                .send()
+                .await
+                .map_err(|e| redact_url(endpoint, e));
+            let retryable = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+            };
+            if !retryable || attempt >= self.config.max_retries {
+                return match result {
+                    Ok(response) => response
+                        .error_for_status()
+                        .map_err(|e| QueryError::from(redact_url(endpoint, e))),
+                    Err(e) if e.is_timeout() => Err(QueryError::Timeout(format!(
+                        "{url} did not respond within {:?}",
+                        self.config.request_timeout
+                    ))),
+                    Err(e) => Err(QueryError::from(e)),
+                };
+            }
+
+            let backoff = self.backoff(attempt);
+            debug!(
+                url,
+                attempt = attempt + 1,
+                backoff = tracing::field::debug(&backoff),
+                "Query failed, retry after backoff"
+            );
+            trace!(result = tracing::field::debug(&result), "Failed query");
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+
+    /// Exponential backoff capped at `max_backoff`, jittered within its upper half
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .config
+            .initial_backoff
+            .saturating_mul(2_u32.saturating_pow(attempt));
+        let capped = exponential.min(self.config.max_backoff);
+        capped.mul_f64(thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// Read the whole body into the response, so it is downloaded within the timed query
+async fn buffered(response: reqwest::Response) -> Result<reqwest::Response, reqwest::Error> {
+    let status = response.status();
+    let version = response.version();
+    let headers = response.headers().clone();
+    let mut buffered = http::Response::new(response.bytes().await?);
+    *buffered.status_mut() = status;
+    *buffered.version_mut() = version;
+    *buffered.headers_mut() = headers;
+    Ok(buffered.into())
+}
+
+/// Drop the requested URL from errors when it carries the endpoint's API key
+fn redact_url(endpoint: &Endpoint, error: reqwest::Error) -> reqwest::Error {
+    if endpoint.url_has_secret() {
+        error.without_url()
+    } else {
+        error
+    }
+}
+
+impl Default for QueryClient {
+    fn default() -> Self {
+        QueryClient::new(QueryClientConfig::default())
+    }
+}
+
+impl PartialEq for QueryClient {
+    fn eq(&self, other: &Self) -> bool {
+        self.config == other.config
+    }
+}
+
+impl From<QueryClientConfig> for QueryClient {
+    fn from(config: QueryClientConfig) -> Self {
+        QueryClient::new(config)
+    }
+}
+
+impl From<QueryClient> for QueryClientConfig {
+    fn from(client: QueryClient) -> Self {
+        client.config
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use axum::{extract::State, http::StatusCode, routing::post, Router};
+
+    use super::*;
+
+    /// Serve a stub on a free local port, returning its base URL
+    pub(crate) async fn spawn_stub(app: Router) -> String {
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        format!("http://{addr}")
+    }
+
+    fn test_config() -> QueryClientConfig {
+        QueryClientConfig {
+            request_timeout: Duration::from_millis(200),
+            connect_timeout: Duration::from_millis(200),
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+        }
+    }
+
+    /// Serve a stub that answers with 503 for the first `failures` requests
+    async fn flaky_server(failures: usize) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/",
+                post(move |State(hits): State<Arc<AtomicUsize>>| async move {
+                    if hits.fetch_add(1, Ordering::SeqCst) < failures {
+                        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+                    } else {
+                        (StatusCode::OK, "{\"data\":{}}")
+                    }
+                }),
+            )
+            .with_state(hits.clone());
+        let url = spawn_stub(app).await;
+        (url, hits)
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_server_error() {
+        let (url, hits) = flaky_server(2).await;
+        let client = QueryClient::new(test_config());
+        let response = client
+            .post_json(&Endpoint::new(format!("{url}/")), &serde_json::json!({}))
+            .await;
+        assert!(response.is_ok());
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retries_exhausted() {
+        let (url, hits) = flaky_server(usize::MAX).await;
+        let client = QueryClient::new(test_config());
+        let response = client
+            .post_json(&Endpoint::new(format!("{url}/")), &serde_json::json!({}))
+            .await;
+        assert!(matches!(response, Err(QueryError::Transport(_))));
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let app = Router::new().route(
+            "/",
+            post(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                "{}"
+            }),
+        );
+        let url = spawn_stub(app).await;
+
+        let client = QueryClient::new(QueryClientConfig {
+            max_retries: 0,
+            ..test_config()
+        });
+        let response = client
+            .post_json(&Endpoint::new(format!("{url}/")), &serde_json::json!({}))
+            .await;
+        assert!(matches!(response, Err(QueryError::Timeout(_))));
+    }
+
+    #[tokio::test]
+    async fn test_latency_includes_body() {
+        let app = Router::new().route(
+            "/",
+            post(|| async {
+                let (mut sender, body) = axum::body::Body::channel();
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    sender.send_data("{\"data\":{}}".into()).await.unwrap();
+                });
+                axum::response::Response::new(axum::body::boxed(body))
+            }),
+        );
+        let url = spawn_stub(app).await;
+
+        let latency = || {
+            metrics::QUERY_LATENCY
+                .with_label_values(&["127.0.0.1", "success"])
+                .get_sample_sum()
+        };
+        let before = latency();
+        let response = QueryClient::new(test_config())
+            .post_json(&Endpoint::new(format!("{url}/")), &serde_json::json!({}))
+            .await
+            .unwrap();
+        assert!(latency() - before >= 0.1);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body, serde_json::json!({"data": {}}));
+    }
+
+    #[test]
+    fn test_backoff_bounds() {
+        let client = QueryClient::new(QueryClientConfig {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            ..test_config()
+        });
+        let first = client.backoff(0);
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+        let capped = client.backoff(10);
+        assert!(capped >= Duration::from_millis(150) && capped <= Duration::from_millis(300));
+    }
+}
@@ -1,22 +1,51 @@
+pub mod client_epoch;
 pub mod client_graph_account;
 pub mod client_graph_node;
 pub mod client_network;
+pub mod client_prometheus;
 pub mod client_registry;
+pub mod client_registry_contract;
+pub mod client_rpc;
+pub mod deployment_status;
+pub mod endpoint;
+pub mod endpoint_health;
+pub mod grt;
+pub mod http_client;
+pub mod pagination;
+
+pub use grt::GrtAmount;
 
 #[derive(Debug, thiserror::Error)]
 pub enum QueryError {
     #[error(transparent)]
     Transport(#[from] reqwest::Error),
+    #[error("Query timed out: {0}")]
+    Timeout(String),
     #[error("The subgraph is in a failed state")]
     IndexingError,
     #[error("Query response is unexpected: {0}")]
     ParseResponseError(String),
     #[error("Query response is empty: {0}")]
     PrometheusError(#[from] prometheus_http_query::Error),
+    #[error("JSON-RPC provider error: {0}")]
+    Rpc(String),
+    #[error("Graph node and JSON-RPC provider disagree on {network} block {block_number}: {graph_node} != {rpc}")]
+    BlockHashMismatch {
+        network: String,
+        block_number: u64,
+        graph_node: String,
+        rpc: String,
+    },
     #[error("Unknown error: {0}")]
     Other(anyhow::Error),
 }
 
+/// Parse a GRT amount in wei as returned by the subgraphs
+pub fn grt_wei_string_to_amount(input: &str) -> Result<GrtAmount, QueryError> {
+    GrtAmount::from_wei_str(input).map_err(|e| QueryError::ParseResponseError(e.to_string()))
+}
+
+#[deprecated(note = "loses precision for real stake sizes, use `grt_wei_string_to_amount`")]
 pub fn grt_gwei_string_to_f32(input: &str) -> Result<f32, QueryError> {
     add_decimal(input)
         .parse::<f32>()
@@ -50,6 +79,16 @@ mod tests {
     }
 
     #[test]
+    fn test_grt_wei_string_to_amount() {
+        assert_eq!(
+            grt_wei_string_to_amount("100000000000000000000000").unwrap(),
+            GrtAmount::from_grt(100_000),
+        );
+        assert!(grt_wei_string_to_amount("abc").is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)]
     fn test_grt_gwei_string_to_f32() {
         // valid inputs
         assert!(grt_gwei_string_to_f32("100000000000000000000000").is_ok());
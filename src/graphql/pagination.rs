@@ -0,0 +1,84 @@
+//! Cursor-based pagination over subgraph entities.
+//!
+//! Subgraph list fields return a default page of 100 entities when no `first` is
+//! given, and `skip` gets slow for large offsets. Queries page through entities
+//! ordered by id instead, asking for the entities with `id_gt` the last id seen.
+
+/// Number of entities requested per page unless configured otherwise
+pub const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// Tracks the `id_gt` cursor across the pages of a query
+///
+/// ```ignore
+/// let mut cursor = PageCursor::new(DEFAULT_PAGE_SIZE);
+/// while let Some(last_id) = cursor.next_page() {
+///     let page = query_page(cursor.first(), last_id).await?;
+///     cursor.advance(&page, |entity| &entity.id);
+///     entities.extend(page);
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageCursor {
+    page_size: usize,
+    last_id: String,
+    exhausted: bool,
+}
+
+impl PageCursor {
+    pub fn new(page_size: usize) -> Self {
+        PageCursor {
+            page_size: page_size.max(1),
+            last_id: String::new(),
+            exhausted: false,
+        }
+    }
+
+    /// Value for the `first` argument of each page
+    pub fn first(&self) -> i64 {
+        self.page_size as i64
+    }
+
+    /// Value for the `id_gt` filter of the next page, `None` once the last page was read
+    pub fn next_page(&self) -> Option<String> {
+        (!self.exhausted).then(|| self.last_id.clone())
+    }
+
+    /// Move past a page of entities; a page shorter than the page size is the last one
+    pub fn advance<T>(&mut self, page: &[T], id: impl Fn(&T) -> &str) {
+        if let Some(last) = page.last() {
+            self.last_id = id(last).to_string();
+        }
+        self.exhausted = page.len() < self.page_size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_pages() {
+        let ids: Vec<String> = (0..5).map(|i| format!("0x{i}")).collect();
+        let mut cursor = PageCursor::new(2);
+        let mut pages = vec![];
+        while let Some(last_id) = cursor.next_page() {
+            let page: Vec<String> = ids
+                .iter()
+                .filter(|id| id.as_str() > last_id.as_str())
+                .take(cursor.first() as usize)
+                .cloned()
+                .collect();
+            cursor.advance(&page, |id| id);
+            pages.push(page);
+        }
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages.concat(), ids);
+
+        // A full last page takes one more, empty, page to detect the end
+        let mut cursor = PageCursor::new(5);
+        cursor.advance(&ids, |id| id);
+        assert_eq!(cursor.next_page(), Some("0x4".to_string()));
+        cursor.advance::<String>(&[], |id| id);
+        assert_eq!(cursor.next_page(), None);
+    }
+}
@@ -22,11 +22,10 @@ use ethers::signers::{
 use ethers_core::k256::ecdsa::SigningKey;
 use graphcast_agent::message_typing::{IdentityValidation, MessageError};
 use graphql::{
-    client_graph_account::{query_graph_account, subgraph_hash_by_id},
-    client_network::query_network_subgraph,
+    client_network::{AccountResolution, StakeResolution},
     QueryError,
 };
-use networks::{NetworkName, NETWORKS};
+use networks::{NetworkName, NetworkRegistry, NETWORKS};
 
 use once_cell::sync::OnceCell;
 
@@ -39,20 +38,25 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use tracing::warn;
 use tracing::{debug, subscriber::SetGlobalDefaultError};
+use tracing::{trace, warn};
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::FmtSubscriber;
 use url::{Host, Url};
 pub use waku::{waku_set_event_callback, WakuMessage, WakuPeerData, WakuPubSubTopic};
 
-use crate::{graphcast_agent::ConfigError, graphql::client_registry::query_registry};
+use crate::{callbook::CallBook, graphcast_agent::ConfigError};
 
+pub mod block_scheduler;
+pub mod block_validation;
 pub mod bots;
 pub mod callbook;
+pub mod data_source;
 pub mod graphcast_agent;
 pub mod graphql;
+pub mod metrics;
 pub mod networks;
+pub mod registration;
 
 type NoncesMap = HashMap<String, HashMap<String, u64>>;
 
@@ -128,12 +132,26 @@ pub fn init_tracing(format: String) -> Result<(), SetGlobalDefaultError> {
 pub fn determine_message_block(
     network_chainhead_blocks: &HashMap<NetworkName, BlockPointer>,
     network_name: NetworkName,
+) -> Result<u64, NetworkBlockError> {
+    determine_message_block_in(
+        &NETWORKS.read().unwrap(),
+        network_chainhead_blocks,
+        network_name,
+    )
+}
+
+/// Same as `determine_message_block`, with the examination frequencies of the given
+/// network registry instead of the process-wide one
+pub fn determine_message_block_in(
+    network_registry: &NetworkRegistry,
+    network_chainhead_blocks: &HashMap<NetworkName, BlockPointer>,
+    network_name: NetworkName,
 ) -> Result<u64, NetworkBlockError> {
     // Get the pre-configured examination frequency of the network
-    let examination_frequency = match NETWORKS.iter().find(|n| n.name == network_name) {
+    let examination_frequency = match network_registry.get(&network_name) {
         Some(n) => n.interval,
         None => {
-            let err_msg = format!("Subgraph is indexing an unsupported network {network_name}, please report an issue on https://github.com/graphops/graphcast-sdk");
+            let err_msg = format!("Subgraph is indexing an unsupported network {network_name}, add it to the networks file or report an issue on https://github.com/graphops/graphcast-sdk");
             warn!(err_msg);
             return Err(NetworkBlockError::UnsupportedNetwork(err_msg));
         }
@@ -158,11 +176,57 @@ pub struct NetworkPointer {
     pub block: BlockPointer,
 }
 
+/// Position of a network's chainhead relative to its examination intervals
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct BlockClock {
+    /// Latest chainhead block number observed
     pub current_block: u64,
+    /// Message block of the last interval crossed, 0 before any
     pub compare_block: u64,
 }
 
+/// Outcome of moving a `BlockClock` to a new chainhead
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockTick {
+    /// Still within the last crossed interval
+    Unchanged,
+    /// Crossed into the interval starting at `message_block`. `missed` counts the
+    /// intervals skipped since the previous crossing, which are not fired
+    Crossed { message_block: u64, missed: u64 },
+    /// The chainhead moved back from `previous`. Intervals after the new chainhead's
+    /// message block fire again once the chain crosses them
+    Regressed { previous: u64, message_block: u64 },
+}
+
+impl BlockClock {
+    /// Move the clock to `chainhead` for a network examined every `interval` blocks
+    pub fn tick(&mut self, chainhead: u64, interval: u64) -> ClockTick {
+        let message_block = chainhead - chainhead % interval.max(1);
+        if chainhead < self.current_block {
+            let previous = self.current_block;
+            self.current_block = chainhead;
+            self.compare_block = self.compare_block.min(message_block);
+            return ClockTick::Regressed {
+                previous,
+                message_block,
+            };
+        }
+        self.current_block = chainhead;
+        if message_block <= self.compare_block {
+            return ClockTick::Unchanged;
+        }
+        let missed = match self.compare_block {
+            0 => 0,
+            compare_block => ((message_block - compare_block) / interval.max(1)).saturating_sub(1),
+        };
+        self.compare_block = message_block;
+        ClockTick::Crossed {
+            message_block,
+            missed,
+        }
+    }
+}
+
 /// Struct for a block pointer
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct BlockPointer {
@@ -176,6 +240,22 @@ impl BlockPointer {
     }
 }
 
+/// Protocol epoch and the block it starts at on a network
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct EpochPointer {
+    pub number: u64,
+    pub start_block: u64,
+}
+
+impl EpochPointer {
+    pub fn new(number: u64, start_block: u64) -> Self {
+        EpochPointer {
+            number,
+            start_block,
+        }
+    }
+}
+
 /// Account information to keep graphcast agent signer address,
 /// and its correseponding Graph Account. `agent` address can be validated as either
 /// a graphcast_id, an indexer operator, or an indexer. `account` address takes the field `graph_account` from a generic `GraphcastMessage` and gets verified through Graphcast registry and/or Graph network subgraph, through a locally configured `IdentityValidation` mechanism.
@@ -206,11 +286,8 @@ impl Account {
     }
 
     /// Check for sender's registration at Graphcast (registered at graphcast Registry)
-    pub async fn account_from_registry(
-        &self,
-        registry_subgraph: &str,
-    ) -> Result<Account, QueryError> {
-        let registered_address = query_registry(registry_subgraph, self.agent_address()).await?;
+    pub async fn account_from_registry(&self, callbook: &CallBook) -> Result<Account, QueryError> {
+        let registered_address = callbook.registered_indexer(self.agent_address()).await?;
 
         Ok(Account::new(
             self.agent_address().to_string(),
@@ -218,31 +295,51 @@ impl Account {
         ))
     }
 
-    /// Check for sender's registration at Graph Network
+    /// Check for sender's registration at Graph Network, on the network subgraphs
+    /// following the callbook's `NetworkResolution`, with the networks it matched on
     pub async fn account_from_network(
         &self,
-        network_subgraph: &str,
-    ) -> Result<Account, QueryError> {
-        let matched_account =
-            query_graph_account(network_subgraph, self.agent_address(), self.account()).await?;
-        Ok(matched_account)
+        callbook: &CallBook,
+    ) -> Result<AccountResolution, QueryError> {
+        let resolution = callbook
+            .resolve_graph_account(self.agent_address(), self.account())
+            .await?;
+        debug!(
+            account = tracing::field::debug(&resolution.account),
+            networks = tracing::field::debug(&resolution.networks),
+            "Graph account matched on network subgraphs"
+        );
+        Ok(resolution)
     }
 
-    pub async fn valid_indexer(&self, network_subgraph: &str) -> Result<bool, MessageError> {
-        Ok(query_network_subgraph(network_subgraph, self.account())
+    /// Indexer stake of the account across the network subgraphs, with the networks
+    /// it was taken from
+    pub async fn indexer_stake(
+        &self,
+        callbook: &CallBook,
+    ) -> Result<StakeResolution, MessageError> {
+        callbook
+            .resolve_indexer_stake(self.account())
             .await
-            .map_err(MessageError::FieldDerivations)?
-            .stake_satisfy_requirement())
+            .map_err(MessageError::FieldDerivations)
+    }
+
+    pub async fn valid_indexer(&self, callbook: &CallBook) -> Result<bool, MessageError> {
+        callbook
+            .stake_satisfy_requirement(self.account())
+            .await
+            .map_err(MessageError::FieldDerivations)
     }
 
     /// Check if the account owns a subgraph id
     pub async fn valid_owner(
         &self,
-        network_subgraph: &str,
+        callbook: &CallBook,
         subgraph_hash: &str,
         subgraph_id: &str,
     ) -> Result<bool, MessageError> {
-        let subgraph_hashes = subgraph_hash_by_id(network_subgraph, self.account(), subgraph_id)
+        let subgraph_hashes = callbook
+            .subgraph_hash_by_id(self.account(), subgraph_id)
             .await
             .map_err(MessageError::FieldDerivations)?;
         Ok(!subgraph_hashes.contains(&subgraph_hash.to_string()))
@@ -250,34 +347,55 @@ impl Account {
 
     /// Based on id_validation mechanism, perform the corresponding check between the
     /// message sender versus the representing graph_account.
+    /// Results are served from and recorded to the callbook's identity cache.
     ///
     /// Currently do not verify subgraph ownership here, which should be used `IdentityValidation::SubgraphStaker`
     pub async fn verify(
         &self,
-        network_subgraph: &str,
-        registry_subgraph: &str,
+        callbook: &CallBook,
+        id_validation: &IdentityValidation,
+    ) -> Result<Account, MessageError> {
+        if let Some(cached) = callbook.identity_cache().get(self, id_validation) {
+            trace!(
+                account = tracing::field::debug(&self),
+                "Identity verification served from cache"
+            );
+            return cached;
+        }
+        let verified = self.verify_uncached(callbook, id_validation).await;
+        callbook
+            .identity_cache()
+            .insert(self, id_validation, &verified);
+        verified
+    }
+
+    /// Perform the identity check against the data sources, skipping the cache
+    pub async fn verify_uncached(
+        &self,
+        callbook: &CallBook,
         id_validation: &IdentityValidation,
     ) -> Result<Account, MessageError> {
         let verified_account: Account = match id_validation {
             IdentityValidation::NoCheck | IdentityValidation::ValidAddress => self.clone(),
             IdentityValidation::GraphcastRegistered => {
                 // Simply check if the message signer is registered at Graphcast Registry, make no validation on Graph Account field
-                self.account_from_registry(registry_subgraph)
+                self.account_from_registry(callbook)
                     .await
                     .map_err(MessageError::FieldDerivations)?
             }
             IdentityValidation::GraphNetworkAccount => {
                 // allow any Graph account matched with message signer and the self-claimed graph account
-                self.account_from_network(network_subgraph)
+                self.account_from_network(callbook)
                     .await
                     .map_err(MessageError::FieldDerivations)?
+                    .account
             }
             IdentityValidation::RegisteredIndexer => self
-                .account_from_registry(registry_subgraph)
+                .account_from_registry(callbook)
                 .await
                 .map_err(MessageError::FieldDerivations)?,
             IdentityValidation::Indexer | IdentityValidation::SubgraphStaker => {
-                match self.account_from_registry(registry_subgraph).await {
+                match self.account_from_registry(callbook).await {
                     Ok(a) => a,
                     Err(e) => {
                         debug!(
@@ -285,9 +403,10 @@ impl Account {
                             account = tracing::field::debug(&self),
                             "Signer is not registered at Graphcast Registry. Check Graph Network"
                         );
-                        self.account_from_network(network_subgraph)
+                        self.account_from_network(callbook)
                             .await
                             .map_err(MessageError::FieldDerivations)?
+                            .account
                     }
                 }
             }
@@ -302,7 +421,7 @@ impl Account {
         // Indexer check for indexer validating mechanisms
         if ((id_validation == &IdentityValidation::RegisteredIndexer)
             | (id_validation == &IdentityValidation::Indexer))
-            && !(verified_account.valid_indexer(network_subgraph).await?)
+            && !(verified_account.valid_indexer(callbook).await?)
         {
             return Err(MessageError::InvalidFields(anyhow::anyhow!(format!(
                 "Verified account failed indexer requirement. Verified account: {:#?}",
@@ -395,6 +514,52 @@ pub enum NetworkBlockError {
 mod tests {
     use crate::graphcast_agent::waku_handling::build_content_topics;
 
+    #[test]
+    fn test_determine_message_block() {
+        use crate::networks::{Network, NetworkName, NetworkRegistry};
+        use crate::{determine_message_block_in, BlockPointer, NetworkBlockError};
+        use std::collections::HashMap;
+
+        let network = NetworkName::from_string("zora");
+        let chainheads = HashMap::from([(
+            network.clone(),
+            BlockPointer::new(1_234, "0xhead".to_string()),
+        )]);
+        assert!(matches!(
+            determine_message_block_in(&NetworkRegistry::default(), &chainheads, network.clone()),
+            Err(NetworkBlockError::UnsupportedNetwork(_))
+        ));
+
+        let registry = NetworkRegistry::default()
+            .with_toml(
+                r#"
+                [[networks]]
+                name = "zora"
+                caip2 = "eip155:7777777"
+                block_time_ms = 2000
+                interval = 100
+                "#,
+            )
+            .unwrap();
+        assert_eq!(
+            determine_message_block_in(&registry, &chainheads, network.clone()).unwrap(),
+            1_200
+        );
+
+        // Networks added in code are validated, an interval of 0 is derived
+        let registry = NetworkRegistry::empty()
+            .with_networks([Network::new("zora", "eip155:7777777", 2_000).with_interval(0)])
+            .unwrap();
+        assert_eq!(registry.get(&network).unwrap().interval, 150);
+        assert_eq!(
+            determine_message_block_in(&registry, &chainheads, network).unwrap(),
+            1_200
+        );
+        assert!(NetworkRegistry::empty()
+            .with_networks([Network::new("zora", "eip155:7777777", 0)])
+            .is_err());
+    }
+
     #[test]
     fn test_build_content_topics() {
         let basics = ["Qmyumyum".to_string(), "Ymqumqum".to_string()].to_vec();
@@ -0,0 +1,212 @@
+//! Prometheus metrics of the Graphcast agent: messages sent, received and validated,
+//! rejections by error and validation stage, subgraph query latency and connected peers.
+//! Radios register their own metrics in the same `REGISTRY` with `register`, and
+//! `serve_metrics` exports all of them in the text format at `/metrics`.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::{http::header::CONTENT_TYPE, routing::get, Router};
+use once_cell::sync::Lazy;
+use prometheus::{
+    core::Collector, Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder,
+};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::graphcast_agent::message_typing::MessageError;
+
+/// Registry of the agent's metrics, shared with the metrics radios register
+pub static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+pub static MESSAGES_SENT: Lazy<IntCounter> = Lazy::new(|| {
+    register_default(
+        IntCounter::new(
+            "graphcast_messages_sent_total",
+            "Messages sent to the Graphcast network",
+        )
+        .unwrap(),
+    )
+});
+
+pub static MESSAGES_RECEIVED: Lazy<IntCounter> = Lazy::new(|| {
+    register_default(
+        IntCounter::new(
+            "graphcast_messages_received_total",
+            "Messages received from the Graphcast network, before deduplication",
+        )
+        .unwrap(),
+    )
+});
+
+pub static MESSAGES_DUPLICATE: Lazy<IntCounter> = Lazy::new(|| {
+    register_default(
+        IntCounter::new(
+            "graphcast_messages_duplicate_total",
+            "Received messages skipped as already received or sent by the agent",
+        )
+        .unwrap(),
+    )
+});
+
+pub static MESSAGES_IRRELEVANT_TOPIC: Lazy<IntCounter> = Lazy::new(|| {
+    register_default(
+        IntCounter::new(
+            "graphcast_messages_irrelevant_topic_total",
+            "Received messages skipped for a content topic the radio is not subscribed to",
+        )
+        .unwrap(),
+    )
+});
+
+pub static MESSAGES_VALIDATED: Lazy<IntCounter> = Lazy::new(|| {
+    register_default(
+        IntCounter::new(
+            "graphcast_messages_validated_total",
+            "Received messages passing every validation stage",
+        )
+        .unwrap(),
+    )
+});
+
+pub static MESSAGES_REJECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_default(
+        IntCounterVec::new(
+            Opts::new(
+                "graphcast_messages_rejected_total",
+                "Received messages failing validation, by error type and validation stage",
+            ),
+            &["error", "stage"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static QUERY_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_default(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "graphcast_query_duration_seconds",
+                "Latency of subgraph and graph node queries including retries, by host",
+            )
+            .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]),
+            &["host", "outcome"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static CONNECTED_PEERS: Lazy<IntGauge> = Lazy::new(|| {
+    register_default(
+        IntGauge::new(
+            "graphcast_connected_peers",
+            "Waku peers connected to the agent",
+        )
+        .unwrap(),
+    )
+});
+
+/// Register a radio's metric in the agent's registry
+pub fn register<C: Collector + Clone + 'static>(collector: &C) -> Result<(), prometheus::Error> {
+    REGISTRY.register(Box::new(collector.clone()))
+}
+
+fn register_default<C: Collector + Clone + 'static>(collector: C) -> C {
+    if let Err(e) = register(&collector) {
+        warn!(err = e.to_string(), "Could not register metric");
+    }
+    collector
+}
+
+/// Count a received message rejected at a validation stage, such as `sender`, `time`
+/// or `nonce`
+pub fn record_rejection(error: &MessageError, stage: &str) {
+    MESSAGES_REJECTED
+        .with_label_values(&[error.type_string(), stage])
+        .inc();
+}
+
+pub fn record_query(host: &str, success: bool, duration: Duration) {
+    QUERY_LATENCY
+        .with_label_values(&[host, if success { "success" } else { "failure" }])
+        .observe(duration.as_secs_f64());
+}
+
+/// Registered metrics in the Prometheus text format
+pub fn gather_text() -> String {
+    // Make sure the agent's metrics are exported before their first use
+    Lazy::force(&MESSAGES_SENT);
+    Lazy::force(&MESSAGES_RECEIVED);
+    Lazy::force(&MESSAGES_DUPLICATE);
+    Lazy::force(&MESSAGES_IRRELEVANT_TOPIC);
+    Lazy::force(&MESSAGES_VALIDATED);
+    Lazy::force(&MESSAGES_REJECTED);
+    Lazy::force(&QUERY_LATENCY);
+    Lazy::force(&CONNECTED_PEERS);
+
+    let mut buffer = vec![];
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        warn!(err = e.to_string(), "Could not encode metrics");
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+/// Router serving the metrics at `/metrics`, to be merged into a radio's HTTP server
+pub fn metrics_router() -> Router {
+    Router::new().route(
+        "/metrics",
+        get(|| async {
+            (
+                [(CONTENT_TYPE, TextEncoder::new().format_type().to_string())],
+                gather_text(),
+            )
+        }),
+    )
+}
+
+/// Serve the metrics at `/metrics` on the address in a background task
+pub fn serve_metrics(address: SocketAddr) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        info!(address = %address, "Serve metrics at /metrics");
+        if let Err(e) = axum::Server::bind(&address)
+            .serve(metrics_router().into_make_service())
+            .await
+        {
+            warn!(err = e.to_string(), "Metrics server stopped");
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphql::http_client::tests::spawn_stub;
+
+    #[tokio::test]
+    async fn test_metrics_exporter() {
+        let radio_metric =
+            IntCounter::new("test_radio_comparisons_total", "Comparisons made").unwrap();
+        register(&radio_metric).unwrap();
+        assert!(register(&radio_metric).is_err());
+        radio_metric.inc_by(3);
+        record_rejection(&MessageError::Signing, "time");
+        record_query("api.thegraph.com", true, Duration::from_millis(120));
+
+        let url = spawn_stub(metrics_router()).await;
+
+        let response = reqwest::get(format!("{url}/metrics")).await.unwrap();
+        assert!(response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let text = response.text().await.unwrap();
+        assert!(text.contains("test_radio_comparisons_total 3"));
+        assert!(text.contains(r#"graphcast_messages_rejected_total{error="Signing",stage="time"}"#));
+        assert!(text.contains(
+            r#"graphcast_query_duration_seconds_count{host="api.thegraph.com",outcome="success"}"#
+        ));
+        assert!(text.contains("graphcast_messages_sent_total"));
+        assert!(text.contains("graphcast_connected_peers"));
+    }
+}
@@ -1,128 +1,303 @@
+//! Networks radios can send messages about, keyed by the network name graph node reports
+//! in indexing statuses. The registry starts from built-in defaults and can be extended
+//! or overridden at runtime, such as from a TOML file, to support new chains without a
+//! new release.
+
 use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
+use std::path::Path;
+use std::sync::RwLock;
 
-/// Struct for Network and block interval for updates
-#[derive(Debug, Clone)]
-pub struct Network {
-    pub name: NetworkName,
-    pub interval: u64,
-}
+/// Examination window that built-in intervals and intervals derived from the average
+/// block time target
+pub const TARGET_INTERVAL_MS: u64 = 300_000;
 
-/// List of supported networks
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-pub enum NetworkName {
-    Goerli,
-    Mainnet,
-    Gnosis,
-    Hardhat,
-    ArbitrumOne,
-    ArbitrumGoerli,
-    Avalanche,
-    Matic, // Previously Polygon
-    Celo,
-    Optimism,
-    Fantom,
-    Unknown,
+#[derive(Debug, thiserror::Error)]
+pub enum NetworkRegistryError {
+    #[error("Could not read networks file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse networks file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Invalid network {name}: {reason}")]
+    InvalidNetwork { name: String, reason: String },
 }
 
+/// Network name as reported by graph node, such as `mainnet` or `arbitrum-one`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NetworkName(String);
+
 impl NetworkName {
     pub fn from_string(name: &str) -> Self {
-        match name {
-            "goerli" => NetworkName::Goerli,
-            "mainnet" => NetworkName::Mainnet,
-            "gnosis" => NetworkName::Gnosis,
-            "hardhat" => NetworkName::Hardhat,
-            "arbitrum-one" => NetworkName::ArbitrumOne,
-            "arbitrum-goerli" => NetworkName::ArbitrumGoerli,
-            "avalanche" => NetworkName::Avalanche,
-            "matic" => NetworkName::Matic,
-            "celo" => NetworkName::Celo,
-            "optimism" => NetworkName::Optimism,
-            "fantom" => NetworkName::Fantom,
-            _ => NetworkName::Unknown,
-        }
+        NetworkName(name.to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for NetworkName {
+    fn from(name: &str) -> Self {
+        NetworkName::from_string(name)
+    }
+}
+
+impl From<String> for NetworkName {
+    fn from(name: String) -> Self {
+        NetworkName(name)
     }
 }
 
 impl fmt::Display for NetworkName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let name = match self {
-            NetworkName::Goerli => "goerli",
-            NetworkName::Mainnet => "mainnet",
-            NetworkName::Gnosis => "gnosis",
-            NetworkName::Hardhat => "hardhat",
-            NetworkName::ArbitrumOne => "arbitrum-one",
-            NetworkName::ArbitrumGoerli => "arbitrum-goerli",
-            NetworkName::Avalanche => "avalanche",
-            NetworkName::Matic => "matic",
-            NetworkName::Celo => "celo",
-            NetworkName::Optimism => "optimism",
-            NetworkName::Fantom => "fantom",
-            NetworkName::Unknown => "unknown",
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Network and the block interval for updates
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Network {
+    pub name: NetworkName,
+    /// CAIP-2 chain id, such as `eip155:1`
+    pub caip2: String,
+    /// Average block time in milliseconds
+    pub block_time_ms: u64,
+    /// Number of blocks between message blocks, derived from the block time to target
+    /// ~5 minutes when not set
+    #[serde(default)]
+    pub interval: u64,
+}
+
+impl Network {
+    pub fn new(name: &str, caip2: &str, block_time_ms: u64) -> Self {
+        Network {
+            name: NetworkName::from_string(name),
+            caip2: caip2.to_string(),
+            block_time_ms,
+            interval: (TARGET_INTERVAL_MS / block_time_ms.max(1)).max(1),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: u64) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    fn validate(mut self) -> Result<Self, NetworkRegistryError> {
+        let invalid = |reason: &str| NetworkRegistryError::InvalidNetwork {
+            name: self.name.to_string(),
+            reason: reason.to_string(),
         };
+        if !valid_caip2(&self.caip2) {
+            return Err(invalid("CAIP-2 id must look like `eip155:1`"));
+        }
+        if self.block_time_ms == 0 {
+            return Err(invalid("block_time_ms must be positive"));
+        }
+        if self.interval == 0 {
+            self.interval = (TARGET_INTERVAL_MS / self.block_time_ms).max(1);
+        }
+        Ok(self)
+    }
+}
 
-        write!(f, "{name}")
+/// `namespace:reference` with the character sets and lengths of the CAIP-2 spec
+fn valid_caip2(id: &str) -> bool {
+    match id.split_once(':') {
+        Some((namespace, reference)) => {
+            (3..=8).contains(&namespace.len())
+                && namespace
+                    .chars()
+                    .all(|c| c == '-' || c.is_ascii_lowercase() || c.is_ascii_digit())
+                && (1..=32).contains(&reference.len())
+                && reference
+                    .chars()
+                    .all(|c| c == '-' || c == '_' || c.is_ascii_alphanumeric())
+        }
+        None => false,
     }
 }
 
-/// Maintained static list of supported Networks, the intervals target ~5minutes
-/// depending on the blockchain average block processing time
-pub static NETWORKS: Lazy<Vec<Network>> = Lazy::new(|| {
+/// Built-in networks. The intervals target ~5 minutes depending on the blockchain
+/// average block processing time
+fn builtin_networks() -> Vec<Network> {
     vec![
         // Goerli (Ethereum Testnet): ~15 seconds
-        Network {
-            name: NetworkName::from_string("goerli"),
-            interval: 20,
-        },
+        Network::new("goerli", "eip155:5", 15_000).with_interval(20),
         // Mainnet (Ethereum): ~10-12 seconds
-        Network {
-            name: NetworkName::from_string("mainnet"),
-            interval: 30,
-        },
+        Network::new("mainnet", "eip155:1", 12_000).with_interval(30),
+        // Sepolia (Ethereum Testnet): ~12 seconds
+        Network::new("sepolia", "eip155:11155111", 12_000),
         // Gnosis: ~5 seconds
-        Network {
-            name: NetworkName::from_string("gnosis"),
-            interval: 60,
-        },
+        Network::new("gnosis", "eip155:100", 5_000).with_interval(60),
         // Local test network
-        Network {
-            name: NetworkName::from_string("hardhat"),
-            interval: 10,
-        },
+        Network::new("hardhat", "eip155:1337", 1_000).with_interval(10),
         // ArbitrumOne: ~0.25-1 second
-        Network {
-            name: NetworkName::from_string("arbitrum-one"),
-            interval: 600,
-        },
+        Network::new("arbitrum-one", "eip155:42161", 250).with_interval(600),
         // ArbitrumGoerli (Arbitrum Testnet): ~.6 seconds
-        Network {
-            name: NetworkName::from_string("arbitrum-goerli"),
-            interval: 500,
-        },
+        Network::new("arbitrum-goerli", "eip155:421613", 600).with_interval(500),
+        // ArbitrumSepolia (Arbitrum Testnet): ~0.25 seconds
+        Network::new("arbitrum-sepolia", "eip155:421614", 250).with_interval(600),
         // Avalanche: ~3-5 seconds
-        Network {
-            name: NetworkName::from_string("avalanche"),
-            interval: 60,
-        },
-        // Matic: ~2 seconds
-        Network {
-            name: NetworkName::from_string("matic"),
-            interval: 150,
-        },
+        Network::new("avalanche", "eip155:43114", 3_000).with_interval(60),
+        // Matic (previously Polygon): ~2 seconds
+        Network::new("matic", "eip155:137", 2_000).with_interval(150),
         // Celo: ~5-10 seconds
-        Network {
-            name: NetworkName::from_string("celo"),
-            interval: 30,
-        },
+        Network::new("celo", "eip155:42220", 5_000).with_interval(30),
         // Optimism: ~10-15 seconds
-        Network {
-            name: NetworkName::from_string("optimism"),
-            interval: 20,
-        },
+        Network::new("optimism", "eip155:10", 12_000).with_interval(20),
+        // Base: ~2 seconds
+        Network::new("base", "eip155:8453", 2_000),
         // Fantom: ~2-3 seconds
-        Network {
-            name: NetworkName::from_string("fantom"),
-            interval: 100,
-        },
+        Network::new("fantom", "eip155:250", 2_000).with_interval(100),
     ]
-});
+}
+
+/// Supported networks by graph node network name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkRegistry {
+    networks: BTreeMap<NetworkName, Network>,
+}
+
+/// Layout of a networks file, e.g.
+///
+/// ```toml
+/// [[networks]]
+/// name = "base"
+/// caip2 = "eip155:8453"
+/// block_time_ms = 2000
+/// interval = 150
+/// ```
+#[derive(Debug, Deserialize)]
+struct NetworksFile {
+    #[serde(default)]
+    networks: Vec<Network>,
+}
+
+impl Default for NetworkRegistry {
+    fn default() -> Self {
+        NetworkRegistry::empty()
+            .with_networks(builtin_networks())
+            .expect("Built-in networks are valid")
+    }
+}
+
+impl NetworkRegistry {
+    /// Registry without the built-in networks
+    pub fn empty() -> Self {
+        NetworkRegistry {
+            networks: BTreeMap::new(),
+        }
+    }
+
+    /// Add networks, replacing registered networks of the same name. Networks are
+    /// validated like those listed in TOML, an interval of 0 is derived from the block time
+    pub fn with_networks(
+        mut self,
+        networks: impl IntoIterator<Item = Network>,
+    ) -> Result<Self, NetworkRegistryError> {
+        for network in networks {
+            let network = network.validate()?;
+            self.networks.insert(network.name.clone(), network);
+        }
+        Ok(self)
+    }
+
+    /// Add or override the networks listed in TOML
+    pub fn with_toml(self, toml: &str) -> Result<Self, NetworkRegistryError> {
+        let file: NetworksFile = toml::from_str(toml)?;
+        self.with_networks(file.networks)
+    }
+
+    /// Add or override the networks listed in a TOML file
+    pub fn with_file(self, path: impl AsRef<Path>) -> Result<Self, NetworkRegistryError> {
+        let toml = std::fs::read_to_string(path)?;
+        self.with_toml(&toml)
+    }
+
+    pub fn get(&self, name: &NetworkName) -> Option<&Network> {
+        self.networks.get(name)
+    }
+
+    pub fn by_caip2(&self, caip2: &str) -> Option<&Network> {
+        self.networks.values().find(|n| n.caip2 == caip2)
+    }
+
+    pub fn networks(&self) -> impl Iterator<Item = &Network> {
+        self.networks.values()
+    }
+}
+
+/// Process-wide registry used by `determine_message_block`, the built-in networks until
+/// replaced with `set_network_registry`
+pub static NETWORKS: Lazy<RwLock<NetworkRegistry>> =
+    Lazy::new(|| RwLock::new(NetworkRegistry::default()));
+
+/// Snapshot of the process-wide network registry
+pub fn network_registry() -> NetworkRegistry {
+    NETWORKS.read().unwrap().clone()
+}
+
+/// Replace the process-wide network registry
+pub fn set_network_registry(registry: NetworkRegistry) {
+    *NETWORKS.write().unwrap() = registry;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_from_toml() {
+        let registry = NetworkRegistry::default()
+            .with_toml(
+                r#"
+                [[networks]]
+                name = "mainnet"
+                caip2 = "eip155:1"
+                block_time_ms = 12000
+                interval = 25
+
+                [[networks]]
+                name = "zksync-era"
+                caip2 = "eip155:324"
+                block_time_ms = 1000
+                "#,
+            )
+            .unwrap();
+
+        let mainnet = registry.get(&NetworkName::from("mainnet")).unwrap();
+        assert_eq!(mainnet.interval, 25);
+        let zksync = registry.by_caip2("eip155:324").unwrap();
+        assert_eq!(zksync.name.as_str(), "zksync-era");
+        assert_eq!(zksync.interval, 300);
+        // Built-in networks remain
+        assert_eq!(
+            registry.get(&NetworkName::from("base")).unwrap().caip2,
+            "eip155:8453"
+        );
+
+        assert!(matches!(
+            NetworkRegistry::empty().with_toml(
+                r#"
+                [[networks]]
+                name = "broken"
+                caip2 = "1"
+                block_time_ms = 1000
+                "#
+            ),
+            Err(NetworkRegistryError::InvalidNetwork { .. })
+        ));
+    }
+
+    #[test]
+    fn test_network_name_serde() {
+        let name: NetworkName = serde_json::from_str("\"base-sepolia\"").unwrap();
+        assert_eq!(name, NetworkName::from_string("base-sepolia"));
+        assert_eq!(serde_json::to_string(&name).unwrap(), "\"base-sepolia\"");
+        assert_eq!(name.to_string(), "base-sepolia");
+    }
+}
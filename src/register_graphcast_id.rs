@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use clap::Parser;
+use graphcast_sdk::callbook::CallBook;
+use graphcast_sdk::graphql::client_registry_contract::RegistryContractConfig;
+use graphcast_sdk::graphql::endpoint::Endpoint;
+use graphcast_sdk::graphql::http_client::QueryClient;
+use graphcast_sdk::registration::{
+    confirm_registration, register_graphcast_id, registration_calldata,
+};
+use graphcast_sdk::{build_wallet, init_tracing, wallet_address, LogFormat};
+
+/// Register a Graphcast ID for an indexer at the Graphcast registry contract
+#[derive(Parser, Debug)]
+#[command(name = "register-graphcast-id")]
+struct Cli {
+    /// Address of the Graphcast registry contract
+    #[arg(long, env = "REGISTRY_ADDRESS")]
+    registry_address: String,
+    /// Graphcast ID to register, the address radios sign messages with
+    #[arg(long, env = "GRAPHCAST_ID")]
+    graphcast_id: String,
+    /// JSON-RPC provider of the chain the registry is deployed on
+    #[arg(long, env = "RPC_URL", required_unless_present = "dry_run")]
+    rpc_url: Option<String>,
+    /// Private key or mnemonic of the indexer account, which sends the transaction
+    #[arg(
+        long,
+        env = "INDEXER_KEY",
+        hide_env_values = true,
+        required_unless_present = "dry_run"
+    )]
+    indexer_key: Option<String>,
+    /// Print the transaction calldata to execute from the indexer account, such as
+    /// through a multisig, instead of submitting it
+    #[arg(long)]
+    dry_run: bool,
+    /// Registry subgraph to confirm the registration with once mined
+    #[arg(long, env = "REGISTRY_SUBGRAPH")]
+    registry_subgraph: Option<String>,
+    /// Seconds to wait for the registry subgraph to resolve the registration
+    #[arg(long, default_value_t = 300)]
+    confirm_timeout: u64,
+    #[arg(long, value_enum, default_value_t = LogFormat::Pretty)]
+    log_format: LogFormat,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    init_tracing(cli.log_format.to_string())?;
+
+    if cli.dry_run {
+        let call = registration_calldata(&cli.registry_address, &cli.graphcast_id)?;
+        println!("{}", serde_json::to_string_pretty(&call)?);
+        return Ok(());
+    }
+
+    let indexer_key = cli.indexer_key.expect("Indexer key is required");
+    let indexer = wallet_address(&build_wallet(&indexer_key)?);
+    let registry_contract = RegistryContractConfig::new(
+        Endpoint::new(cli.rpc_url.expect("RPC URL is required")),
+        cli.registry_address,
+        0,
+    );
+    let tx_hash = register_graphcast_id(
+        &QueryClient::default(),
+        &registry_contract,
+        &indexer_key,
+        &cli.graphcast_id,
+    )
+    .await?;
+    println!(
+        "Registered Graphcast ID {} for indexer {indexer} in transaction {tx_hash:#x}",
+        cli.graphcast_id
+    );
+
+    if let Some(registry_subgraph) = cli.registry_subgraph {
+        let callbook = CallBook::new(registry_subgraph, String::new(), None);
+        confirm_registration(
+            &callbook,
+            &cli.graphcast_id,
+            &indexer,
+            Duration::from_secs(10),
+            Duration::from_secs(cli.confirm_timeout),
+        )
+        .await?;
+        println!(
+            "Registry subgraph resolves Graphcast ID {} to indexer {indexer}",
+            cli.graphcast_id
+        );
+    }
+    Ok(())
+}
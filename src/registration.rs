@@ -0,0 +1,262 @@
+//! Graphcast ID registration at the Graphcast registry contract.
+//!
+//! An indexer registers the Graphcast ID its radios sign messages with by calling
+//! `setGraphcastID` from the indexer account. The transaction is either signed with the
+//! indexer key and submitted through a JSON-RPC provider, or output as calldata for a
+//! multisig to execute. Once mined, the registry subgraph is polled until it resolves
+//! the new mapping.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use ethers::abi::AbiEncode;
+use ethers::middleware::SignerMiddleware;
+use ethers::providers::Middleware;
+use ethers::signers::Signer;
+use ethers::types::{Bytes, H256};
+use serde_derive::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::build_wallet;
+use crate::callbook::CallBook;
+use crate::graphql::client_registry::query_registry;
+use crate::graphql::client_registry_contract::{
+    parse_address, GraphcastRegistryContract, RegistryContractConfig, SetGraphcastIDCall,
+};
+use crate::graphql::client_rpc::provider;
+use crate::graphql::http_client::QueryClient;
+use crate::graphql::QueryError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RegistrationError {
+    #[error("Invalid indexer key, use private key or mnemonic: {0}")]
+    Wallet(String),
+    #[error(transparent)]
+    Query(#[from] QueryError),
+    #[error("Registration transaction failed: {0}")]
+    Transaction(String),
+    #[error("Registry did not resolve Graphcast ID {graphcast_id} to indexer {indexer} in time")]
+    NotConfirmed {
+        graphcast_id: String,
+        indexer: String,
+    },
+}
+
+/// Unsigned `setGraphcastID` call, to be executed from the indexer account such as
+/// through a multisig
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistrationCall {
+    /// Registry contract address
+    pub to: String,
+    /// ABI encoded `setGraphcastID(graphcast_id)` calldata
+    pub data: String,
+}
+
+/// Build the `setGraphcastID` calldata without signing or submitting it
+pub fn registration_calldata(
+    registry_address: &str,
+    graphcast_id: &str,
+) -> Result<RegistrationCall, RegistrationError> {
+    let registry = parse_address(registry_address)?;
+    let graphcast_id = parse_address(graphcast_id)?;
+    let data = Bytes::from(SetGraphcastIDCall { graphcast_id }.encode());
+    Ok(RegistrationCall {
+        to: format!("{registry:#x}"),
+        data: format!("{data}"),
+    })
+}
+
+/// Sign `setGraphcastID(graphcast_id)` with the indexer key, submit it to the registry
+/// contract and wait for it to be mined. Returns the transaction hash
+pub async fn register_graphcast_id(
+    client: &QueryClient,
+    registry_contract: &RegistryContractConfig,
+    indexer_key: &str,
+    graphcast_id: &str,
+) -> Result<H256, RegistrationError> {
+    let wallet = build_wallet(indexer_key).map_err(|e| RegistrationError::Wallet(e.to_string()))?;
+    let graphcast_id = parse_address(graphcast_id)?;
+    let provider = provider(client, &registry_contract.rpc)?;
+    let chain_id = provider
+        .get_chainid()
+        .await
+        .map_err(|e| RegistrationError::Transaction(e.to_string()))?;
+    let signer = SignerMiddleware::new(provider, wallet.with_chain_id(chain_id.as_u64()));
+    let contract = GraphcastRegistryContract::new(
+        parse_address(&registry_contract.address)?,
+        Arc::new(signer),
+    );
+
+    let call = contract.set_graphcast_id(graphcast_id);
+    let pending = call
+        .send()
+        .await
+        .map_err(|e| RegistrationError::Transaction(e.to_string()))?;
+    let tx_hash = pending.tx_hash();
+    info!(
+        tx_hash = tracing::field::debug(tx_hash),
+        graphcast_id = tracing::field::debug(graphcast_id),
+        "Submitted Graphcast ID registration"
+    );
+    let receipt = pending
+        .await
+        .map_err(|e| RegistrationError::Transaction(e.to_string()))?
+        .ok_or(RegistrationError::Transaction(format!(
+            "Registration transaction {tx_hash:#x} was dropped"
+        )))?;
+    if receipt.status != Some(1.into()) {
+        return Err(RegistrationError::Transaction(format!(
+            "Registration transaction {tx_hash:#x} reverted"
+        )));
+    }
+    Ok(tx_hash)
+}
+
+/// Poll the registry subgraph every `interval` until it resolves `graphcast_id` to
+/// `indexer`, for at most `timeout`
+pub async fn confirm_registration(
+    callbook: &CallBook,
+    graphcast_id: &str,
+    indexer: &str,
+    interval: Duration,
+    timeout: Duration,
+) -> Result<(), RegistrationError> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        match query_registry(
+            callbook.query_client(),
+            callbook.graphcast_registry(),
+            &graphcast_id.to_lowercase(),
+        )
+        .await
+        {
+            Ok(resolved) if resolved.eq_ignore_ascii_case(indexer) => return Ok(()),
+            resolved => debug!(
+                resolved = tracing::field::debug(&resolved),
+                "Registry does not resolve the new Graphcast ID yet"
+            ),
+        }
+        if tokio::time::Instant::now() + interval > deadline {
+            return Err(RegistrationError::NotConfirmed {
+                graphcast_id: graphcast_id.to_string(),
+                indexer: indexer.to_string(),
+            });
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{routing::post, Json, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::graphql::http_client::tests::spawn_stub;
+
+    const REGISTRY: &str = "0x26ebbad1f2e4e9e9e2ab5e8e8d8a5d7b0c3e0e01";
+    const GRAPHCAST_ID: &str = "0x70997970c51812dc3a010c7d01b50e0d17dc79c8";
+
+    #[test]
+    fn test_registration_calldata() {
+        let call = registration_calldata(REGISTRY, GRAPHCAST_ID).unwrap();
+        assert_eq!(call.to, REGISTRY);
+        let selector = &ethers::utils::id("setGraphcastID(address)")[..4];
+        assert_eq!(
+            call.data,
+            format!(
+                "0x{}000000000000000000000000{}",
+                ethers::utils::hex::encode(selector),
+                GRAPHCAST_ID.trim_start_matches("0x")
+            )
+        );
+        assert!(matches!(
+            registration_calldata(REGISTRY, "not an address"),
+            Err(RegistrationError::Query(QueryError::ParseResponseError(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_confirm_registration() {
+        // Registry subgraph indexes the registration on the third query
+        let queries = Arc::new(AtomicUsize::new(0));
+        let app = Router::new().route(
+            "/",
+            post(move |_: Json<Value>| async move {
+                let graphcast_ids = if queries.fetch_add(1, Ordering::SeqCst) < 2 {
+                    json!([])
+                } else {
+                    json!([{"indexer": "0xindexer", "graphcastID": GRAPHCAST_ID}])
+                };
+                Json(json!({"data": {"graphcast_ids": graphcast_ids}}))
+            }),
+        );
+        let url = spawn_stub(app).await;
+        let callbook = CallBook::new(format!("{url}/"), String::new(), None);
+
+        assert!(matches!(
+            confirm_registration(
+                &callbook,
+                GRAPHCAST_ID,
+                "0xIndexer",
+                Duration::from_millis(10),
+                Duration::from_millis(15),
+            )
+            .await,
+            Err(RegistrationError::NotConfirmed { .. })
+        ));
+        confirm_registration(
+            &callbook,
+            GRAPHCAST_ID,
+            "0xIndexer",
+            Duration::from_millis(10),
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+    }
+
+    /// Deploy a registry, then register the second anvil development account as the
+    /// Graphcast ID of the first on a local anvil chain and resolve it from the contract.
+    /// Set `ANVIL_RPC_URL` to run it against another node than `127.0.0.1:8545`
+    #[tokio::test]
+    #[ignore = "requires an anvil node"]
+    async fn test_register_graphcast_id_anvil() {
+        use ethers::providers::{Http, Provider};
+        use ethers::signers::LocalWallet;
+
+        use crate::graphql::client_registry_contract::{
+            tests::deploy_test_registry, RegistryContractReader,
+        };
+        use crate::graphql::endpoint::Endpoint;
+
+        let rpc = std::env::var("ANVIL_RPC_URL").unwrap_or("http://127.0.0.1:8545".to_string());
+        let client = QueryClient::default();
+        let indexer_key = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+        let provider = Provider::<Http>::try_from(rpc.clone()).unwrap();
+        let chain_id = provider.get_chainid().await.unwrap();
+        let deployer: LocalWallet = indexer_key.parse().unwrap();
+        let signer = SignerMiddleware::new(provider, deployer.with_chain_id(chain_id.as_u64()));
+        let address = deploy_test_registry(&signer).await;
+        let deployment_block = signer.get_block_number().await.unwrap().as_u64();
+        let config = RegistryContractConfig::new(
+            Endpoint::new(rpc),
+            format!("{address:#x}"),
+            deployment_block,
+        );
+
+        register_graphcast_id(&client, &config, indexer_key, GRAPHCAST_ID)
+            .await
+            .unwrap();
+        assert_eq!(
+            RegistryContractReader::new(config)
+                .registered_indexer(&client, GRAPHCAST_ID)
+                .await
+                .unwrap(),
+            "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266"
+        );
+    }
+}